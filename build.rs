@@ -6,6 +6,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "protocols/client.proto",
         "protocols/basic.proto",
         "protocols/node.proto",
+        "protocols/fsimage.proto",
     ];
     tonic_build::configure()
         .build_server(true)
@@ -1,27 +1,137 @@
-use crate::proto::BlockInfo;
+use crate::checksum;
+use std::error::Error;
+use std::ops::Range;
 
+/// A replica stored on a datanode, without its data and checksums, which stay in storage until
+/// they are read
 #[derive(Clone, Debug)]
 pub struct Block {
-    pub name: String,
-    data: Vec<u8>,
+    /// Id the namenode allocated for the block
+    pub id: u64,
+    /// Version of the block's contents, bumped by the namenode every time the block is rewritten
+    pub generation_stamp: u64,
+    /// Length of the block in bytes
+    length: usize,
+    /// Bytes of data covered by each checksum
+    bytes_per_checksum: usize,
 }
 
 impl Block {
-    pub fn new(name: String, data: Vec<u8>) -> Self {
-        Self { name, data }
+    pub fn new(id: u64, generation_stamp: u64, length: usize, bytes_per_checksum: usize) -> Self {
+        Self {
+            id,
+            generation_stamp,
+            length,
+            bytes_per_checksum,
+        }
     }
 
-    pub fn read(&self) -> Vec<u8> {
-        self.data.clone()
+    /// Length of the block in bytes
+    pub fn length(&self) -> usize {
+        self.length
     }
 
-    pub fn write(&mut self, block_info: BlockInfo) {
-        let data_to_write = if block_info.block_data.len() > block_info.block_size as usize {
-            &block_info.block_data[..block_info.block_size as usize]
+    pub fn bytes_per_checksum(&self) -> usize {
+        self.bytes_per_checksum
+    }
+
+    /// Returns the bytes and the indexes of the stored chunks that cover `length` bytes of the
+    /// block from `offset`, cut short at the end of the block
+    pub fn chunks_covering(
+        &self,
+        offset: usize,
+        length: usize,
+    ) -> Result<(Range<usize>, Range<usize>), Box<dyn Error>> {
+        let bytes_per_checksum = self.bytes_per_checksum;
+        if bytes_per_checksum == 0 {
+            return Err("Bytes per checksum must be positive".into());
+        }
+        if offset > self.length {
+            return Err(format!("Offset {} is past the end of the block", offset).into());
+        }
+        let end = std::cmp::min(offset.saturating_add(length), self.length);
+
+        let chunks = offset / bytes_per_checksum..end.div_ceil(bytes_per_checksum);
+        let bytes = chunks.start * bytes_per_checksum
+            ..std::cmp::min(chunks.end * bytes_per_checksum, self.length);
+        Ok((bytes, chunks))
+    }
+
+    /// Returns `length` bytes of the block from `offset`, cut short at the end of the block, along
+    /// with the CRC32C of every `bytes_per_checksum` sized chunk of them
+    /// `covered` holds the stored chunks the range covers, as found by `chunks_covering`, and is
+    /// checked against their `stored_checksums` first, so a replica that has gone bad is never
+    /// handed out
+    pub fn cut_range(
+        &self,
+        offset: usize,
+        length: usize,
+        covered: &[u8],
+        stored_checksums: &[u32],
+    ) -> Result<(Vec<u8>, Vec<u32>), Box<dyn Error>> {
+        let (bytes, _) = self.chunks_covering(offset, length)?;
+        if covered.len() != bytes.len() {
+            return Err(format!(
+                "Expected {} bytes of the block but found {}",
+                bytes.len(),
+                covered.len()
+            )
+            .into());
+        }
+        checksum::verify(covered, self.bytes_per_checksum, stored_checksums)?;
+
+        let end = std::cmp::min(offset.saturating_add(length), self.length);
+        let data = covered[offset - bytes.start..end - bytes.start].to_vec();
+        // a range made of whole stored chunks can be sent with their stored checksums
+        let checksums = if offset == bytes.start && end == bytes.end {
+            stored_checksums.to_vec()
         } else {
-            &block_info.block_data
+            checksum::compute(&data, self.bytes_per_checksum)
         };
+        Ok((data, checksums))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a range of a block held in `data` the way storage does
+    fn read_range(
+        block: &Block,
+        data: &[u8],
+        checksums: &[u32],
+        offset: usize,
+        length: usize,
+    ) -> Result<(Vec<u8>, Vec<u32>), Box<dyn Error>> {
+        let (bytes, chunks) = block.chunks_covering(offset, length)?;
+        block.cut_range(offset, length, &data[bytes], &checksums[chunks])
+    }
+
+    #[test]
+    fn test_read_range() {
+        let data: Vec<u8> = (0..10).collect();
+        let checksums = checksum::compute(&data, 4);
+        let block = Block::new(1, 1, data.len(), 4);
+
+        let (range, range_checksums) = read_range(&block, &data, &checksums, 4, 4).unwrap();
+        assert_eq!(range, vec![4, 5, 6, 7]);
+        assert_eq!(range_checksums, checksums[1..2]);
+
+        // ranges off the chunk boundaries get checksums of their own
+        let (range, range_checksums) = read_range(&block, &data, &checksums, 3, 100).unwrap();
+        assert_eq!(range, data[3..]);
+        assert_eq!(range_checksums, checksum::compute(&range, 4));
+        assert!(read_range(&block, &data, &checksums, 10, 1)
+            .unwrap()
+            .0
+            .is_empty());
+        assert!(block.chunks_covering(11, 1).is_err());
 
-        self.data = data_to_write.to_vec();
+        // only the chunks a read covers are checked
+        let mut corrupt = data.clone();
+        corrupt[9] = 0;
+        assert!(read_range(&block, &corrupt, &checksums, 0, 8).is_ok());
+        assert!(read_range(&block, &corrupt, &checksums, 6, 3).is_err());
     }
 }
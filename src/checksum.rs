@@ -0,0 +1,39 @@
+use std::error::Error;
+
+/// Number of bytes of block data covered by each checksum
+pub const BYTES_PER_CHECKSUM: usize = 512;
+
+/// Returns the CRC32C of every `bytes_per_checksum` sized chunk of `data`
+pub fn compute(data: &[u8], bytes_per_checksum: usize) -> Vec<u32> {
+    data.chunks(bytes_per_checksum.max(1))
+        .map(crc32c::crc32c)
+        .collect()
+}
+
+/// Checks `data` against the checksums of its chunks, failing on the first chunk that differs
+pub fn verify(
+    data: &[u8],
+    bytes_per_checksum: usize,
+    checksums: &[u32],
+) -> Result<(), Box<dyn Error>> {
+    if bytes_per_checksum == 0 {
+        return Err("Bytes per checksum must be positive".into());
+    }
+
+    let chunks = data.chunks(bytes_per_checksum);
+    if chunks.len() != checksums.len() {
+        return Err(format!(
+            "Expected {} checksums but received {}",
+            chunks.len(),
+            checksums.len()
+        )
+        .into());
+    }
+
+    for (i, (chunk, expected)) in chunks.zip(checksums).enumerate() {
+        if crc32c::crc32c(chunk) != *expected {
+            return Err(format!("Checksum mismatch in chunk {}", i).into());
+        }
+    }
+    Ok(())
+}
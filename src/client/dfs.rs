@@ -0,0 +1,187 @@
+use super::{Client, FileReader, FileWriter};
+use crate::proto::FileStatus;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use tonic::Code;
+
+/// Error returned by `DfsClient` and the files it opens
+#[derive(Debug)]
+pub enum DfsError {
+    /// Nothing exists at the path, or the directory it would go in doesn't exist
+    NotFound(String),
+    /// Something already exists at the path
+    AlreadyExists(String),
+    /// The user doesn't have permission to make the request
+    PermissionDenied(String),
+    /// The namenode or a datanode couldn't be reached
+    Unavailable(String),
+    /// Any other failure, such as a block none of whose replicas can be read
+    Other(String),
+}
+
+impl From<tonic::Status> for DfsError {
+    fn from(status: tonic::Status) -> Self {
+        let message = status.message().to_string();
+        match status.code() {
+            Code::NotFound => DfsError::NotFound(message),
+            Code::AlreadyExists => DfsError::AlreadyExists(message),
+            Code::PermissionDenied => DfsError::PermissionDenied(message),
+            Code::Unavailable => DfsError::Unavailable(message),
+            _ => DfsError::Other(message),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for DfsError {
+    fn from(err: Box<dyn Error>) -> Self {
+        let err = match err.downcast::<tonic::Status>() {
+            Ok(status) => return (*status).into(),
+            Err(err) => err,
+        };
+        match err.downcast::<DfsError>() {
+            Ok(err) => *err,
+            Err(err) if err.is::<tonic::transport::Error>() => {
+                DfsError::Unavailable(err.to_string())
+            }
+            Err(err) => DfsError::Other(err.to_string()),
+        }
+    }
+}
+
+impl From<DfsError> for io::Error {
+    fn from(err: DfsError) -> Self {
+        let kind = match err {
+            DfsError::NotFound(_) => io::ErrorKind::NotFound,
+            DfsError::AlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            DfsError::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+            DfsError::Unavailable(_) => io::ErrorKind::NotConnected,
+            DfsError::Other(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl fmt::Display for DfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DfsError::NotFound(message)
+            | DfsError::AlreadyExists(message)
+            | DfsError::PermissionDenied(message)
+            | DfsError::Unavailable(message)
+            | DfsError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for DfsError {}
+
+/// Client for using the filesystem from other programs
+/// Clones share the connection to the namenode, so a client can be cloned into each task using it
+#[derive(Clone)]
+pub struct DfsClient {
+    client: Client,
+}
+
+impl DfsClient {
+    /// Connects to the namenode on `namenode_port`, acting as `user` in the groups the namenode
+    /// has for them
+    pub async fn connect(namenode_port: u16, user: &str) -> Result<Self, DfsError> {
+        Ok(Self {
+            client: Client::new(namenode_port, user).await?,
+        })
+    }
+
+    /// Opens a file for reading
+    pub async fn open(&self, path: &str) -> Result<FileReader, DfsError> {
+        Ok(self.client.clone().open(path).await?)
+    }
+
+    /// Creates a new, empty file and opens it for writing
+    /// Each block is written to the datanodes once it fills, so the writer holds at most two
+    /// blocks in memory. The file can't be read until the writer is shut down, which writes the
+    /// last block and completes the file. If the writer is dropped first, the file stays under
+    /// construction until the namenode's lease hard limit passes, and is then deleted
+    pub async fn create(&self, path: &str) -> Result<FileWriter, DfsError> {
+        let mut client = self.client.clone();
+        let block_size = client.begin_file(path).await?;
+        Ok(FileWriter::new(client, path, block_size, vec![], vec![]))
+    }
+
+    /// Opens an existing file for writing after its current contents, taking the lease on it so
+    /// no other client can write it until the writer is shut down
+    /// The file's full blocks are kept as they are, while a partly filled last block is read into
+    /// the writer and written again along with what is appended to it. If the writer is dropped
+    /// before it is shut down, the file is left as it was once the namenode's lease hard limit
+    /// passes
+    pub async fn append(&self, path: &str) -> Result<FileWriter, DfsError> {
+        let mut client = self.client.clone();
+        let response = client.begin_append(path).await?;
+        let block_size = response.block_size as usize;
+        let length = response.file_length as u64;
+        let full_blocks = length as usize / block_size;
+        let mut reader = FileReader::new(client.clone(), length, response.blocks.clone());
+        let offset = (full_blocks * block_size) as u64;
+        let last_block = reader.read_at(offset, (length - offset) as usize).await?;
+
+        let mut blocks = response.blocks;
+        blocks.truncate(full_blocks);
+        Ok(FileWriter::new(
+            client, path, block_size, blocks, last_block,
+        ))
+    }
+
+    /// Returns the contents of a directory, in name order
+    pub async fn list(&self, path: &str) -> Result<Vec<FileStatus>, DfsError> {
+        Ok(self.client.clone().list(path).await?)
+    }
+
+    /// Returns the metadata of a file or directory
+    pub async fn stat(&self, path: &str) -> Result<FileStatus, DfsError> {
+        Ok(self.client.clone().stat(path).await?)
+    }
+
+    /// Creates a directory, along with any missing parents if `parents` is set
+    pub async fn mkdir(&self, path: &str, parents: bool) -> Result<(), DfsError> {
+        Ok(self.client.clone().mkdir(path, parents).await?)
+    }
+
+    /// Deletes a file or an empty directory, or a directory and everything under it if
+    /// `recursive` is set
+    pub async fn delete(&self, path: &str, recursive: bool) -> Result<(), DfsError> {
+        Ok(self.client.clone().delete(path, recursive).await?)
+    }
+
+    /// Moves a file or directory, replacing whatever is at `destination` if `overwrite` is set
+    pub async fn rename(
+        &self,
+        source: &str,
+        destination: &str,
+        overwrite: bool,
+    ) -> Result<(), DfsError> {
+        Ok(self
+            .client
+            .clone()
+            .rename(source, destination, overwrite)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_from() {
+        let err: Box<dyn Error> = Box::new(tonic::Status::not_found("File does not exist"));
+        assert!(matches!(DfsError::from(err), DfsError::NotFound(_)));
+        let err: Box<dyn Error> = Box::new(tonic::Status::internal("Failed"));
+        assert!(matches!(DfsError::from(err), DfsError::Other(_)));
+        let err: Box<dyn Error> = "Every replica of block 1 is unreachable or corrupt".into();
+        assert!(matches!(DfsError::from(err), DfsError::Other(_)));
+
+        let err = io::Error::from(DfsError::PermissionDenied("Permission denied".to_string()));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "Permission denied");
+    }
+}
@@ -0,0 +1,1342 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, SeekFrom};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::checksum::{self, BYTES_PER_CHECKSUM};
+use crate::packet::{self, Assembler};
+use crate::proto::{
+    client_protocols_client::ClientProtocolsClient,
+    data_node_protocols_client::DataNodeProtocolsClient, set_acl_request::Action, AclEntry,
+    AclEntryType, AclScope, AddBlockRequest, BlockPacket, CompleteFileRequest, DeleteBlockRequest,
+    DeleteDirectoryRequest, FailedReplica, FailedReplicaReport, FileInfo, FileRequest,
+    FileResponse, FileStatus, GetAclRequest, GetFileInfoRequest, ListDirectoryRequest,
+    LocatedBlock, MkdirRequest, NamespaceEvent, NodeState, NodeStatus, ReadBlockRequest,
+    RenameRequest, RenewLeaseRequest, SetAclRequest, SetOwnerRequest, SetPermissionRequest,
+    SystemInfoRequest, WatchEventsRequest,
+};
+
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub mod dfs;
+pub mod reader;
+pub mod writer;
+pub use dfs::{DfsClient, DfsError};
+pub use reader::FileReader;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::{transport::Channel, Code, Request};
+pub use writer::FileWriter;
+
+/// Number of times a block is sent to a replica before the write is given up on
+const WRITE_ATTEMPTS: usize = 2;
+
+/// Most bytes the shell reads from a file at a time
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Number of lines `head` and `tail` print unless told otherwise
+const DEFAULT_LINES: u64 = 10;
+
+/// Time between renewals of the lease on a file being written, well within the time the namenode
+/// lets a lease go unrenewed before another client can take the file over
+const LEASE_RENEWAL_INTERVAL: Duration = Duration::from_secs(20);
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state() {
+            NodeState::Live => "Online",
+            NodeState::Stale => "Stale",
+            NodeState::Dead => "Offline",
+        };
+        write!(f, "{}\t\t{}", self.node_address, state)
+    }
+}
+
+impl fmt::Display for NamespaceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = if self.is_directory { "/" } else { "" };
+        write!(
+            f,
+            "{}\t{}\t{:<8}\t{}{}",
+            self.txid,
+            format_time(self.time),
+            self.r#type().as_str_name(),
+            self.path,
+            suffix
+        )?;
+        if !self.destination.is_empty() {
+            write!(f, " -> {}{}", self.destination, suffix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats read, write and execute bits like `ls -l` does for one class, e.g. `r-x`
+fn permissions_string(bits: u32) -> String {
+    [(4, 'r'), (2, 'w'), (1, 'x')]
+        .iter()
+        .map(|(bit, c)| if bits & bit != 0 { *c } else { '-' })
+        .collect()
+}
+
+impl FileStatus {
+    /// Formats the permission bits like `ls -l`, e.g. `drwxr-xr-x`, with a trailing `+` if there
+    /// is an ACL
+    fn mode_string(&self) -> String {
+        let attributes = self.attributes.clone().unwrap_or_default();
+        let mut s = String::from(if self.is_directory { "d" } else { "-" });
+        for shift in [6, 3, 0] {
+            s.push_str(&permissions_string(attributes.mode >> shift));
+        }
+        if !attributes.acl.is_empty() {
+            s.push('+');
+        }
+        s
+    }
+}
+
+/// Formats an ACL entry like getfacl, e.g. `default:user:alice:rw-`
+impl fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scope() == AclScope::Default {
+            write!(f, "default:")?;
+        }
+        let kind = match self.r#type() {
+            AclEntryType::User => "user",
+            AclEntryType::Group => "group",
+            AclEntryType::Mask => "mask",
+            AclEntryType::Other => "other",
+        };
+        write!(
+            f,
+            "{}:{}:{}",
+            kind,
+            self.name,
+            permissions_string(self.permissions)
+        )
+    }
+}
+
+/// Parses a comma separated list of ACL entries like setfacl, e.g. `user:bob:rw-,default:g:staff:5`
+/// Entries to be removed are given without permissions
+fn parse_acl_spec(spec: &str, with_permissions: bool) -> Result<Vec<AclEntry>, Box<dyn Error>> {
+    let mut entries = vec![];
+    for entry in spec.split(',') {
+        let invalid = || format!("Invalid ACL entry: {}", entry);
+        let mut parts: Vec<&str> = entry.split(':').collect();
+        let scope = match parts[0] {
+            "default" | "d" => {
+                parts.remove(0);
+                AclScope::Default
+            }
+            _ => AclScope::Access,
+        };
+        let kind = match parts[0] {
+            "user" | "u" => AclEntryType::User,
+            "group" | "g" => AclEntryType::Group,
+            "mask" | "m" => AclEntryType::Mask,
+            "other" | "o" => AclEntryType::Other,
+            _ => return Err(invalid().into()),
+        };
+        let name = parts.get(1).copied().unwrap_or("");
+        let permissions = match (with_permissions, parts.len()) {
+            (true, 3) => {
+                let perms = parts[2];
+                match u32::from_str_radix(perms, 8) {
+                    Ok(bits) if bits <= 7 => bits,
+                    _ if perms.len() == 3 => perms
+                        .chars()
+                        .zip([('r', 4), ('w', 2), ('x', 1)])
+                        .try_fold(0, |bits, (c, (expected, bit))| match c {
+                            '-' => Ok(bits),
+                            c if c == expected => Ok(bits | bit),
+                            _ => Err(invalid()),
+                        })?,
+                    _ => return Err(invalid().into()),
+                }
+            }
+            (false, 1 | 2) => 0,
+            _ => return Err(invalid().into()),
+        };
+        entries.push(AclEntry {
+            scope: scope.into(),
+            r#type: kind.into(),
+            name: name.to_string(),
+            permissions,
+        });
+    }
+    Ok(entries)
+}
+
+/// Formats milliseconds since the Unix epoch as a UTC date and time
+fn format_time(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86400) as i64;
+    let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Removes `flag` and the number after it from a command's arguments, returning the number
+fn take_option(args: &mut Vec<&str>, flag: &str) -> Result<Option<u64>, Box<dyn Error>> {
+    let Some(i) = args.iter().position(|arg| *arg == flag) else {
+        return Ok(None);
+    };
+    let value = args
+        .get(i + 1)
+        .ok_or_else(|| UsageError(format!("{} needs a value", flag)))?;
+    let value = value
+        .parse()
+        .map_err(|_| UsageError(format!("Invalid value for {}: {}", flag, value)))?;
+    args.drain(i..i + 2);
+    Ok(Some(value))
+}
+
+/// Returns the path of `name` in the directory at `dir`
+fn child_path(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+/// How each shell command is used, shown when it is given the wrong arguments
+const COMMAND_USAGE: &[(&str, &str)] = &[
+    ("system_checkup", "system_checkup"),
+    ("create", "create local_path [path]"),
+    ("update", "update local_path [path]"),
+    ("get", "get path [local_path]"),
+    ("delete", "delete [-r] path"),
+    ("read", "read path"),
+    ("cat", "cat [--offset bytes] [--length bytes] path"),
+    ("head", "head [-n lines | -c bytes] path"),
+    ("tail", "tail [-n lines | -c bytes] path"),
+    ("mkdir", "mkdir [-p] path"),
+    ("ls", "ls [path]"),
+    ("du", "du [-s] [path]"),
+    ("mv", "mv [-f] source destination"),
+    ("stat", "stat path"),
+    ("chmod", "chmod mode path"),
+    ("chown", "chown owner[:group] path"),
+    ("getfacl", "getfacl path"),
+    (
+        "setfacl",
+        "setfacl {-m|-x|--set} <entries> <path> | setfacl {-b|-k} <path>",
+    ),
+    ("watch", "watch [path] [since_txid]"),
+    ("rmdir", "rmdir path"),
+];
+
+/// Error for a shell command that doesn't exist or was given the wrong arguments
+#[derive(Debug)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for UsageError {}
+
+/// Returns the error for a command given the wrong arguments, or one that doesn't exist
+fn usage(command: &str) -> Box<dyn Error> {
+    match COMMAND_USAGE.iter().find(|(name, _)| *name == command) {
+        Some((_, text)) => UsageError(format!("Usage: {}", text)).into(),
+        None => UsageError(format!("Invalid command: {}", command)).into(),
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    namenode_client: ClientProtocolsClient<Channel>,
+    /// User the client acts as, sent with every request to the namenode
+    user: MetadataValue<Ascii>,
+    /// Name unique to this client, which holds the leases on the files it writes
+    name: MetadataValue<Ascii>,
+}
+
+impl Client {
+    pub async fn new(name_port: u16, user: &str) -> Result<Self, Box<dyn Error>> {
+        let namenode_addr = SocketAddr::from(([127, 0, 0, 1], name_port));
+        let namenode_addr = format!("http://{}", namenode_addr);
+        let channel = Channel::from_shared(namenode_addr)
+            .unwrap()
+            .connect()
+            .await?;
+
+        let client = ClientProtocolsClient::new(channel);
+
+        Ok(Client {
+            namenode_client: client,
+            user: user.parse()?,
+            name: format!("client_{}_{}", std::process::id(), rand::random::<u32>()).parse()?,
+        })
+    }
+
+    /// Wraps a message to the namenode in a request carrying the identity of the user and client
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        let metadata = request.metadata_mut();
+        metadata.insert("user", self.user.clone());
+        metadata.insert("client", self.name.clone());
+        request
+    }
+
+    async fn create_client(
+        &self,
+        client_addr: &str,
+    ) -> Result<DataNodeProtocolsClient<Channel>, Box<dyn Error>> {
+        let client_addr = format!("http://{}", client_addr);
+        let channel = Channel::from_shared(client_addr).unwrap().connect().await?;
+
+        Ok(DataNodeProtocolsClient::new(channel))
+    }
+
+    /// Runs the interactive shell, reading commands from stdin until `exit` or the end of input
+    pub async fn run_client(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut stdout = io::stdout();
+        let stdin = io::stdin();
+        let mut reader = io::BufReader::new(stdin);
+
+        // shell implementation
+        loop {
+            stdout.write_all(b"> ").await?;
+            stdout.flush().await?;
+
+            let mut input = String::new();
+            if reader.read_line(&mut input).await? == 0 {
+                break;
+            }
+
+            let args: Vec<&str> = input.split_whitespace().collect();
+            match args.first() {
+                Some(command) if command.eq_ignore_ascii_case("exit") => break,
+                Some(_) => match self.run_command(&args).await {
+                    Ok(_) => {}
+                    Err(e) if e.is::<UsageError>() => println!("{}", e),
+                    Err(e) => println!("Error: {}", e),
+                },
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the commands of a script in order, one per line or separated by `;`, stopping at
+    /// `exit` or the first command that fails
+    /// Blank lines and lines starting with `#` are skipped
+    pub async fn run_script(&mut self, script: &str) -> Result<(), Box<dyn Error>> {
+        for line in script.lines() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            for command in line.split(';') {
+                let args: Vec<&str> = command.split_whitespace().collect();
+                match args.first() {
+                    Some(command) if command.eq_ignore_ascii_case("exit") => return Ok(()),
+                    Some(_) => self.run_command(&args).await?,
+                    None => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a single shell command, given as the command's name followed by its arguments
+    pub async fn run_command(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        const ANSI_BOLD: &str = "\x1b[1m";
+        const ANSI_RESET: &str = "\x1b[0m";
+
+        let Some((command, args)) = args.split_first() else {
+            return Ok(());
+        };
+        let command = command.to_lowercase();
+        match (command.as_str(), args) {
+            ("system_checkup", []) => {
+                let request = self.request(SystemInfoRequest {});
+                let response = self.namenode_client.get_system_status(request).await?;
+                let response = response.into_inner();
+                let namenode_status = response.namenode.unwrap_or_default();
+                let datanode_statuses = response.nodes;
+
+                let count_state = |state: NodeState| {
+                    datanode_statuses
+                        .iter()
+                        .filter(|node| node.state() == state)
+                        .count()
+                };
+                let num_online = count_state(NodeState::Live);
+                let num_stale = count_state(NodeState::Stale);
+                let num_offline = count_state(NodeState::Dead);
+
+                println!("{}Node Type\tIP Address\t\tStatus{}", ANSI_BOLD, ANSI_RESET);
+                println!("Namenode\t{}", namenode_status);
+                for node in datanode_statuses {
+                    println!("Datanode\t{}", node);
+                }
+                println!(
+                    "\n{}Summary{}: {} online, {} stale, {} offline\n",
+                    ANSI_BOLD, ANSI_RESET, num_online, num_stale, num_offline
+                );
+                Ok(())
+            }
+
+            // the file keeps its local path in the filesystem unless given another
+            ("create", [local_path]) => self.handle_create(local_path, local_path).await,
+            ("create", [local_path, file_path]) => self.handle_create(local_path, file_path).await,
+            ("update", [local_path]) => self.handle_update(local_path, local_path).await,
+            ("update", [local_path, file_path]) => self.handle_update(local_path, file_path).await,
+
+            ("get", [file_path]) => {
+                // the local copy is named after the file unless given another path
+                match file_path.rsplit('/').next() {
+                    Some("" | "." | "..") | None => Err(usage("get")),
+                    Some(local_path) => self.handle_get(file_path, local_path).await,
+                }
+            }
+            ("get", [file_path, local_path]) => self.handle_get(file_path, local_path).await,
+
+            ("delete", ["-r", path]) | ("delete", [path, "-r"]) => self.delete(path, true).await,
+            ("delete", [path]) => self.delete(path, false).await,
+
+            ("read", [file_path]) => self.handle_read(file_path).await,
+
+            ("cat", _) => {
+                let mut args = args.to_vec();
+                let offset = take_option(&mut args, "--offset")?;
+                let length = take_option(&mut args, "--length")?;
+                match args[..] {
+                    [path] => self.handle_cat(path, offset.unwrap_or(0), length).await,
+                    _ => Err(usage(&command)),
+                }
+            }
+
+            ("head" | "tail", _) => {
+                let mut args = args.to_vec();
+                let lines = take_option(&mut args, "-n")?;
+                let bytes = take_option(&mut args, "-c")?;
+                let (count, bytes) = match (lines, bytes, &args[..]) {
+                    (None, Some(count), [_]) => (count, true),
+                    (lines, None, [_]) => (lines.unwrap_or(DEFAULT_LINES), false),
+                    _ => return Err(usage(&command)),
+                };
+                if command == "head" {
+                    self.handle_head(args[0], count, bytes).await
+                } else {
+                    self.handle_tail(args[0], count, bytes).await
+                }
+            }
+
+            ("mkdir", ["-p", path]) | ("mkdir", [path, "-p"]) => self.mkdir(path, true).await,
+            ("mkdir", [path]) => self.mkdir(path, false).await,
+
+            ("ls", []) => self.handle_ls("/").await,
+            ("ls", [path]) => self.handle_ls(path).await,
+
+            ("du", []) => self.handle_du("/", false).await,
+            ("du", ["-s"]) => self.handle_du("/", true).await,
+            ("du", ["-s", path]) | ("du", [path, "-s"]) => self.handle_du(path, true).await,
+            ("du", [path]) => self.handle_du(path, false).await,
+
+            ("mv", ["-f", source, destination]) | ("mv", [source, destination, "-f"]) => {
+                self.rename(source, destination, true).await
+            }
+            ("mv", [source, destination]) => self.rename(source, destination, false).await,
+
+            ("stat", [path]) => self.handle_stat(path).await,
+
+            ("chmod", [mode, path]) => self.handle_chmod(mode, path).await,
+
+            ("chown", [owner, path]) => {
+                // `owner:group` changes both, and `:group` just the group
+                let (owner, group) = owner.split_once(':').unwrap_or((owner, ""));
+                self.handle_chown(path, owner, group).await
+            }
+
+            ("getfacl", [path]) => self.handle_getfacl(path).await,
+            ("setfacl", _) => self.handle_setfacl(args).await,
+
+            ("watch", _) if args.len() <= 2 => {
+                let path = args.first().copied().unwrap_or("/");
+                let since_txid = match args.get(1).map(|txid| txid.parse()) {
+                    Some(Ok(txid)) => txid,
+                    Some(Err(_)) => return Err(usage(&command)),
+                    None => 0,
+                };
+                self.handle_watch(path, since_txid).await
+            }
+
+            ("rmdir", [path]) => self.delete_directory(path, false).await,
+
+            _ => Err(usage(&command)),
+        }
+    }
+
+    /// Opens a file for reading, fetching the layout of its blocks from the namenode
+    pub async fn open(&mut self, file_path: &str) -> Result<FileReader, Box<dyn Error>> {
+        let file = FileInfo {
+            file_path: file_path.to_string(),
+            file_size: 0,
+        };
+        let request = self.request(FileRequest {
+            file_info: Some(file),
+        });
+        let response = match self.namenode_client.read_file(request).await {
+            Ok(response) => response.into_inner(),
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(FileReader::new(
+            self.clone(),
+            response.file_length as u64,
+            response.blocks,
+        ))
+    }
+
+    /// Reads up to `len` bytes of a file from `offset`, fewer if the file ends first, fetching
+    /// only the ranges of the blocks that cover them
+    pub async fn pread(
+        &mut self,
+        file_path: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        Ok(reader.read_at(offset, len).await?)
+    }
+
+    /// Prints a whole file to the terminal
+    async fn handle_read(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        println!("Reading file: {}", file_path);
+        Self::print_from(&mut reader, u64::MAX).await
+    }
+
+    /// Downloads the file at `file_path` to `local_path`
+    async fn handle_get(
+        &mut self,
+        file_path: &str,
+        local_path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        let mut file = tokio::fs::File::create(local_path).await?;
+        io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Prints `length` bytes of a file from `offset`, or the rest of the file if no length is given
+    async fn handle_cat(
+        &mut self,
+        file_path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        reader.seek(SeekFrom::Start(offset)).await?;
+        Self::print_from(&mut reader, length.unwrap_or(u64::MAX)).await
+    }
+
+    /// Prints the first `count` lines of a file, or the first `count` bytes if `bytes` is set
+    async fn handle_head(
+        &mut self,
+        file_path: &str,
+        count: u64,
+        bytes: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        if bytes {
+            return Self::print_from(&mut reader, count).await;
+        }
+
+        let mut end = 0;
+        let mut lines = 0;
+        while lines < count && end < reader.length() {
+            let data = reader.read_at(end, READ_BUFFER_SIZE).await?;
+            for byte in data {
+                end += 1;
+                if byte == b'\n' {
+                    lines += 1;
+                    if lines == count {
+                        break;
+                    }
+                }
+            }
+        }
+        Self::print_from(&mut reader, end).await
+    }
+
+    /// Prints the last `count` lines of a file, or the last `count` bytes if `bytes` is set,
+    /// reading back from the end of the file only as far as it needs to
+    async fn handle_tail(
+        &mut self,
+        file_path: &str,
+        count: u64,
+        bytes: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut reader = self.open(file_path).await?;
+        let length = reader.length();
+        if bytes {
+            reader
+                .seek(SeekFrom::Start(length.saturating_sub(count)))
+                .await?;
+            return Self::print_from(&mut reader, count).await;
+        }
+
+        let mut start = length;
+        let mut lines = 0;
+        'search: while start > 0 {
+            let chunk_start = start.saturating_sub(READ_BUFFER_SIZE as u64);
+            let data = reader
+                .read_at(chunk_start, (start - chunk_start) as usize)
+                .await?;
+            for byte in data.iter().rev() {
+                // a newline ending the file doesn't start another line
+                if *byte == b'\n' && start < length {
+                    lines += 1;
+                    if lines == count {
+                        break 'search;
+                    }
+                }
+                start -= 1;
+            }
+        }
+        reader.seek(SeekFrom::Start(start)).await?;
+        Self::print_from(&mut reader, length - start).await
+    }
+
+    /// Prints up to `length` bytes from a reader's position
+    async fn print_from(reader: &mut FileReader, length: u64) -> Result<(), Box<dyn Error>> {
+        let mut stdout = io::stdout();
+        io::copy(&mut reader.take(length), &mut stdout).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+
+    /// Reads `length` bytes of a block from `offset` off the first replica that reads cleanly,
+    /// trying the healthiest replicas first and adding those that fail to `failed_replicas`
+    async fn read_block_range(
+        &self,
+        block: &LocatedBlock,
+        offset: u64,
+        length: u64,
+        failed_replicas: &mut Vec<FailedReplica>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        for datanode_addr in &block.nodes {
+            match self
+                .read_replica(datanode_addr, block, offset, length)
+                .await
+            {
+                Ok(data) => return Ok(data),
+                Err((e, corrupt)) => {
+                    eprintln!(
+                        "Failed to read block {} from datanode {}: {}",
+                        block.block_id, datanode_addr, e
+                    );
+                    if let Some(corrupt) = corrupt {
+                        failed_replicas.push(FailedReplica {
+                            block_id: block.block_id,
+                            address: datanode_addr.clone(),
+                            corrupt,
+                        });
+                    }
+                }
+            }
+        }
+        Err(format!(
+            "Every replica of block {} is unreachable or corrupt",
+            block.block_id
+        )
+        .into())
+    }
+
+    /// Reads `length` bytes of a block from `offset` off a single replica in packets, checking
+    /// each against its checksums
+    /// On failure, also returns whether the datanode was reached but the replica was bad, or None
+    /// if the failure isn't worth reporting, such as a replica the datanode no longer has
+    async fn read_replica(
+        &self,
+        datanode_addr: &str,
+        block: &LocatedBlock,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, (Box<dyn Error>, Option<bool>)> {
+        let mut datanode_client = self
+            .create_client(datanode_addr)
+            .await
+            .map_err(|e| (e, Some(false)))?;
+
+        let request = Request::new(ReadBlockRequest {
+            block_id: block.block_id,
+            offset: offset as i64,
+            length: length as i64,
+        });
+        // a missing replica or range is caught up on by the datanode's block reports
+        let status_error = |e: tonic::Status| {
+            let corrupt = match e.code() {
+                Code::NotFound | Code::OutOfRange => None,
+                code => Some(code == Code::DataLoss),
+            };
+            (Box::new(e) as Box<dyn Error>, corrupt)
+        };
+        let mut packets = datanode_client
+            .read_block(request)
+            .await
+            .map_err(status_error)?
+            .into_inner();
+        let mut assembler = Assembler::new();
+        while let Some(packet) = packets.message().await.map_err(status_error)? {
+            assembler.add(packet).map_err(|e| (e, Some(true)))?;
+        }
+        let replica = assembler.finish().map_err(|e| (e, Some(true)))?;
+
+        if replica.block_data.len() as u64 != length {
+            let e = format!(
+                "Replica returned {} bytes but {} were asked for",
+                replica.block_data.len(),
+                length
+            );
+            return Err((e.into(), Some(true)));
+        }
+        // a replica that missed a rewrite still matches its own checksums, but holds old data
+        if replica.generation_stamp < block.generation_stamp {
+            let e = format!(
+                "Replica has generation stamp {} but the block is at {}",
+                replica.generation_stamp, block.generation_stamp
+            );
+            return Err((e.into(), Some(true)));
+        }
+        Ok(replica.block_data)
+    }
+
+    /// Tells the namenode about replicas that couldn't be read
+    /// This is best effort, so a failure is only logged
+    async fn report_failed_replicas(&mut self, replicas: Vec<FailedReplica>) {
+        if replicas.is_empty() {
+            return;
+        }
+        let request = self.request(FailedReplicaReport { replicas });
+        if let Err(e) = self.namenode_client.report_failed_replicas(request).await {
+            eprintln!("Failed to report bad replicas to the namenode: {}", e);
+        }
+    }
+
+    async fn delete_file(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let file = FileInfo {
+            file_path: file_path.to_string(),
+            file_size: 4096,
+        };
+        let request = self.request(FileRequest {
+            file_info: Some(file.clone()),
+        });
+        let response = match self.namenode_client.delete_file(request).await {
+            Ok(response) => response,
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let response = response.into_inner();
+        for block in &response.removed_blocks {
+            self.delete_block(block).await;
+        }
+        Ok(())
+    }
+
+    /// Deletes a file or an empty directory, or a directory and everything under it if
+    /// `recursive` is set
+    async fn delete(&mut self, path: &str, recursive: bool) -> Result<(), Box<dyn Error>> {
+        if self.stat(path).await?.is_directory {
+            self.delete_directory(path, recursive).await
+        } else {
+            self.delete_file(path).await
+        }
+    }
+
+    /// Deletes a directory, along with every file under it if `recursive` is set
+    async fn delete_directory(
+        &mut self,
+        path: &str,
+        recursive: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = self.request(DeleteDirectoryRequest {
+            path: path.to_string(),
+            recursive,
+        });
+        let response = match self.namenode_client.delete_directory(request).await {
+            Ok(response) => response,
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let response = response.into_inner();
+        for block in &response.removed_blocks {
+            self.delete_block(block).await;
+        }
+        Ok(())
+    }
+
+    async fn mkdir(&mut self, path: &str, parents: bool) -> Result<(), Box<dyn Error>> {
+        let request = self.request(MkdirRequest {
+            path: path.to_string(),
+            parents,
+        });
+        match self.namenode_client.mkdir(request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Returns the contents of a directory, in name order
+    async fn list(&mut self, path: &str) -> Result<Vec<FileStatus>, Box<dyn Error>> {
+        let request = self.request(ListDirectoryRequest {
+            path: path.to_string(),
+        });
+        match self.namenode_client.list_directory(request).await {
+            Ok(response) => Ok(response.into_inner().entries),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Prints the contents of a directory, directories marked with a trailing `/`
+    async fn handle_ls(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        for entry in self.list(path).await? {
+            let attributes = entry.attributes.clone().unwrap_or_default();
+            let name = if entry.is_directory {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            };
+            println!(
+                "{}  {:<8} {:<10} {:>10}  {}  {}",
+                entry.mode_string(),
+                attributes.owner,
+                attributes.group,
+                entry.length,
+                format_time(attributes.mtime),
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata of a file or directory
+    async fn stat(&mut self, path: &str) -> Result<FileStatus, Box<dyn Error>> {
+        let request = self.request(GetFileInfoRequest {
+            path: path.to_string(),
+        });
+        match self.namenode_client.get_file_info(request).await {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Prints the total size of each file and directory in a directory, or of the path itself if
+    /// `summary` is set or it is a file
+    async fn handle_du(&mut self, path: &str, summary: bool) -> Result<(), Box<dyn Error>> {
+        let status = self.stat(path).await?;
+        if summary || !status.is_directory {
+            let size = self.disk_usage(path, &status).await?;
+            println!("{:>12}  {}", size, path);
+            return Ok(());
+        }
+        for entry in self.list(path).await? {
+            let entry_path = child_path(path, &entry.name);
+            let size = self.disk_usage(&entry_path, &entry).await?;
+            println!("{:>12}  {}", size, entry_path);
+        }
+        Ok(())
+    }
+
+    /// Returns the length of a file, or the total length of every file under a directory
+    async fn disk_usage(&mut self, path: &str, status: &FileStatus) -> Result<u64, Box<dyn Error>> {
+        if !status.is_directory {
+            return Ok(status.length as u64);
+        }
+        let mut total = 0;
+        let mut dirs = vec![path.to_string()];
+        while let Some(dir) = dirs.pop() {
+            for entry in self.list(&dir).await? {
+                if entry.is_directory {
+                    dirs.push(child_path(&dir, &entry.name));
+                } else {
+                    total += entry.length as u64;
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Prints the metadata of a file or directory
+    async fn handle_stat(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let status = self.stat(path).await?;
+
+        let attributes = status.attributes.clone().unwrap_or_default();
+        println!("  Path: {}", path);
+        if status.is_directory {
+            println!("  Type: directory");
+        } else {
+            println!("  Type: file");
+            println!("  Size: {}", status.length);
+            println!("  Block size: {}", status.block_size);
+            println!("  Replication: {}", status.replication);
+        }
+        println!("  Mode: {} ({:04o})", status.mode_string(), attributes.mode);
+        println!("  Owner: {}", attributes.owner);
+        println!("  Group: {}", attributes.group);
+        println!("  Created: {}", format_time(attributes.ctime));
+        println!("  Modified: {}", format_time(attributes.mtime));
+        println!("  Accessed: {}", format_time(attributes.atime));
+        Ok(())
+    }
+
+    /// Sets the permission bits of a file or directory from an octal mode such as `755`
+    async fn handle_chmod(&mut self, mode: &str, path: &str) -> Result<(), Box<dyn Error>> {
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| format!("Invalid mode: {}", mode))?;
+        let request = self.request(SetPermissionRequest {
+            path: path.to_string(),
+            mode,
+        });
+        match self.namenode_client.set_permission(request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Sets the owner and group of a file or directory, leaving either alone if it is empty
+    async fn handle_chown(
+        &mut self,
+        path: &str,
+        owner: &str,
+        group: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = self.request(SetOwnerRequest {
+            path: path.to_string(),
+            owner: owner.to_string(),
+            group: group.to_string(),
+        });
+        match self.namenode_client.set_owner(request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Prints the owner, group and ACL of a file or directory like getfacl
+    async fn handle_getfacl(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let request = self.request(GetAclRequest {
+            path: path.to_string(),
+        });
+        let acl = match self.namenode_client.get_acl(request).await {
+            Ok(response) => response.into_inner(),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        println!("# file: {}", path);
+        println!("# owner: {}", acl.owner);
+        println!("# group: {}", acl.group);
+        for entry in acl.entries {
+            println!("{}", entry);
+        }
+        Ok(())
+    }
+
+    /// Changes the ACL of a file or directory, taking setfacl's `-m`, `-x`, `-b`, `-k` and `--set`
+    /// options followed by the path
+    async fn handle_setfacl(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let (action, entries, path) = match args {
+            ["-m", spec, path] => (Action::Modify, parse_acl_spec(spec, true)?, path),
+            ["-x", spec, path] => (Action::Remove, parse_acl_spec(spec, false)?, path),
+            ["--set", spec, path] => (Action::Replace, parse_acl_spec(spec, true)?, path),
+            ["-b", path] => (Action::RemoveAll, vec![], path),
+            ["-k", path] => (Action::RemoveDefault, vec![], path),
+            _ => return Err(usage("setfacl")),
+        };
+        let request = self.request(SetAclRequest {
+            path: path.to_string(),
+            action: action.into(),
+            entries,
+        });
+        match self.namenode_client.set_acl(request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Prints every change at or under a path as it happens, starting after transaction
+    /// `since_txid`, or with the next change if it is 0, until interrupted with Ctrl-C
+    async fn handle_watch(&mut self, path: &str, since_txid: u64) -> Result<(), Box<dyn Error>> {
+        let request = self.request(WatchEventsRequest {
+            path: path.to_string(),
+            since_txid,
+        });
+        let mut events = match self.namenode_client.watch_events(request).await {
+            Ok(response) => response.into_inner(),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        println!("Watching {}, press Ctrl-C to stop", path);
+        loop {
+            let event = tokio::select! {
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+                event = events.message() => event?,
+            };
+            match event {
+                Some(event) => println!("{}", event),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Moves a file or directory, replacing whatever is at `destination` if `overwrite` is set
+    async fn rename(
+        &mut self,
+        source: &str,
+        destination: &str,
+        overwrite: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = self.request(RenameRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite,
+        });
+        let response = match self.namenode_client.rename(request).await {
+            Ok(response) => response,
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        // the blocks of a file that was replaced
+        let response = response.into_inner();
+        for block in &response.removed_blocks {
+            self.delete_block(block).await;
+        }
+        Ok(())
+    }
+
+    /// Deletes every replica of a block it can reach
+    /// A replica that can't be deleted is left for the namenode to have its datanode delete
+    async fn delete_block(&self, block: &LocatedBlock) {
+        for datanode_addr in &block.nodes {
+            let datanode_client = self
+                .create_client(datanode_addr)
+                .await
+                .map_err(|e| e.to_string());
+            let result = match datanode_client {
+                Ok(mut datanode_client) => {
+                    let request = Request::new(DeleteBlockRequest {
+                        block_id: block.block_id,
+                    });
+                    datanode_client
+                        .delete_file(request)
+                        .await
+                        .map(|response| response.into_inner().success)
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(true) => {}
+                Ok(false) => eprintln!(
+                    "Failed to delete block {} from datanode: {}",
+                    block.block_id, datanode_addr,
+                ),
+                Err(e) => eprintln!(
+                    "Failed to delete block {} from datanode {}: {}",
+                    block.block_id, datanode_addr, e
+                ),
+            }
+        }
+    }
+
+    /// Replaces the contents of `file_path` with the file at `local_path`
+    async fn handle_update(
+        &mut self,
+        local_path: &str,
+        file_path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let file_data = Self::read_local_file(local_path)?;
+        self.put(file_path, &file_data, false).await
+    }
+
+    /// Uploads the file at `local_path` to `file_path`
+    async fn handle_create(
+        &mut self,
+        local_path: &str,
+        file_path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let file_data = Self::read_local_file(local_path)?;
+        self.put(file_path, &file_data, true).await
+    }
+
+    /// Reads the whole of a local file
+    fn read_local_file(local_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let read = || -> io::Result<Vec<u8>> {
+            let mut file = File::open(local_path)?;
+            let size = file.metadata()?.len() as usize;
+            let mut data = Vec::with_capacity(size);
+            file.read_to_end(&mut data)?;
+            Ok(data)
+        };
+        read().map_err(|e| io::Error::new(e.kind(), format!("{}: {}", local_path, e)).into())
+    }
+
+    /// Writes `file_data` to `file_path`, creating a new file if `is_create` is set and otherwise
+    /// replacing the contents of the file, or creating it if it doesn't exist
+    async fn put(
+        &mut self,
+        file_path: &str,
+        file_data: &[u8],
+        is_create: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = FileInfo {
+            file_path: file_path.to_string(),
+            file_size: file_data.len() as i64,
+        };
+        let request = self.request(FileRequest {
+            file_info: Some(file),
+        });
+        let response = if is_create {
+            self.namenode_client.create_file(request).await
+        } else {
+            self.namenode_client.update_file(request).await
+        };
+        let response = match response {
+            Ok(response) => response.into_inner(),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        self.write_file(file_path, &response.blocks, file_data)
+            .await?;
+        for block in &response.removed_blocks {
+            self.delete_block(block).await;
+        }
+        Ok(())
+    }
+
+    /// Creates an empty file and takes the lease on it, leaving it under construction until its
+    /// blocks are added with `write_next_block` and it is completed, and returns its block size
+    async fn begin_file(&mut self, file_path: &str) -> Result<usize, Box<dyn Error>> {
+        let file = FileInfo {
+            file_path: file_path.to_string(),
+            file_size: 0,
+        };
+        let request = self.request(FileRequest {
+            file_info: Some(file),
+        });
+        match self.namenode_client.create_file(request).await {
+            Ok(response) => Ok(response.into_inner().block_size as usize),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Takes the lease on an existing file, returning all of its blocks, of which the file keeps
+    /// only the full ones until the rest are added with `write_next_block`
+    async fn begin_append(&mut self, file_path: &str) -> Result<FileResponse, Box<dyn Error>> {
+        let file = FileInfo {
+            file_path: file_path.to_string(),
+            file_size: 0,
+        };
+        let request = self.request(FileRequest {
+            file_info: Some(file),
+        });
+        match self.namenode_client.append_file(request).await {
+            Ok(response) => Ok(response.into_inner()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Adds a block holding `block_data` to the end of a file this client is writing, and writes
+    /// it to the datanodes the namenode assigns it, returning it with the datanodes that stored it
+    async fn write_next_block(
+        &mut self,
+        file_path: &str,
+        block_data: Vec<u8>,
+    ) -> Result<LocatedBlock, Box<dyn Error>> {
+        let request = self.request(AddBlockRequest {
+            path: file_path.to_string(),
+            length: block_data.len() as i64,
+        });
+        let block = match self.namenode_client.add_block(request).await {
+            Ok(response) => response.into_inner(),
+            Err(e) => return Err(Box::new(e)),
+        };
+        let packets = Self::packets(&block, block_data);
+        self.write_block(&block, packets).await?;
+        Ok(block)
+    }
+
+    /// Writes the blocks of a file this client holds the lease on, renewing the lease for as long
+    /// as the writes take, then completes the file by confirming every block with the namenode,
+    /// which makes it readable
+    /// A write that fails leaves the lease to run out, after which the namenode recovers the file
+    async fn write_file(
+        &mut self,
+        file_path: &str,
+        blocks: &[LocatedBlock],
+        file_data: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let write_blocks = async {
+            for block in blocks {
+                let packets = Self::block_packets(block, file_data)?;
+                self.write_block(block, packets).await?;
+            }
+            Ok::<(), Box<dyn Error>>(())
+        };
+        tokio::select! {
+            result = write_blocks => result?,
+            _ = self.renew_lease_loop() => {}
+        }
+        self.complete_file(file_path, blocks).await
+    }
+
+    /// Completes a file this client has written, given every block of it with the datanodes that
+    /// stored it, which makes it readable and releases the lease on it
+    async fn complete_file(
+        &mut self,
+        file_path: &str,
+        blocks: &[LocatedBlock],
+    ) -> Result<(), Box<dyn Error>> {
+        let request = self.request(CompleteFileRequest {
+            path: file_path.to_string(),
+            blocks: blocks.to_vec(),
+        });
+        match self.namenode_client.complete_file(request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Renews this client's leases every `LEASE_RENEWAL_INTERVAL`, until it is dropped
+    async fn renew_lease_loop(&self) {
+        let mut namenode_client = self.namenode_client.clone();
+        loop {
+            tokio::time::sleep(LEASE_RENEWAL_INTERVAL).await;
+            let request = self.request(RenewLeaseRequest {});
+            if let Err(e) = namenode_client.renew_lease(request).await {
+                eprintln!("Failed to renew lease: {}", e.message());
+            }
+        }
+    }
+
+    /// Cuts a block out of the file's contents, where the namenode laid it out, into packets
+    fn block_packets(
+        block: &LocatedBlock,
+        file_data: &[u8],
+    ) -> Result<Vec<BlockPacket>, Box<dyn Error>> {
+        let start = block.offset as usize;
+        let end = start + block.length as usize;
+        let block_data = file_data
+            .get(start..end)
+            .ok_or("File is shorter than the namenode's layout of it")?
+            .to_vec();
+        Ok(Self::packets(block, block_data))
+    }
+
+    /// Splits the contents of a block into packets, checksumming each chunk
+    fn packets(block: &LocatedBlock, block_data: Vec<u8>) -> Vec<BlockPacket> {
+        let checksums = checksum::compute(&block_data, BYTES_PER_CHECKSUM);
+        let packets = packet::split(
+            block.block_id,
+            block.generation_stamp,
+            block_data,
+            checksums,
+            BYTES_PER_CHECKSUM,
+        );
+        packets.collect()
+    }
+
+    /// Sends a block to every replica the namenode assigned it, retrying replicas that fail
+    /// The write fails unless every replica acknowledges it
+    async fn write_block(
+        &self,
+        block: &LocatedBlock,
+        packets: Vec<BlockPacket>,
+    ) -> Result<(), Box<dyn Error>> {
+        if block.nodes.is_empty() {
+            return Err(format!("No datanodes available to store block {}", block.block_id).into());
+        }
+
+        let mut num_failed = 0;
+        for datanode_addr in &block.nodes {
+            let mut error = None;
+            for _ in 0..WRITE_ATTEMPTS {
+                match self.write_block_to(datanode_addr, packets.clone()).await {
+                    Ok(()) => {
+                        error = None;
+                        break;
+                    }
+                    Err(e) => error = Some(e.to_string()),
+                }
+            }
+
+            if let Some(e) = error {
+                eprintln!(
+                    "Failed to write block {} to datanode {}: {}",
+                    block.block_id, datanode_addr, e
+                );
+                num_failed += 1;
+            }
+        }
+
+        if num_failed > 0 {
+            return Err(format!(
+                "{} of {} replicas of block {} did not acknowledge the write",
+                num_failed,
+                block.nodes.len(),
+                block.block_id
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Streams the packets of a block to a single datanode, which must acknowledge every packet in
+    /// order and then the whole block once it has stored it
+    async fn write_block_to(
+        &self,
+        datanode_addr: &str,
+        packets: Vec<BlockPacket>,
+    ) -> Result<(), Box<dyn Error>> {
+        let num_packets = packets.len() as u64;
+        let block_size = packets.first().map_or(0, |packet| packet.block_size);
+        let mut datanode_client = self.create_client(datanode_addr).await?;
+        let mut acks = datanode_client
+            .write_block(tokio_stream::iter(packets))
+            .await?
+            .into_inner();
+
+        let mut num_acked = 0;
+        while let Some(ack) = acks.message().await? {
+            if ack.block_stored {
+                if num_acked != num_packets || ack.bytes_received != block_size {
+                    return Err(format!(
+                        "Datanode stored {} of {} bytes",
+                        ack.bytes_received, block_size
+                    )
+                    .into());
+                }
+                return Ok(());
+            }
+            if ack.seqno != num_acked {
+                return Err(format!(
+                    "Expected an acknowledgement of packet {} but received one of packet {}",
+                    num_acked, ack.seqno
+                )
+                .into());
+            }
+            num_acked += 1;
+        }
+        Err("Datanode closed the stream before storing the block".into())
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Client, DfsError, READ_BUFFER_SIZE};
+use crate::proto::LocatedBlock;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+/// A read of a range of the file that is under way
+type PendingRead = Pin<Box<dyn Future<Output = Result<Vec<u8>, DfsError>> + Send>>;
+
+/// Reads a file from any position, fetching only the ranges of its blocks that are asked for
+/// Reads through `AsyncRead` fetch up to `READ_BUFFER_SIZE` bytes at a time and hand them out
+/// as they are asked for
+pub struct FileReader {
+    client: Client,
+    length: u64,
+    /// Blocks of the file in order
+    blocks: Arc<[LocatedBlock]>,
+    /// Offset of the next byte `AsyncRead` returns
+    position: u64,
+    /// Bytes fetched from `position` on that haven't been returned yet
+    buffer: Vec<u8>,
+    pending: Option<PendingRead>,
+}
+
+impl FileReader {
+    /// Creates a reader at the start of a file laid out in `blocks`
+    pub(crate) fn new(client: Client, length: u64, blocks: Vec<LocatedBlock>) -> Self {
+        Self {
+            client,
+            length,
+            blocks: blocks.into(),
+            position: 0,
+            buffer: vec![],
+            pending: None,
+        }
+    }
+
+    /// Length of the file in bytes
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Offset the next read starts at
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads up to `len` bytes from `offset` without moving the position
+    /// Returns fewer bytes if the file ends first, and none past its end
+    pub async fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, DfsError> {
+        let end = std::cmp::min(offset.saturating_add(len as u64), self.length);
+        read_range(&mut self.client, &self.blocks, offset, end).await
+    }
+}
+
+impl AsyncRead for FileReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.buffer.is_empty() {
+            if this.position >= this.length || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let pending = this.pending.get_or_insert_with(|| {
+                let mut client = this.client.clone();
+                let blocks = this.blocks.clone();
+                let offset = this.position;
+                let len = buf.remaining().max(READ_BUFFER_SIZE) as u64;
+                let end = std::cmp::min(offset.saturating_add(len), this.length);
+                Box::pin(async move { read_range(&mut client, &blocks, offset, end).await })
+            });
+            let result = ready!(pending.as_mut().poll(cx));
+            this.pending = None;
+            this.buffer = result?;
+        }
+
+        let len = std::cmp::min(buf.remaining(), this.buffer.len());
+        buf.put_slice(&this.buffer[..len]);
+        this.buffer.drain(..len);
+        this.position += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for FileReader {
+    /// Moves the position of the next read, which may be past the end of the file
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+        this.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot seek before the start of the file",
+            )
+        })?;
+        this.buffer.clear();
+        this.pending = None;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// Reads the part of a file from `start` up to `end`, reporting the replicas it couldn't read to
+/// the namenode
+async fn read_range(
+    client: &mut Client,
+    blocks: &[LocatedBlock],
+    start: u64,
+    end: u64,
+) -> Result<Vec<u8>, DfsError> {
+    let mut data = Vec::with_capacity(end.saturating_sub(start) as usize);
+    let mut failed_replicas = vec![];
+    let mut result = Ok(());
+
+    for (block, range_start, range_length) in block_ranges(blocks, start, end) {
+        match client
+            .read_block_range(block, range_start, range_length, &mut failed_replicas)
+            .await
+        {
+            Ok(block_data) => data.extend_from_slice(&block_data),
+            Err(e) => {
+                result = Err(DfsError::from(e));
+                break;
+            }
+        }
+    }
+
+    client.report_failed_replicas(failed_replicas).await;
+    result.map(|_| data)
+}
+
+/// Returns each block holding part of the file from `start` up to `end`, with the offset and
+/// length of that part within the block
+fn block_ranges(
+    blocks: &[LocatedBlock],
+    start: u64,
+    end: u64,
+) -> impl Iterator<Item = (&LocatedBlock, u64, u64)> {
+    blocks.iter().filter_map(move |block| {
+        let block_start = block.offset as u64;
+        let block_end = block_start + block.length as u64;
+        if block_end <= start || block_start >= end {
+            return None;
+        }
+        let range_start = start.max(block_start) - block_start;
+        let range_end = end.min(block_end) - block_start;
+        Some((block, range_start, range_end - range_start))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_ranges() {
+        let blocks: Vec<LocatedBlock> = [(0, 4), (4, 4), (8, 2)]
+            .into_iter()
+            .map(|(offset, length)| LocatedBlock {
+                offset,
+                length,
+                ..Default::default()
+            })
+            .collect();
+        let ranges = |start, end| -> Vec<(i64, u64, u64)> {
+            block_ranges(&blocks, start, end)
+                .map(|(block, offset, length)| (block.offset, offset, length))
+                .collect()
+        };
+
+        assert_eq!(ranges(0, 10), vec![(0, 0, 4), (4, 0, 4), (8, 0, 2)]);
+        assert_eq!(ranges(3, 9), vec![(0, 3, 1), (4, 0, 4), (8, 0, 1)]);
+        assert_eq!(ranges(5, 7), vec![(4, 1, 2)]);
+        assert_eq!(ranges(4, 4), vec![]);
+        assert_eq!(ranges(10, 10), vec![]);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Client, DfsError};
+use crate::proto::LocatedBlock;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::task::JoinHandle;
+
+/// Completion of the file that is under way
+type PendingClose = Pin<Box<dyn Future<Output = Result<(), DfsError>> + Send>>;
+
+/// Writes a file, which this client holds the lease on while the writer is open
+/// Each block is written to the datanodes once it fills, while the next one is filled, so at most
+/// two blocks are held in memory. The last block is written when the writer is shut down, which
+/// completes the file, and nothing written can be read until then
+pub struct FileWriter {
+    client: Client,
+    path: String,
+    block_size: usize,
+    /// Blocks of the file written so far, with the datanodes that stored them
+    blocks: Vec<LocatedBlock>,
+    /// Contents of the block being filled
+    buffer: Vec<u8>,
+    /// Write of the last block that filled up
+    pending_block: Option<JoinHandle<Result<LocatedBlock, DfsError>>>,
+    pending_close: Option<PendingClose>,
+    /// Keeps the lease on the file until the writer is shut down or dropped
+    lease_renewal: JoinHandle<()>,
+    /// Set once a block couldn't be written, after which the file can't be completed
+    failed: bool,
+    closed: bool,
+}
+
+impl FileWriter {
+    /// Creates a writer that adds blocks of `block_size` bytes to the file at `path` after its
+    /// full `blocks`, starting with `data`
+    pub(crate) fn new(
+        client: Client,
+        path: &str,
+        block_size: usize,
+        blocks: Vec<LocatedBlock>,
+        data: Vec<u8>,
+    ) -> Self {
+        let renewer = client.clone();
+        Self {
+            client,
+            path: path.to_string(),
+            block_size,
+            blocks,
+            buffer: data,
+            pending_block: None,
+            pending_close: None,
+            lease_renewal: tokio::spawn(async move { renewer.renew_lease_loop().await }),
+            failed: false,
+            closed: false,
+        }
+    }
+
+    /// Path of the file being written
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        if self.closed || self.pending_close.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "File has been closed",
+            ));
+        }
+        if self.failed {
+            return Err(io::Error::other("An earlier write to the file failed"));
+        }
+        Ok(())
+    }
+
+    /// Starts writing the contents of the buffer to the datanodes as the next block of the file
+    fn start_block(&mut self) {
+        let mut client = self.client.clone();
+        let path = self.path.clone();
+        let data = std::mem::take(&mut self.buffer);
+        self.pending_block = Some(tokio::spawn(async move {
+            Ok(client.write_next_block(&path, data).await?)
+        }));
+    }
+
+    /// Waits for the block being written, if any, to be stored on its datanodes
+    fn poll_pending_block(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(pending) = self.pending_block.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(Pin::new(pending).poll(cx));
+        self.pending_block = None;
+        match result {
+            Ok(Ok(block)) => {
+                self.blocks.push(block);
+                Poll::Ready(Ok(()))
+            }
+            Ok(Err(e)) => {
+                self.failed = true;
+                Poll::Ready(Err(e.into()))
+            }
+            Err(e) => {
+                self.failed = true;
+                Poll::Ready(Err(io::Error::other(e)))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for FileWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.check_open()?;
+        // a full buffer waits for the block before it to be written
+        if this.buffer.len() == this.block_size {
+            ready!(this.poll_pending_block(cx))?;
+            this.start_block();
+        }
+        let len = std::cmp::min(buf.len(), this.block_size - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..len]);
+        if this.buffer.len() == this.block_size && this.pending_block.is_none() {
+            this.start_block();
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    /// Waits for every full block written so far to be stored on the datanodes
+    /// A partly filled block is kept until the writer is shut down, as a block can't be added to
+    /// once it has been written
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.check_open()?;
+        loop {
+            ready!(this.poll_pending_block(cx))?;
+            if this.buffer.len() < this.block_size {
+                return Poll::Ready(Ok(()));
+            }
+            this.start_block();
+        }
+    }
+
+    /// Writes the rest of the file to the datanodes and completes it, after which it can be read
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        if this.pending_close.is_none() {
+            this.check_open()?;
+            loop {
+                ready!(this.poll_pending_block(cx))?;
+                if this.buffer.is_empty() {
+                    break;
+                }
+                this.start_block();
+            }
+            let mut client = this.client.clone();
+            let path = this.path.clone();
+            let blocks = std::mem::take(&mut this.blocks);
+            this.pending_close = Some(Box::pin(async move {
+                Ok(client.complete_file(&path, &blocks).await?)
+            }));
+        }
+        let pending = this
+            .pending_close
+            .as_mut()
+            .expect("Completion was just started");
+        let result = ready!(pending.as_mut().poll(cx));
+        this.pending_close = None;
+        this.closed = true;
+        this.lease_renewal.abort();
+        Poll::Ready(result.map_err(io::Error::from))
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        // a file that isn't completed is recovered by the namenode once the lease runs out
+        self.lease_renewal.abort();
+        if let Some(pending) = &self.pending_block {
+            pending.abort();
+        }
+    }
+}
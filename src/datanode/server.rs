@@ -1,20 +1,36 @@
 use std::error::Error;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio::time::interval;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
 
+use crate::proto::data_node_protocols_client::DataNodeProtocolsClient;
 use crate::proto::data_node_protocols_server::{DataNodeProtocols, DataNodeProtocolsServer};
 use crate::proto::{
-    hearbeat_protocol_client::HearbeatProtocolClient, DeleteBlockRequest, EditBlockRequest,
-    EmptyResponse, FileInfo, FileRequest, Heartbeat, ReadBlockResponse,
+    hearbeat_protocol_client::HearbeatProtocolClient, BadBlockReport, BlockInfo, BlockPacket,
+    BlockReport, CopyBlockResponse, DeleteBlockRequest, EditBlockRequest, EmptyResponse, Heartbeat,
+    IncrementalBlockReport, PacketAck, ReadBlockRequest, ReadBlockResponse, TransferBlockRequest,
+    TransferBlockResponse,
 };
 
-use crate::datanode::storage::Storage;
+use crate::checksum;
+use crate::datanode::storage::{BlockReader, BlockWriter, Storage};
+use crate::packet::{self, BlockHeader, PacketChecker};
 use tonic::transport::Channel;
 use tonic::transport::Server;
 
+/// Number of acknowledgements queued for a client writing a block before the datanode stops
+/// reading packets until the client catches up
+const ACK_BUFFER: usize = 16;
+
+/// Number of packets read ahead of a client reading a block
+const PACKET_BUFFER: usize = 4;
+
 /// Server that runs a datanode
 #[derive(Clone)]
 pub struct DataNodeServer {
@@ -29,20 +45,32 @@ pub struct DataNodeServer {
 }
 
 impl DataNodeServer {
-    pub fn new(port: u16, namenode_port: u16) -> Self {
+    /// Creates a datanode, storing its blocks in `data_dir` if one is given and in memory otherwise
+    pub fn new(
+        port: u16,
+        namenode_port: u16,
+        data_dir: Option<PathBuf>,
+    ) -> Result<Self, Box<dyn Error>> {
         let datanode_addr = SocketAddr::from(([127, 0, 0, 1], port));
         let namenode_addr = SocketAddr::from(([127, 0, 0, 1], namenode_port));
-        DataNodeServer {
+        let storage = match data_dir {
+            Some(data_dir) => Storage::open(&data_dir)?,
+            None => Storage::new(),
+        };
+        Ok(DataNodeServer {
             datanode_addr,
-            storage: Arc::new(Mutex::new(Storage::new())),
+            storage: Arc::new(Mutex::new(storage)),
             namenode_addr,
-        }
+        })
     }
 
     /// Runs the datanode server on the specified port
     pub async fn run_dataserver(&self) -> Result<(), Box<dyn Error>> {
-        let heartbeat_status = self.send_heartbeat_loop();
+        let (suspects_sender, suspects) = mpsc::unbounded_channel();
+        let heartbeat_status = self.send_heartbeat_loop(suspects_sender);
         let service_status = self.run_service();
+        let scanner_status = self.scan_blocks_loop(suspects);
+        let block_report_status = self.send_block_report_loop();
 
         tokio::select! {
             result = heartbeat_status => {
@@ -51,6 +79,18 @@ impl DataNodeServer {
                     Err(e) => return Err(e),
                 }
             }
+            result = scanner_status => {
+                match result {
+                    Ok(_) => {},
+                    Err(e) => return Err(e),
+                }
+            }
+            result = block_report_status => {
+                match result {
+                    Ok(_) => {},
+                    Err(e) => return Err(e),
+                }
+            }
             result = service_status => {
                 match result {
                     Ok(_) => {},
@@ -71,8 +111,16 @@ impl DataNodeServer {
         Ok(())
     }
 
-    /// Sends the heartbeat to the namenode every 5 seconds
-    pub async fn send_heartbeat_loop(&self) -> Result<(), Box<dyn Error>> {
+    /// Sends the heartbeat to the namenode every 5 seconds, along with an incremental block report
+    /// of any blocks stored or deleted since the last heartbeat, and carries out any deletions and
+    /// copies the namenode asks for in its reply, passing blocks it asks to have verified on to
+    /// the scanner through `suspects`
+    /// Heartbeats that fail are retried on the next tick, so the datanode outlives a namenode
+    /// restart and sends the restarted namenode a full block report when asked for one
+    pub async fn send_heartbeat_loop(
+        &self,
+        suspects: mpsc::UnboundedSender<u64>,
+    ) -> Result<(), Box<dyn Error>> {
         let mut interval = interval(Duration::from_secs(5));
         let channel = Channel::from_shared(format!("http://{}", self.namenode_addr))
             .unwrap()
@@ -86,13 +134,375 @@ impl DataNodeServer {
             let request = tonic::Request::new(Heartbeat {
                 address: self.datanode_addr.to_string(),
             });
-            let _ = heartbeat_client.send_heartbeat(request).await?;
+            let response = match heartbeat_client.send_heartbeat(request).await {
+                Ok(response) => response.into_inner(),
+                Err(e) => {
+                    println!("Failed to send heartbeat: {}", e.message());
+                    continue;
+                }
+            };
+
+            if response.send_block_report {
+                if let Err(e) = self.send_block_report(&mut heartbeat_client).await {
+                    println!("Failed to send block report: {}", e);
+                }
+            }
+            for block_id in response.invalidated_blocks {
+                println!("Deleting invalidated block: {}", block_id);
+                if let Err(e) = self.storage.lock().await.delete(block_id).await {
+                    println!("Failed to delete block {}: {}", block_id, e);
+                }
+            }
+            for block_id in response.suspect_blocks {
+                println!("Verifying block {} a client failed to read", block_id);
+                suspects.send(block_id)?;
+            }
+            for command in response.replication_commands {
+                let dataserver = self.clone();
+                tokio::spawn(async move {
+                    let block_id = command.block_id;
+                    if let Err(e) = dataserver.push_block(block_id, command.targets).await {
+                        println!("Failed to replicate block {}: {}", block_id, e);
+                    }
+                });
+            }
+
+            let changes = self.storage.lock().await.take_changes();
+            if !changes.is_empty() {
+                let request = tonic::Request::new(IncrementalBlockReport {
+                    address: self.datanode_addr.to_string(),
+                    changes,
+                });
+                // changes lost here are caught up on by the next full block report
+                if let Err(e) = heartbeat_client.incremental_block_report(request).await {
+                    println!("Failed to send incremental block report: {}", e.message());
+                }
+            }
+        }
+    }
+
+    /// Streams a block to each of the targets, returning the targets that acknowledged a verified
+    /// copy of it
+    /// The block is read from storage a packet at a time, so a replica that has gone bad stops the
+    /// copy short and the target rejects it
+    async fn push_block(
+        &self,
+        block_id: u64,
+        targets: Vec<String>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut completed_targets = vec![];
+        for target in targets {
+            let reader = self.storage.lock().await.open_block(block_id).await?;
+            match Self::stream_block(&target, reader).await {
+                Ok(_) => {
+                    println!("Copied block {} to {}", block_id, target);
+                    completed_targets.push(target);
+                }
+                Err(e) => println!("Failed to copy block {} to {}: {}", block_id, target, e),
+            }
+        }
+        Ok(completed_targets)
+    }
+
+    /// Streams the packets of a block to another datanode's CopyBlock endpoint
+    async fn stream_block(target: &str, reader: BlockReader) -> Result<(), Box<dyn Error>> {
+        let expected_bytes = reader.block().length();
+        let channel = Channel::from_shared(format!("http://{}", target))?
+            .connect()
+            .await?;
+        let mut datanode_client = DataNodeProtocolsClient::new(channel);
+
+        let packets = stream_packets(reader, 0, expected_bytes).map_while(Result::ok);
+        let response = datanode_client.copy_block(packets).await?.into_inner();
+        if response.bytes_received as usize != expected_bytes {
+            return Err(format!(
+                "Target acknowledged {} of {} bytes",
+                response.bytes_received, expected_bytes
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Opens the range of a block a read asks for, returning a reader along with the offset and
+    /// length of the range
+    async fn open_range(
+        &self,
+        block_id: u64,
+        offset: i64,
+        length: i64,
+    ) -> Result<(BlockReader, usize, usize), tonic::Status> {
+        let storage = self.storage.lock().await;
+        let block = storage
+            .get_block(block_id)
+            .ok_or_else(|| tonic::Status::not_found("Block does not exist"))?;
+        let (offset, length) = requested_range(offset, length, block.length())
+            .map_err(|e| tonic::Status::out_of_range(e.to_string()))?;
+        let reader = storage
+            .open_block(block_id)
+            .await
+            .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)))?;
+        Ok((reader, offset, length))
+    }
+
+    /// Sends a full block report to the namenode on startup and every minute after that
+    pub async fn send_block_report_loop(&self) -> Result<(), Box<dyn Error>> {
+        let mut interval = interval(Duration::from_secs(60));
+        let channel = Channel::from_shared(format!("http://{}", self.namenode_addr))
+            .unwrap()
+            .connect()
+            .await?;
+
+        let mut heartbeat_client = HearbeatProtocolClient::new(channel);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.send_block_report(&mut heartbeat_client).await {
+                println!("Failed to send block report: {}", e);
+            }
+        }
+    }
+
+    /// Sends the namenode every replica in storage
+    async fn send_block_report(
+        &self,
+        heartbeat_client: &mut HearbeatProtocolClient<Channel>,
+    ) -> Result<(), Box<dyn Error>> {
+        let replicas = self.storage.lock().await.replicas();
+        let request = tonic::Request::new(BlockReport {
+            address: self.datanode_addr.to_string(),
+            replicas,
+        });
+        heartbeat_client.block_report(request).await?;
+        Ok(())
+    }
+
+    /// Verifies every stored block once a minute, and each block received on `suspects` as soon as
+    /// it arrives, reporting corrupt replicas to the namenode and discarding them once it has
+    /// accepted the report
+    /// A report that fails is sent again on the next pass, so the scanner outlives a namenode that
+    /// is briefly unreachable
+    pub async fn scan_blocks_loop(
+        &self,
+        mut suspects: mpsc::UnboundedReceiver<u64>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut interval = interval(Duration::from_secs(60));
+        let channel = Channel::from_shared(format!("http://{}", self.namenode_addr))
+            .unwrap()
+            .connect()
+            .await?;
+
+        let mut heartbeat_client = HearbeatProtocolClient::new(channel);
+        // corrupt replicas, along with their generation stamps, the namenode hasn't been told about
+        let mut pending: Vec<(u64, u64)> = vec![];
+
+        loop {
+            let block_ids = tokio::select! {
+                _ = interval.tick() => self.storage.lock().await.block_ids(),
+                Some(block_id) = suspects.recv() => {
+                    let mut block_ids = vec![block_id];
+                    while let Ok(block_id) = suspects.try_recv() {
+                        block_ids.push(block_id);
+                    }
+                    block_ids
+                }
+            };
+            for bad_block in self.scan_blocks(block_ids).await {
+                if !pending.contains(&bad_block) {
+                    pending.push(bad_block);
+                }
+            }
+            // a replica deleted or rewritten since it was found corrupt isn't reported
+            let storage = self.storage.lock().await;
+            pending.retain(|(block_id, generation_stamp)| {
+                storage
+                    .get_block(*block_id)
+                    .is_some_and(|block| block.generation_stamp == *generation_stamp)
+            });
+            drop(storage);
+            if pending.is_empty() {
+                continue;
+            }
+
+            let request = tonic::Request::new(BadBlockReport {
+                address: self.datanode_addr.to_string(),
+                block_ids: pending.iter().map(|(block_id, _)| *block_id).collect(),
+            });
+            if let Err(e) = heartbeat_client.report_bad_blocks(request).await {
+                println!("Failed to report corrupt blocks: {}", e.message());
+                continue;
+            }
+
+            let mut storage = self.storage.lock().await;
+            for (block_id, _) in pending.drain(..) {
+                if let Err(e) = storage.delete(block_id).await {
+                    println!("Failed to delete corrupt block {}: {}", block_id, e);
+                }
+            }
+        }
+    }
+
+    /// Returns the ids and generation stamps of the blocks that fail checksum verification, out of
+    /// `block_ids`
+    /// Blocks that aren't stored are skipped
+    async fn scan_blocks(&self, block_ids: Vec<u64>) -> Vec<(u64, u64)> {
+        let mut bad_blocks = vec![];
+
+        // blocks are read outside the lock so that client requests aren't held up by the scan
+        for block_id in block_ids {
+            let storage = self.storage.lock().await;
+            let Some(generation_stamp) = storage
+                .get_block(block_id)
+                .map(|block| block.generation_stamp)
+            else {
+                continue;
+            };
+            let reader = storage
+                .open_block(block_id)
+                .await
+                .map_err(|e| e.to_string());
+            drop(storage);
+            let result = match reader {
+                Ok(mut reader) => reader.verify().await.map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                println!("Block {} failed verification: {}", block_id, e);
+                bad_blocks.push((block_id, generation_stamp));
+            }
+        }
+        bad_blocks
+    }
+}
+
+/// Writes a block streamed in packets to storage a packet at a time, checking that the packets
+/// arrive in order and add up to the advertised size, and that each matches the checksums sent
+/// with it
+/// Each packet is acknowledged on `acks` once it has been checked, if given. The block is returned
+/// uncommitted, so it only replaces the stored replica once every packet has arrived
+async fn receive_block(
+    storage: &Mutex<Storage>,
+    mut stream: tonic::Streaming<BlockPacket>,
+    acks: Option<&mpsc::Sender<Result<PacketAck, tonic::Status>>>,
+) -> Result<(BlockHeader, BlockWriter), Box<dyn Error>> {
+    let mut checker = PacketChecker::new();
+    let mut writer = None;
+    while let Some(packet) = stream.next().await {
+        let packet = packet?;
+        checker.check(&packet)?;
+        if writer.is_none() {
+            let header = checker.header().cloned().ok_or("Packet has no header")?;
+            let mut storage = storage.lock().await;
+            let started = storage
+                .begin_write(
+                    header.block_id,
+                    header.generation_stamp,
+                    header.bytes_per_checksum,
+                )
+                .await?;
+            writer = Some(started);
+        }
+        if let Some(writer) = &mut writer {
+            writer.write(&packet.data, &packet.checksums).await?;
+        }
+        if let Some(acks) = acks {
+            let ack = PacketAck {
+                seqno: packet.seqno,
+                ..Default::default()
+            };
+            acks.send(Ok(ack)).await.map_err(|_| "Writer went away")?;
         }
     }
+    let header = checker.finish()?;
+    let writer = writer.ok_or("No packets received")?;
+    Ok((header, writer))
+}
+
+/// Streams `length` bytes of a block from `offset` in packets, reading and checking a packet's
+/// worth of the block at a time
+/// The stream ends with an error if part of the block fails verification or can't be read
+fn stream_packets(
+    mut reader: BlockReader,
+    offset: usize,
+    length: usize,
+) -> ReceiverStream<Result<BlockPacket, tonic::Status>> {
+    let (sender, receiver) = mpsc::channel(PACKET_BUFFER);
+    tokio::spawn(async move {
+        let block = reader.block().clone();
+        let packet_size = packet::packet_size(block.bytes_per_checksum());
+        // an empty range is still sent as one packet, which carries the header
+        let num_packets = length.div_ceil(packet_size).max(1);
+
+        for seqno in 0..num_packets {
+            let start = seqno * packet_size;
+            let packet_length = std::cmp::min(packet_size, length - start);
+            let packet = reader
+                .read_range(offset + start, packet_length)
+                .await
+                .map(|(data, checksums)| BlockPacket {
+                    block_id: block.id,
+                    block_size: length as i64,
+                    checksums,
+                    bytes_per_checksum: block.bytes_per_checksum() as i64,
+                    offset: start as i64,
+                    data,
+                    generation_stamp: block.generation_stamp,
+                    seqno: seqno as u64,
+                })
+                .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)));
+            let failed = packet.is_err();
+            if sender.send(packet).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
+}
+
+/// Returns the offset and length of the range of a block a read asks for, where a length of 0 asks
+/// for the rest of the block
+fn requested_range(
+    offset: i64,
+    length: i64,
+    block_length: usize,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    if offset < 0 || length < 0 {
+        return Err("Offset and length can't be negative".into());
+    }
+    let offset = offset as usize;
+    if offset > block_length {
+        return Err(format!(
+            "Offset {} is past the end of the {} byte block",
+            offset, block_length
+        )
+        .into());
+    }
+    let remaining = block_length - offset;
+    let length = match length as usize {
+        0 => remaining,
+        length => std::cmp::min(length, remaining),
+    };
+    Ok((offset, length))
+}
+
+/// Checks the data of an incoming block against the checksums the client computed for it
+fn verify_block_info(block_info: &BlockInfo) -> Result<(), Box<dyn Error>> {
+    if block_info.checksums.is_empty() {
+        return Ok(());
+    }
+    let len = std::cmp::min(block_info.block_data.len(), block_info.block_size as usize);
+    checksum::verify(
+        &block_info.block_data[..len],
+        block_info.bytes_per_checksum as usize,
+        &block_info.checksums,
+    )
 }
 
 #[tonic::async_trait]
 impl DataNodeProtocols for DataNodeServer {
+    type WriteBlockStream = ReceiverStream<Result<PacketAck, tonic::Status>>;
+    type ReadBlockStream = Pin<Box<dyn Stream<Item = Result<BlockPacket, tonic::Status>> + Send>>;
+
     /// Creates a file in the datanode and returns a success message
     async fn create_file(
         &self,
@@ -102,13 +512,14 @@ impl DataNodeProtocols for DataNodeServer {
         let block_info = request.block_info.ok_or_else(|| {
             tonic::Status::new(tonic::Code::InvalidArgument, "Block_info not found")
         })?;
-        let file_path = request.file_name;
 
-        println!("Creating file: {}", file_path);
+        println!("Creating block: {}", block_info.block_id);
+        verify_block_info(&block_info)
+            .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)))?;
 
         let mut storage = self.storage.lock().await;
         storage
-            .create(&file_path, block_info)
+            .create(block_info)
             .await
             .map_err(|_| tonic::Status::new(tonic::Code::Internal, "Failed to create file"))?;
         drop(storage);
@@ -126,22 +537,16 @@ impl DataNodeProtocols for DataNodeServer {
         let block_info = request.block_info.ok_or_else(|| {
             tonic::Status::new(tonic::Code::InvalidArgument, "Block_info not found")
         })?;
-        let file_path = request.file_name;
 
-        println!("Updating file: {}", file_path);
+        println!("Updating block: {}", block_info.block_id);
+        verify_block_info(&block_info)
+            .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)))?;
 
         let mut storage = self.storage.lock().await;
-        if block_info.block_size == 0 {
-            storage
-                .delete(&file_path)
-                .await
-                .expect("Failed to delete file");
-        } else {
-            storage
-                .update(&file_path, block_info)
-                .await
-                .map_err(|_| tonic::Status::new(tonic::Code::Internal, "Failed to update file"))?;
-        }
+        storage
+            .update(block_info)
+            .await
+            .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
 
         let reply = EmptyResponse { success: true };
         Ok(tonic::Response::new(reply))
@@ -153,42 +558,146 @@ impl DataNodeProtocols for DataNodeServer {
         request: tonic::Request<DeleteBlockRequest>,
     ) -> Result<tonic::Response<EmptyResponse>, tonic::Status> {
         let request = request.into_inner();
-        let block_name = request.block_name;
+        let block_id = request.block_id;
 
-        println!("Deleting file: {}", block_name);
+        println!("Deleting block: {}", block_id);
 
         let mut storage = self.storage.lock().await;
         storage
-            .delete(&block_name)
+            .delete(block_id)
             .await
-            .expect("Failed to delete file");
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
         drop(storage);
 
         let reply = EmptyResponse { success: true };
         Ok(tonic::Response::new(reply))
     }
 
-    /// Read a file from the datanode and return the file data
+    /// Reads a range of a block from the datanode, checking it against the stored checksums
     async fn read_file(
         &self,
-        request: tonic::Request<FileRequest>,
+        request: tonic::Request<ReadBlockRequest>,
     ) -> Result<tonic::Response<ReadBlockResponse>, tonic::Status> {
-        let request = request.into_inner();
-        let FileInfo {
-            file_path,
-            file_size: _,
-        } = request.file_info.ok_or_else(|| {
-            tonic::Status::new(tonic::Code::InvalidArgument, "File info not found")
-        })?;
+        let ReadBlockRequest {
+            block_id,
+            offset,
+            length,
+        } = request.into_inner();
 
-        let storage = self.storage.lock().await;
-        let buf = storage.read(&file_path).await.expect("Failed to read file");
-        drop(storage);
+        let (mut reader, offset, length) = self.open_range(block_id, offset, length).await?;
+        let (block_data, checksums) = reader
+            .read_range(offset, length)
+            .await
+            .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)))?;
 
+        let block = reader.block();
         let response = ReadBlockResponse {
-            bytes_read: buf.len() as i64,
-            bytes_total: 0,
-            block_data: buf,
+            bytes_read: block_data.len() as i64,
+            bytes_total: block.length() as i64,
+            block_data,
+            checksums,
+            bytes_per_checksum: block.bytes_per_checksum() as i64,
+            generation_stamp: block.generation_stamp,
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    /// Stores a block a client streams in packets, acknowledging each packet once it has been
+    /// verified and the block once it has been stored
+    async fn write_block(
+        &self,
+        request: tonic::Request<tonic::Streaming<BlockPacket>>,
+    ) -> Result<tonic::Response<Self::WriteBlockStream>, tonic::Status> {
+        let stream = request.into_inner();
+        let (acks, ack_stream) = mpsc::channel(ACK_BUFFER);
+        let storage = self.storage.clone();
+
+        tokio::spawn(async move {
+            let received = receive_block(&storage, stream, Some(&acks))
+                .await
+                .map_err(|e| {
+                    println!("Failed to receive block: {}", e);
+                    tonic::Status::data_loss(format!("Block failed verification: {}", e))
+                });
+            let (header, writer) = match received {
+                Ok(received) => received,
+                Err(status) => {
+                    let _ = acks.send(Err(status)).await;
+                    return;
+                }
+            };
+
+            println!("Writing block: {}", header.block_id);
+            let ack = PacketAck {
+                block_stored: true,
+                bytes_received: header.block_size as i64,
+                ..Default::default()
+            };
+            let result = storage.lock().await.commit(writer).await;
+            let ack = result
+                .map(|_| ack)
+                .map_err(|e| tonic::Status::failed_precondition(e.to_string()));
+            let _ = acks.send(ack).await;
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(ack_stream)))
+    }
+
+    /// Streams a range of a block to a client in packets, once it has been checked against the
+    /// stored checksums
+    async fn read_block(
+        &self,
+        request: tonic::Request<ReadBlockRequest>,
+    ) -> Result<tonic::Response<Self::ReadBlockStream>, tonic::Status> {
+        let ReadBlockRequest {
+            block_id,
+            offset,
+            length,
+        } = request.into_inner();
+
+        let (reader, offset, length) = self.open_range(block_id, offset, length).await?;
+        let stream = stream_packets(reader, offset, length);
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
+    /// Copies one of this datanode's blocks to other datanodes
+    async fn transfer_block(
+        &self,
+        request: tonic::Request<TransferBlockRequest>,
+    ) -> Result<tonic::Response<TransferBlockResponse>, tonic::Status> {
+        let TransferBlockRequest { block_id, targets } = request.into_inner();
+
+        println!("Transferring block {} to {:?}", block_id, targets);
+
+        let completed_targets = self
+            .push_block(block_id, targets)
+            .await
+            .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
+
+        let response = TransferBlockResponse { completed_targets };
+        Ok(tonic::Response::new(response))
+    }
+
+    /// Stores a block streamed from another datanode once it has been verified
+    async fn copy_block(
+        &self,
+        request: tonic::Request<tonic::Streaming<BlockPacket>>,
+    ) -> Result<tonic::Response<CopyBlockResponse>, tonic::Status> {
+        let (header, writer) = receive_block(&self.storage, request.into_inner(), None)
+            .await
+            .map_err(|e| tonic::Status::data_loss(format!("Block failed verification: {}", e)))?;
+
+        println!("Receiving copy of block: {}", header.block_id);
+
+        let mut storage = self.storage.lock().await;
+        storage
+            .commit(writer)
+            .await
+            .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
+        drop(storage);
+
+        let response = CopyBlockResponse {
+            bytes_received: header.block_size as i64,
         };
         Ok(tonic::Response::new(response))
     }
@@ -1,11 +1,40 @@
 use crate::block::Block;
-use crate::proto::BlockInfo;
+use crate::checksum;
+use crate::packet;
+use crate::proto::{BlockChange, BlockInfo, ReplicaInfo};
+use std::collections::HashMap;
 use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 /// Block storage for a datanode
+/// Only the metadata of each block is kept here; with a data directory, a block's data and
+/// checksums stay on disk and are streamed in and out a packet at a time
 pub struct Storage {
     /// Blocks stored in datanode
     pub blocks: Vec<Block>,
+
+    /// Directory the blocks are persisted to, or None if the storage is in memory only
+    data_dir: Option<PathBuf>,
+
+    /// Data and checksums of every block, if the storage is in memory only
+    contents: HashMap<u64, Arc<Contents>>,
+
+    /// Blocks stored or deleted since the namenode was last told about changes
+    changes: Vec<BlockChange>,
+
+    /// Number of writes started, which keeps the temporary files of concurrent writes apart
+    writes_started: u64,
+}
+
+/// Data of a block along with the CRC32C of every chunk of it, for a storage in memory only
+struct Contents {
+    data: Vec<u8>,
+    checksums: Vec<u32>,
 }
 
 impl Default for Storage {
@@ -16,75 +45,514 @@ impl Default for Storage {
 
 impl Storage {
     pub fn new() -> Self {
-        Storage { blocks: vec![] }
+        Storage {
+            blocks: vec![],
+            data_dir: None,
+            contents: HashMap::new(),
+            changes: vec![],
+            writes_started: 0,
+        }
     }
 
-    /// Takes a block name and returns the bytes stored in that block
-    pub async fn read(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
-        if !self.exists(name) {
-            return Err("Block does not exist".into());
+    /// Opens a disk-backed storage in `data_dir`, reloading the metadata of every block already
+    /// stored there and clearing out writes that never finished
+    pub fn open(data_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(data_dir)?;
+
+        let mut blocks = vec![];
+        for entry in fs::read_dir(data_dir)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("tmp")) {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if path.extension() != Some(OsStr::new("meta")) {
+                continue;
+            }
+            match Self::load_block(&path) {
+                Ok(block) => blocks.push(block),
+                Err(e) => println!("Skipping block {}: {}", path.display(), e),
+            }
         }
-        let block = self.get_block(name).unwrap();
-        let buffer = block.read();
-        Ok(buffer)
+        println!("Loaded {} blocks from {}", blocks.len(), data_dir.display());
+
+        Ok(Storage {
+            blocks,
+            data_dir: Some(data_dir.to_path_buf()),
+            contents: HashMap::new(),
+            changes: vec![],
+            writes_started: 0,
+        })
+    }
+
+    /// Opens a block for reading, as it is at the time it is opened
+    pub async fn open_block(&self, id: u64) -> Result<BlockReader, Box<dyn Error>> {
+        let block = self.get_block(id).ok_or("Block does not exist")?.clone();
+        let source = match &self.data_dir {
+            Some(data_dir) => {
+                let (data_path, meta_path) = Self::block_paths(data_dir, id);
+                let meta = tokio::fs::read_to_string(&meta_path).await?;
+                let checksums =
+                    Self::parse_checksums(&meta)?.ok_or("Metadata is missing the checksums")?;
+                let file = tokio::fs::File::open(&data_path).await?;
+                Source::File { file, checksums }
+            }
+            None => Source::Memory(self.contents.get(&id).ok_or("Block has no data")?.clone()),
+        };
+        Ok(BlockReader { block, source })
     }
 
-    /// Takes a block name and the bytes to be written and stores the block
-    pub async fn create(
+    /// Starts writing a block, which replaces any stored replica of it once it is committed
+    pub async fn begin_write(
         &mut self,
-        name: &str,
-        block_info: BlockInfo,
-    ) -> Result<(), Box<dyn Error>> {
-        if self.exists(name) {
+        block_id: u64,
+        generation_stamp: u64,
+        bytes_per_checksum: usize,
+    ) -> Result<BlockWriter, Box<dyn Error>> {
+        self.writes_started += 1;
+        let tmp_path = match &self.data_dir {
+            Some(data_dir) => {
+                let path = data_dir.join(format!("blk_{}.{}.tmp", block_id, self.writes_started));
+                Some(path)
+            }
+            None => None,
+        };
+        let file = match &tmp_path {
+            Some(tmp_path) => Some(tokio::fs::File::create(tmp_path).await?),
+            None => None,
+        };
+        Ok(BlockWriter {
+            block_id,
+            generation_stamp,
+            bytes_per_checksum,
+            length: 0,
+            checksums: vec![],
+            file,
+            data: vec![],
+            tmp_path,
+        })
+    }
+
+    /// Stores a block that has been written in full, replacing any stored replica of it
+    /// A write older than the stored replica is rejected so a late write can't roll it back
+    pub async fn commit(&mut self, mut writer: BlockWriter) -> Result<(), Box<dyn Error>> {
+        let block = writer.block();
+        if let Some(stored) = self.get_block(block.id) {
+            if block.generation_stamp < stored.generation_stamp {
+                return Err(format!(
+                    "Replica has generation stamp {} but the update has {}",
+                    stored.generation_stamp, block.generation_stamp
+                )
+                .into());
+            }
+        }
+
+        match (&self.data_dir, writer.file.take()) {
+            (Some(data_dir), Some(mut file)) => {
+                file.flush().await?;
+                file.sync_all().await?;
+                drop(file);
+                let (data_path, meta_path) = Self::block_paths(data_dir, block.id);
+                let tmp_path = writer
+                    .tmp_path
+                    .take()
+                    .ok_or("Block was already committed")?;
+                tokio::fs::rename(&tmp_path, &data_path).await?;
+                let meta = Self::format_meta(&block, &writer.checksums);
+                Self::write_atomic(&meta_path, meta.as_bytes()).await?;
+            }
+            _ => {
+                let contents = Contents {
+                    data: std::mem::take(&mut writer.data),
+                    checksums: std::mem::take(&mut writer.checksums),
+                };
+                self.contents.insert(block.id, Arc::new(contents));
+            }
+        }
+
+        self.record_change(&block, false);
+        match self.get_block_mut(block.id) {
+            Some(stored) => *stored = block,
+            None => self.blocks.push(block),
+        }
+        Ok(())
+    }
+
+    /// Stores a new block under the id in `block_info`
+    pub async fn create(&mut self, block_info: BlockInfo) -> Result<(), Box<dyn Error>> {
+        if self.exists(block_info.block_id) {
             return Err("Block already exists".into());
         }
+        self.update(block_info).await
+    }
 
-        let data_to_write = if block_info.block_data.len() > block_info.block_size as usize {
-            &block_info.block_data[..block_info.block_size as usize]
+    /// Overwrites the block with the id in `block_info`, creating it if it doesn't exist
+    /// An update older than the stored replica is rejected so a late write can't roll it back
+    pub async fn update(&mut self, block_info: BlockInfo) -> Result<(), Box<dyn Error>> {
+        let mut data = block_info.block_data;
+        data.truncate(block_info.block_size.max(0) as usize);
+        let (bytes_per_checksum, checksums) = if block_info.checksums.is_empty() {
+            let bytes_per_checksum = checksum::BYTES_PER_CHECKSUM;
+            (
+                bytes_per_checksum,
+                checksum::compute(&data, bytes_per_checksum),
+            )
         } else {
-            &block_info.block_data
+            (block_info.bytes_per_checksum as usize, block_info.checksums)
         };
 
-        let mut data = Vec::<u8>::with_capacity(block_info.block_size as usize);
-        data.extend_from_slice(data_to_write);
-        let block = Block::new(name.to_string(), data);
-        self.blocks.push(block);
+        let mut writer = self
+            .begin_write(
+                block_info.block_id,
+                block_info.generation_stamp,
+                bytes_per_checksum,
+            )
+            .await?;
+        writer.write(&data, &checksums).await?;
+        self.commit(writer).await
+    }
+
+    /// Takes a block id and deletes the block
+    pub async fn delete(&mut self, id: u64) -> Result<(), Box<dyn Error>> {
+        let block = self.get_block(id).cloned();
+        if let Some(data_dir) = &self.data_dir {
+            let (data_path, meta_path) = Self::block_paths(data_dir, id);
+            for path in [meta_path, data_path] {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        self.blocks.retain(|b| b.id != id);
+        self.contents.remove(&id);
+        if let Some(block) = block {
+            self.record_change(&block, true);
+        }
         Ok(())
     }
 
-    /// Takes a block name and the bytes to be written and updates or creates the block
-    pub async fn update(
+    /// Returns the blocks stored or deleted since the last call, in the order it happened
+    pub fn take_changes(&mut self) -> Vec<BlockChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    fn record_change(&mut self, block: &Block, deleted: bool) {
+        self.changes.push(BlockChange {
+            replica: Some(Self::replica_info(block)),
+            deleted,
+        });
+    }
+
+    /// Returns the ids of every block in storage
+    pub fn block_ids(&self) -> Vec<u64> {
+        self.blocks.iter().map(|b| b.id).collect()
+    }
+
+    /// Returns the id and generation stamp of every block in storage
+    pub fn replicas(&self) -> Vec<ReplicaInfo> {
+        self.blocks.iter().map(Self::replica_info).collect()
+    }
+
+    fn replica_info(block: &Block) -> ReplicaInfo {
+        ReplicaInfo {
+            block_id: block.id,
+            generation_stamp: block.generation_stamp,
+        }
+    }
+
+    /// Formats the metadata sidecar of a block
+    fn format_meta(block: &Block, checksums: &[u32]) -> String {
+        let checksums = checksums
+            .iter()
+            .map(|c| format!("{:08x}", c))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "id={}\ngeneration_stamp={}\nlength={}\nbytes_per_checksum={}\nchecksums={}\n",
+            block.id,
+            block.generation_stamp,
+            block.length(),
+            block.bytes_per_checksum(),
+            checksums
+        )
+    }
+
+    /// Parses a metadata sidecar into the block it describes
+    fn parse_meta(meta: &str) -> Result<Block, Box<dyn Error>> {
+        let mut id = None;
+        let mut generation_stamp = 0;
+        let mut length = None;
+        let mut bytes_per_checksum = checksum::BYTES_PER_CHECKSUM;
+        for line in meta.lines() {
+            match line.split_once('=') {
+                Some(("id", value)) => id = Some(value.parse::<u64>()?),
+                Some(("generation_stamp", value)) => generation_stamp = value.parse()?,
+                Some(("length", value)) => length = Some(value.parse::<usize>()?),
+                Some(("bytes_per_checksum", value)) => bytes_per_checksum = value.parse()?,
+                _ => {}
+            }
+        }
+        let id = id.ok_or("Metadata is missing the block id")?;
+        let length = length.ok_or("Metadata is missing the block length")?;
+        Ok(Block::new(id, generation_stamp, length, bytes_per_checksum))
+    }
+
+    /// Parses the checksums out of a metadata sidecar, if it has them
+    fn parse_checksums(meta: &str) -> Result<Option<Vec<u32>>, Box<dyn Error>> {
+        let value = match meta
+            .lines()
+            .find_map(|line| line.strip_prefix("checksums="))
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let checksums = value
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| u32::from_str_radix(c, 16))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(checksums))
+    }
+
+    /// Writes `contents` to `path` by way of a temporary file and a rename
+    async fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, contents).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+
+    /// Reads the metadata of a block back from its sidecar, checking its data file is all there
+    fn load_block(meta_path: &Path) -> Result<Block, Box<dyn Error>> {
+        let meta = fs::read_to_string(meta_path)?;
+        let block = Self::parse_meta(&meta)?;
+        let data_path = meta_path.with_extension("data");
+        let length = fs::metadata(&data_path)?.len() as usize;
+        if length != block.length() {
+            return Err(format!("Expected {} bytes but found {}", block.length(), length).into());
+        }
+        // blocks written before checksums were stored are trusted as they are
+        if Self::parse_checksums(&meta)?.is_none() {
+            let checksums = checksum::compute(&fs::read(&data_path)?, block.bytes_per_checksum());
+            let meta = Self::format_meta(&block, &checksums);
+            fs::write(meta_path, meta)?;
+        }
+        Ok(block)
+    }
+
+    /// Returns the paths of the data file and metadata sidecar for a block
+    fn block_paths(data_dir: &Path, id: u64) -> (PathBuf, PathBuf) {
+        (
+            data_dir.join(format!("blk_{}.data", id)),
+            data_dir.join(format!("blk_{}.meta", id)),
+        )
+    }
+
+    /// Returns a mutable references to blocks with a given id
+    fn get_block_mut(&mut self, id: u64) -> Option<&mut Block> {
+        self.blocks.iter_mut().find(|b| b.id == id)
+    }
+
+    /// Returns a references to blocks with a given id
+    pub fn get_block(&self, id: u64) -> Option<&Block> {
+        self.blocks.iter().find(|b| b.id == id)
+    }
+
+    /// Returns true if a block with a given id exists
+    pub fn exists(&self, id: u64) -> bool {
+        self.blocks.iter().any(|b| b.id == id)
+    }
+}
+
+/// Where a block being read comes from
+enum Source {
+    /// The block's data file, which stays readable even if the block is rewritten or deleted
+    File {
+        file: tokio::fs::File,
+        checksums: Vec<u32>,
+    },
+    Memory(Arc<Contents>),
+}
+
+/// Reads ranges of a block as it was when it was opened, checking each against the stored
+/// checksums, without holding on to the storage
+pub struct BlockReader {
+    block: Block,
+    source: Source,
+}
+
+impl BlockReader {
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Returns `length` bytes of the block from `offset`, cut short at the end of the block, along
+    /// with the CRC32C of every chunk of them
+    /// Only the stored chunks the range covers are read, and they are checked against their
+    /// checksums first, so a replica that has gone bad is never handed out
+    pub async fn read_range(
         &mut self,
-        name: &str,
-        block_info: BlockInfo,
-    ) -> Result<(), Box<dyn Error>> {
-        if !self.exists(name) {
-            self.create(name, block_info).await?;
-        } else {
-            let block = self.get_block_mut(name).unwrap();
-            block.write(block_info);
+        offset: usize,
+        length: usize,
+    ) -> Result<(Vec<u8>, Vec<u32>), Box<dyn Error>> {
+        let (bytes, chunks) = self.block.chunks_covering(offset, length)?;
+        match &mut self.source {
+            Source::File { file, checksums } => {
+                let mut covered = vec![0; bytes.len()];
+                file.seek(SeekFrom::Start(bytes.start as u64)).await?;
+                file.read_exact(&mut covered).await?;
+                let stored_checksums = checksums.get(chunks).ok_or("Block is missing checksums")?;
+                self.block
+                    .cut_range(offset, length, &covered, stored_checksums)
+            }
+            Source::Memory(contents) => {
+                let covered = contents
+                    .data
+                    .get(bytes)
+                    .ok_or("Block is shorter than its metadata")?;
+                let stored_checksums = contents
+                    .checksums
+                    .get(chunks)
+                    .ok_or("Block is missing checksums")?;
+                self.block
+                    .cut_range(offset, length, covered, stored_checksums)
+            }
+        }
+    }
+
+    /// Checks the whole block against its checksums, a packet's worth at a time
+    pub async fn verify(&mut self) -> Result<(), Box<dyn Error>> {
+        let step = packet::packet_size(self.block.bytes_per_checksum());
+        let mut offset = 0;
+        while offset < self.block.length() {
+            self.read_range(offset, step).await?;
+            offset += step;
         }
         Ok(())
     }
+}
 
-    /// Takes a block name and deletes the block
-    pub async fn delete(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
-        self.blocks.retain(|b| b.name != name);
+/// A block being written a packet at a time, which only replaces the stored replica once it is
+/// committed
+/// With a data directory the data goes straight to a temporary file, which is removed if the
+/// writer is dropped before it is committed
+pub struct BlockWriter {
+    block_id: u64,
+    generation_stamp: u64,
+    bytes_per_checksum: usize,
+    /// Bytes written so far
+    length: usize,
+    checksums: Vec<u32>,
+    file: Option<tokio::fs::File>,
+    /// Data written so far, if the storage is in memory only
+    data: Vec<u8>,
+    /// Temporary file holding the data, until it is committed
+    tmp_path: Option<PathBuf>,
+}
+
+impl BlockWriter {
+    /// Appends data to the block, along with the checksums of its chunks
+    pub async fn write(&mut self, data: &[u8], checksums: &[u32]) -> Result<(), Box<dyn Error>> {
+        match &mut self.file {
+            Some(file) => file.write_all(data).await?,
+            None => self.data.extend_from_slice(data),
+        }
+        self.checksums.extend_from_slice(checksums);
+        self.length += data.len();
         Ok(())
     }
 
-    /// Returns a mutable references to blocks with a given name
-    fn get_block_mut(&mut self, name: &str) -> Option<&mut Block> {
-        self.blocks.iter_mut().find(|b| b.name == name)
+    /// Returns the block written so far
+    fn block(&self) -> Block {
+        Block::new(
+            self.block_id,
+            self.generation_stamp,
+            self.length,
+            self.bytes_per_checksum,
+        )
     }
+}
 
-    /// Returns a references to blocks with a given name
-    fn get_block(&self, name: &str) -> Option<&Block> {
-        self.blocks.iter().find(|b| b.name == name)
+impl Drop for BlockWriter {
+    fn drop(&mut self) {
+        if let Some(tmp_path) = self.tmp_path.take() {
+            let _ = fs::remove_file(tmp_path);
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_info(block_id: u64, generation_stamp: u64, data: &[u8]) -> BlockInfo {
+        BlockInfo {
+            block_size: data.len() as i64,
+            block_id,
+            block_data: data.to_vec(),
+            checksums: checksum::compute(data, checksum::BYTES_PER_CHECKSUM),
+            bytes_per_checksum: checksum::BYTES_PER_CHECKSUM as i64,
+            generation_stamp,
+        }
+    }
+
+    /// Reads the whole of a block
+    async fn read(storage: &Storage, id: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut reader = storage.open_block(id).await?;
+        let length = reader.block().length();
+        Ok(reader.read_range(0, length).await?.0)
+    }
+
+    #[tokio::test]
+    async fn test_blocks_survive_reopen() {
+        let data_dir = std::env::temp_dir().join(format!("kldfs_storage_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+
+        let mut storage = Storage::open(&data_dir).unwrap();
+        storage.create(block_info(1, 1, b"abcd")).await.unwrap();
+        storage.create(block_info(2, 1, b"ef")).await.unwrap();
+        storage.update(block_info(2, 2, b"gh")).await.unwrap();
+        // a write older than the stored replica is rejected
+        assert!(storage.update(block_info(2, 1, b"xy")).await.is_err());
+        storage.create(block_info(3, 1, b"ij")).await.unwrap();
+        storage.delete(3).await.unwrap();
+        // a write that is never committed leaves nothing behind
+        let mut writer = storage.begin_write(4, 1, 512).await.unwrap();
+        writer
+            .write(b"kl", &checksum::compute(b"kl", 512))
+            .await
+            .unwrap();
+        drop(writer);
+        drop(storage);
+
+        let storage = Storage::open(&data_dir).unwrap();
+        assert_eq!(storage.blocks.len(), 2);
+        assert_eq!(read(&storage, 1).await.unwrap(), b"abcd");
+        assert_eq!(read(&storage, 2).await.unwrap(), b"gh");
+        assert!(read(&storage, 3).await.is_err());
+        assert!(read(&storage, 4).await.is_err());
+        assert_eq!(storage.get_block(2).unwrap().generation_stamp, 2);
+        assert!(storage.open_block(2).await.unwrap().verify().await.is_ok());
+        assert_eq!(fs::read_dir(&data_dir).unwrap().count(), 4);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_detects_corruption_on_disk() {
+        let data_dir = std::env::temp_dir().join(format!("kldfs_scan_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+
+        let mut storage = Storage::open(&data_dir).unwrap();
+        storage.create(block_info(1, 1, b"abcd")).await.unwrap();
+        assert!(storage.open_block(1).await.unwrap().verify().await.is_ok());
+
+        let (data_path, _) = Storage::block_paths(&data_dir, 1);
+        fs::write(&data_path, b"abcx").unwrap();
+        assert!(storage.open_block(1).await.unwrap().verify().await.is_err());
 
-    /// Returns true if a block with a given name exists
-    fn exists(&self, name: &str) -> bool {
-        self.blocks.iter().any(|b| b.name == name)
+        fs::remove_dir_all(&data_dir).unwrap();
     }
 }
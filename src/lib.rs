@@ -0,0 +1,12 @@
+pub mod block;
+pub mod checksum;
+pub mod packet;
+
+pub mod client;
+pub mod datanode;
+pub mod namenode;
+pub use client::{DfsClient, DfsError, FileReader, FileWriter};
+
+pub mod proto {
+    tonic::include_proto!("network_comms");
+}
@@ -1,16 +1,42 @@
 use clap::{Parser, Subcommand};
-pub mod block;
+use kldfs::client::{Client, UsageError};
+use kldfs::datanode::DataNodeServer;
+use kldfs::namenode::NameNodeServer;
+use kldfs::DfsError;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
-pub mod datanode;
-use datanode::DataNodeServer;
-pub mod client;
-use client::Client;
-pub mod namenode;
-use namenode::NameNodeServer;
+/// Seconds without a heartbeat before a datanode is considered stale
+const DEFAULT_STALE_TIMEOUT: u64 = 15;
 
-pub mod proto {
-    tonic::include_proto!("network_comms");
-}
+/// Seconds without a heartbeat before a datanode is considered dead
+const DEFAULT_DEAD_TIMEOUT: u64 = 60;
+
+/// Seconds a client can go without renewing its lease before the namenode recovers its files
+const DEFAULT_LEASE_HARD_LIMIT: u64 = 60 * 60;
+
+/// Size in bytes of every block of a new file but the last
+const DEFAULT_BLOCK_SIZE: usize = 4;
+
+/// Exit status of a command that failed for any reason but those below
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit status of a command that doesn't exist or was given the wrong arguments
+const EXIT_USAGE: i32 = 2;
+
+/// Exit status of a command on a path that doesn't exist
+const EXIT_NOT_FOUND: i32 = 3;
+
+/// Exit status of a command creating something at a path that is already taken
+const EXIT_ALREADY_EXISTS: i32 = 4;
+
+/// Exit status of a command the user doesn't have permission to run
+const EXIT_PERMISSION_DENIED: i32 = 5;
+
+/// Exit status of a command that couldn't reach the namenode or a datanode
+const EXIT_UNAVAILABLE: i32 = 6;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about= None)]
@@ -21,67 +47,346 @@ struct Args {
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    Datanode { port: u16 },
-    Namenode {},
-    Client {},
+    Datanode {
+        port: u16,
+        /// Directory to persist blocks in; blocks are kept in memory if omitted
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+    },
+    Namenode {
+        /// Seconds without a heartbeat before a datanode is considered stale
+        #[arg(long, default_value_t = DEFAULT_STALE_TIMEOUT)]
+        stale_timeout: u64,
+        /// Seconds without a heartbeat before a datanode is considered dead
+        #[arg(long, default_value_t = DEFAULT_DEAD_TIMEOUT)]
+        dead_timeout: u64,
+        /// Seconds a client can go without renewing its lease before the files it is writing
+        /// are recovered
+        #[arg(long, default_value_t = DEFAULT_LEASE_HARD_LIMIT)]
+        lease_hard_limit: u64,
+        /// Size in bytes of the blocks new files are split into
+        #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE, value_parser = parse_block_size)]
+        block_size: usize,
+        /// Directory to persist the namespace in; it is kept in memory if omitted
+        #[arg(long)]
+        name_dir: Option<PathBuf>,
+        /// File in the format of `/etc/group` to look up users' groups in
+        #[arg(long)]
+        groups_file: Option<PathBuf>,
+    },
+    Client {
+        /// User to act as; defaults to the user running the client
+        #[arg(long)]
+        user: Option<String>,
+        /// Shell commands to run, separated by `;`, instead of starting the shell
+        #[arg(short = 'c', conflicts_with = "script")]
+        commands: Option<String>,
+        /// File of shell commands to run, one per line, instead of starting the shell
+        script: Option<PathBuf>,
+    },
+    /// Runs a single command on the filesystem, exiting with a status saying whether and why it
+    /// failed
+    Fs {
+        /// User to act as; defaults to the user running the client
+        #[arg(long)]
+        user: Option<String>,
+        #[command(subcommand)]
+        command: FsCommand,
+    },
     Demo {},
 }
 
+#[derive(Subcommand, Debug)]
+enum FsCommand {
+    /// Uploads a local file
+    Put {
+        local_path: String,
+        path: String,
+        /// Replace the file if it already exists
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Downloads a file, to a local file of the same name unless given another path
+    Get {
+        path: String,
+        local_path: Option<String>,
+    },
+    /// Prints a file, or `length` bytes of it from `offset`
+    Cat {
+        path: String,
+        #[arg(long)]
+        offset: Option<u64>,
+        #[arg(long)]
+        length: Option<u64>,
+    },
+    /// Lists a directory
+    Ls {
+        #[arg(default_value = "/")]
+        path: String,
+    },
+    /// Deletes a file or empty directory
+    Rm {
+        path: String,
+        /// Delete a directory and everything under it
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Creates a directory
+    Mkdir {
+        path: String,
+        /// Create any missing parents, and succeed if the directory exists
+        #[arg(short, long)]
+        parents: bool,
+    },
+    /// Moves a file or directory
+    Mv {
+        source: String,
+        destination: String,
+        /// Replace a file or empty directory at the destination
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Shows the metadata of a file or directory
+    Stat { path: String },
+    /// Shows the total size of each file and directory in a directory
+    Du {
+        #[arg(default_value = "/")]
+        path: String,
+        /// Only show the total size of the path itself
+        #[arg(short, long)]
+        summarize: bool,
+    },
+}
+
+impl FsCommand {
+    /// Returns the shell command that does the same, as its name followed by its arguments
+    fn shell_command(&self) -> Vec<String> {
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        match self {
+            FsCommand::Put {
+                local_path,
+                path,
+                force: false,
+            } => words(&["create", local_path, path]),
+            FsCommand::Put {
+                local_path,
+                path,
+                force: true,
+            } => words(&["update", local_path, path]),
+            FsCommand::Get {
+                path,
+                local_path: None,
+            } => words(&["get", path]),
+            FsCommand::Get {
+                path,
+                local_path: Some(local_path),
+            } => words(&["get", path, local_path]),
+            FsCommand::Cat {
+                path,
+                offset,
+                length,
+            } => {
+                let mut args = words(&["cat", path]);
+                if let Some(offset) = offset {
+                    args.extend(["--offset".to_string(), offset.to_string()]);
+                }
+                if let Some(length) = length {
+                    args.extend(["--length".to_string(), length.to_string()]);
+                }
+                args
+            }
+            FsCommand::Ls { path } => words(&["ls", path]),
+            FsCommand::Rm {
+                path,
+                recursive: false,
+            } => words(&["delete", path]),
+            FsCommand::Rm {
+                path,
+                recursive: true,
+            } => words(&["delete", "-r", path]),
+            FsCommand::Mkdir {
+                path,
+                parents: false,
+            } => words(&["mkdir", path]),
+            FsCommand::Mkdir {
+                path,
+                parents: true,
+            } => words(&["mkdir", "-p", path]),
+            FsCommand::Mv {
+                source,
+                destination,
+                force: false,
+            } => words(&["mv", source, destination]),
+            FsCommand::Mv {
+                source,
+                destination,
+                force: true,
+            } => words(&["mv", "-f", source, destination]),
+            FsCommand::Stat { path } => words(&["stat", path]),
+            FsCommand::Du {
+                path,
+                summarize: false,
+            } => words(&["du", path]),
+            FsCommand::Du {
+                path,
+                summarize: true,
+            } => words(&["du", "-s", path]),
+        }
+    }
+}
+
+/// Parses a block size, which must be at least one byte
+fn parse_block_size(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(0) => Err("block size must be at least 1".to_string()),
+        Ok(block_size) => Ok(block_size),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Returns the user to act as, which is the user running the client unless one is given
+fn user_or_current(user: Option<String>) -> String {
+    user.or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "root".to_string())
+}
+
+/// Prints why a command failed and exits with the status for the reason
+fn exit_with(err: Box<dyn Error>) -> ! {
+    if err.is::<UsageError>() {
+        eprintln!("{}", err);
+        process::exit(EXIT_USAGE);
+    }
+    let err = DfsError::from(err);
+    eprintln!("Error: {}", err);
+    process::exit(match err {
+        DfsError::NotFound(_) => EXIT_NOT_FOUND,
+        DfsError::AlreadyExists(_) => EXIT_ALREADY_EXISTS,
+        DfsError::PermissionDenied(_) => EXIT_PERMISSION_DENIED,
+        DfsError::Unavailable(_) => EXIT_UNAVAILABLE,
+        DfsError::Other(_) => EXIT_FAILURE,
+    })
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
     let namenode_port = 3000;
     let replication_factor = 3;
-    let block_size = 4;
 
     match args.command {
-        Command::Datanode { port } => {
-            let dataserver = DataNodeServer::new(port, namenode_port);
+        Command::Datanode { port, data_dir } => {
+            let dataserver = DataNodeServer::new(port, namenode_port, data_dir)
+                .expect("Failed to open datanode storage");
             let _ = dataserver.run_dataserver().await;
         }
 
-        Command::Namenode {} => {
-            let nameserver = NameNodeServer::new(namenode_port, replication_factor, block_size);
+        Command::Namenode {
+            stale_timeout,
+            dead_timeout,
+            lease_hard_limit,
+            block_size,
+            name_dir,
+            groups_file,
+        } => {
+            let mut nameserver = NameNodeServer::new(
+                namenode_port,
+                replication_factor,
+                block_size,
+                Duration::from_secs(stale_timeout),
+                Duration::from_secs(dead_timeout),
+                Duration::from_secs(lease_hard_limit),
+                name_dir,
+            )
+            .expect("Failed to recover namenode records");
+            if let Some(groups_file) = groups_file {
+                nameserver
+                    .load_groups(&groups_file)
+                    .expect("Failed to read groups file");
+            }
             let _ = nameserver.run_nameserver().await;
         }
 
-        Command::Client {} => {
-            let mut client = Client::new(namenode_port, block_size)
-                .await
-                .expect("Client failed");
-            match client.run_client().await {
-                Ok(_) => println!("Client ran successfully"),
-                Err(err) => println!("Client Error: {}", err),
+        Command::Client {
+            user,
+            commands,
+            script,
+        } => {
+            let user = user_or_current(user);
+            let script = match (commands, script) {
+                (Some(commands), _) => Some(commands),
+                (None, Some(script)) => match std::fs::read_to_string(&script) {
+                    Ok(script) => Some(script),
+                    Err(err) => exit_with(err.into()),
+                },
+                (None, None) => None,
+            };
+            let mut client = match Client::new(namenode_port, &user).await {
+                Ok(client) => client,
+                Err(err) if script.is_some() => exit_with(err),
+                Err(err) => panic!("Client failed: {}", err),
+            };
+            match script {
+                Some(script) => {
+                    if let Err(err) = client.run_script(&script).await {
+                        exit_with(err);
+                    }
+                }
+                None => match client.run_client().await {
+                    Ok(_) => println!("Client ran successfully"),
+                    Err(err) => println!("Client Error: {}", err),
+                },
+            }
+        }
+
+        Command::Fs { user, command } => {
+            let user = user_or_current(user);
+            let mut client = match Client::new(namenode_port, &user).await {
+                Ok(client) => client,
+                Err(err) => exit_with(err),
+            };
+            let args = command.shell_command();
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            if let Err(err) = client.run_command(&args).await {
+                exit_with(err);
             }
         }
 
         Command::Demo {} => {
             tokio::spawn(async move {
-                let dataserver = DataNodeServer::new(8080, 3000);
+                let dataserver = DataNodeServer::new(8080, 3000, None).unwrap();
                 let _ = dataserver.run_dataserver().await;
             });
 
             tokio::spawn(async move {
-                let dataserver = DataNodeServer::new(8081, 3000);
+                let dataserver = DataNodeServer::new(8081, 3000, None).unwrap();
                 let _ = dataserver.run_dataserver().await;
             });
 
             tokio::spawn(async move {
-                let dataserver = DataNodeServer::new(8082, 3000);
+                let dataserver = DataNodeServer::new(8082, 3000, None).unwrap();
                 let _ = dataserver.run_dataserver().await;
             });
 
             tokio::spawn(async move {
-                let dataserver = DataNodeServer::new(8083, 3000);
+                let dataserver = DataNodeServer::new(8083, 3000, None).unwrap();
                 let _ = dataserver.run_dataserver().await;
             });
 
             tokio::spawn(async move {
-                let dataserver = DataNodeServer::new(8084, 3000);
+                let dataserver = DataNodeServer::new(8084, 3000, None).unwrap();
                 let _ = dataserver.run_dataserver().await;
             });
 
-            let nameserver = NameNodeServer::new(3000, replication_factor, block_size);
+            let nameserver = NameNodeServer::new(
+                3000,
+                replication_factor,
+                DEFAULT_BLOCK_SIZE,
+                Duration::from_secs(DEFAULT_STALE_TIMEOUT),
+                Duration::from_secs(DEFAULT_DEAD_TIMEOUT),
+                Duration::from_secs(DEFAULT_LEASE_HARD_LIMIT),
+                None,
+            )
+            .unwrap();
             let _ = nameserver.run_nameserver().await;
         }
     }
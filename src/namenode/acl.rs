@@ -0,0 +1,417 @@
+use crate::namenode::namespace::Attributes;
+use crate::proto;
+use std::error::Error;
+
+/// Whether an ACL entry is checked on access or inherited by new children of a directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclScope {
+    Access,
+    Default,
+}
+
+/// Who an ACL entry applies to, in the order getfacl shows them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclEntryType {
+    User,
+    Group,
+    Mask,
+    Other,
+}
+
+/// An entry of an access control list
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclEntry {
+    pub scope: AclScope,
+    pub kind: AclEntryType,
+    /// User or group the entry is for, or empty for the owner, the owning group, the mask and
+    /// others
+    pub name: String,
+    /// Read, write and execute bits
+    pub permissions: u32,
+}
+
+impl AclEntry {
+    pub fn new(scope: AclScope, kind: AclEntryType, name: &str, permissions: u32) -> Self {
+        Self {
+            scope,
+            kind,
+            name: name.to_string(),
+            permissions,
+        }
+    }
+
+    /// Returns what identifies the entry within an ACL, which is everything but its permissions
+    fn key(&self) -> (AclScope, AclEntryType, &str) {
+        (self.scope, self.kind, &self.name)
+    }
+
+    fn is_named(&self) -> bool {
+        !self.name.is_empty()
+    }
+}
+
+impl From<&proto::AclEntry> for AclEntry {
+    fn from(val: &proto::AclEntry) -> Self {
+        let scope = match val.scope() {
+            proto::AclScope::Access => AclScope::Access,
+            proto::AclScope::Default => AclScope::Default,
+        };
+        let kind = match val.r#type() {
+            proto::AclEntryType::User => AclEntryType::User,
+            proto::AclEntryType::Group => AclEntryType::Group,
+            proto::AclEntryType::Mask => AclEntryType::Mask,
+            proto::AclEntryType::Other => AclEntryType::Other,
+        };
+        AclEntry::new(scope, kind, &val.name, val.permissions)
+    }
+}
+
+impl From<&AclEntry> for proto::AclEntry {
+    fn from(val: &AclEntry) -> Self {
+        let scope = match val.scope {
+            AclScope::Access => proto::AclScope::Access,
+            AclScope::Default => proto::AclScope::Default,
+        };
+        let kind = match val.kind {
+            AclEntryType::User => proto::AclEntryType::User,
+            AclEntryType::Group => proto::AclEntryType::Group,
+            AclEntryType::Mask => proto::AclEntryType::Mask,
+            AclEntryType::Other => proto::AclEntryType::Other,
+        };
+        proto::AclEntry {
+            scope: scope.into(),
+            r#type: kind.into(),
+            name: val.name.clone(),
+            permissions: val.permissions,
+        }
+    }
+}
+
+/// A change to the ACL of a file or directory
+#[derive(Clone, Debug, PartialEq)]
+pub enum AclChange {
+    /// Adds entries, replacing any existing entries for the same users and groups
+    Modify(Vec<AclEntry>),
+    /// Removes the entries for the same users and groups as the entries given
+    Remove(Vec<AclEntry>),
+    /// Removes every entry but the ones the permission bits stand for
+    RemoveAll,
+    /// Removes the default ACL
+    RemoveDefault,
+    /// Replaces the whole ACL
+    Replace(Vec<AclEntry>),
+}
+
+/// Returns every entry of the ACL of a file or directory, including the ones its permission bits
+/// stand for, in the order getfacl shows them
+pub fn full_acl(attributes: &Attributes) -> Vec<AclEntry> {
+    let mode = attributes.mode;
+    let (access, default): (Vec<&AclEntry>, Vec<&AclEntry>) = attributes
+        .acl
+        .iter()
+        .partition(|entry| entry.scope == AclScope::Access);
+
+    let mut entries = vec![
+        AclEntry::new(AclScope::Access, AclEntryType::User, "", (mode >> 6) & 0o7),
+        AclEntry::new(AclScope::Access, AclEntryType::Other, "", mode & 0o7),
+    ];
+    if access.is_empty() {
+        let group = AclEntry::new(AclScope::Access, AclEntryType::Group, "", (mode >> 3) & 0o7);
+        entries.push(group);
+    } else {
+        entries.extend(access.into_iter().cloned());
+        let mask = AclEntry::new(AclScope::Access, AclEntryType::Mask, "", (mode >> 3) & 0o7);
+        entries.push(mask);
+    }
+    entries.extend(default.into_iter().cloned());
+    entries.sort_by(|a, b| a.key().cmp(&b.key()));
+    entries
+}
+
+/// Applies a change to the ACL of a file or directory, returning its new permission bits and
+/// the entries stored alongside them
+/// Unless the change sets a mask, the mask is recalculated to grant everything the named and
+/// owning group entries do
+pub fn apply(
+    attributes: &Attributes,
+    is_directory: bool,
+    change: &AclChange,
+) -> Result<(u32, Vec<AclEntry>), Box<dyn Error>> {
+    let mut entries = full_acl(attributes);
+    let mut given_masks = vec![];
+    match change {
+        AclChange::Modify(spec) => {
+            for entry in spec {
+                entries.retain(|existing| existing.key() != entry.key());
+                entries.push(entry.clone());
+                if entry.kind == AclEntryType::Mask {
+                    given_masks.push(entry.scope);
+                }
+            }
+        }
+        AclChange::Remove(spec) => {
+            for entry in spec {
+                if !entry.is_named() && entry.kind != AclEntryType::Mask {
+                    return Err("Can't remove the owner, owning group or other entries".into());
+                }
+                entries.retain(|existing| existing.key() != entry.key());
+            }
+        }
+        AclChange::RemoveAll => {
+            // the owning group keeps its own permissions, rather than the mask's
+            entries.retain(|entry| {
+                entry.scope == AclScope::Access
+                    && !entry.is_named()
+                    && entry.kind != AclEntryType::Mask
+            });
+        }
+        AclChange::RemoveDefault => {
+            entries.retain(|entry| entry.scope == AclScope::Access);
+        }
+        AclChange::Replace(spec) => {
+            entries = spec.clone();
+            given_masks = spec
+                .iter()
+                .filter(|entry| entry.kind == AclEntryType::Mask)
+                .map(|entry| entry.scope)
+                .collect();
+        }
+    }
+
+    // a default ACL needs base entries too, which are copied from the access ACL if missing
+    if entries.iter().any(|entry| entry.scope == AclScope::Default) {
+        for kind in [AclEntryType::User, AclEntryType::Group, AclEntryType::Other] {
+            let key = (AclScope::Default, kind, "");
+            if entries.iter().all(|entry| entry.key() != key) {
+                let access = entries
+                    .iter()
+                    .find(|entry| entry.key() == (AclScope::Access, kind, ""))
+                    .ok_or("ACL is missing an owner, owning group or other entry")?;
+                entries.push(AclEntry::new(
+                    AclScope::Default,
+                    kind,
+                    "",
+                    access.permissions,
+                ));
+            }
+        }
+    }
+
+    for scope in [AclScope::Access, AclScope::Default] {
+        if !given_masks.contains(&scope) {
+            calculate_mask(&mut entries, scope);
+        }
+    }
+    entries.sort_by(|a, b| a.key().cmp(&b.key()));
+    validate(&entries, is_directory)?;
+    Ok(split(attributes.mode, entries))
+}
+
+/// Sets the mask of one scope of an ACL to everything its group class entries grant, or removes
+/// it if the scope has no named entries
+fn calculate_mask(entries: &mut Vec<AclEntry>, scope: AclScope) {
+    let in_scope = |entry: &&AclEntry| entry.scope == scope;
+    let has_named = entries
+        .iter()
+        .filter(in_scope)
+        .any(|entry| entry.is_named());
+    let permissions = entries
+        .iter()
+        .filter(in_scope)
+        .filter(|entry| entry.is_named() || entry.kind == AclEntryType::Group)
+        .fold(0, |permissions, entry| permissions | entry.permissions);
+
+    entries.retain(|entry| entry.key() != (scope, AclEntryType::Mask, ""));
+    if has_named {
+        entries.push(AclEntry::new(scope, AclEntryType::Mask, "", permissions));
+    }
+}
+
+/// Checks that an ACL is well formed and has all the entries it needs
+fn validate(entries: &[AclEntry], is_directory: bool) -> Result<(), Box<dyn Error>> {
+    for (i, entry) in entries.iter().enumerate() {
+        if entries[..i].iter().any(|prev| prev.key() == entry.key()) {
+            return Err("ACL has duplicate entries".into());
+        }
+        if entry.is_named() && matches!(entry.kind, AclEntryType::Mask | AclEntryType::Other) {
+            return Err("Mask and other entries can't be named".into());
+        }
+        if entry.permissions > 0o7 {
+            return Err("Invalid ACL entry permissions".into());
+        }
+        if entry.scope == AclScope::Default && !is_directory {
+            return Err("Only directories can have a default ACL".into());
+        }
+    }
+    for scope in [AclScope::Access, AclScope::Default] {
+        let in_scope: Vec<&AclEntry> = entries.iter().filter(|e| e.scope == scope).collect();
+        if scope == AclScope::Default && in_scope.is_empty() {
+            continue;
+        }
+        for kind in [AclEntryType::User, AclEntryType::Group, AclEntryType::Other] {
+            if !in_scope
+                .iter()
+                .any(|entry| entry.key() == (scope, kind, ""))
+            {
+                return Err("ACL is missing an owner, owning group or other entry".into());
+            }
+        }
+        let has_named = in_scope.iter().any(|entry| entry.is_named());
+        let has_mask = in_scope
+            .iter()
+            .any(|entry| entry.kind == AclEntryType::Mask);
+        if has_named && !has_mask {
+            return Err("ACL with named entries needs a mask".into());
+        }
+    }
+    Ok(())
+}
+
+/// Splits a full, valid ACL into the permission bits and the entries stored alongside them
+fn split(mode: u32, entries: Vec<AclEntry>) -> (u32, Vec<AclEntry>) {
+    let permissions = |kind: AclEntryType| {
+        entries
+            .iter()
+            .find(|entry| entry.key() == (AclScope::Access, kind, ""))
+            .map_or(0, |entry| entry.permissions)
+    };
+    let has_named = entries
+        .iter()
+        .any(|entry| entry.scope == AclScope::Access && entry.is_named());
+    let group_class = if has_named {
+        permissions(AclEntryType::Mask)
+    } else {
+        permissions(AclEntryType::Group)
+    };
+    let mode = (mode & !0o777)
+        | (permissions(AclEntryType::User) << 6)
+        | (group_class << 3)
+        | permissions(AclEntryType::Other);
+
+    let stored = entries
+        .into_iter()
+        .filter(|entry| match entry.scope {
+            AclScope::Access => {
+                has_named && (entry.is_named() || entry.kind == AclEntryType::Group)
+            }
+            AclScope::Default => true,
+        })
+        .collect();
+    (mode, stored)
+}
+
+/// Returns the permission bits and ACL of a new child of a directory with a default ACL, created
+/// with `mode`, or None if the directory has no default ACL
+/// The child's access ACL is the default ACL limited by `mode`, and a new directory also takes
+/// the default ACL as its own
+pub fn inherit(parent: &Attributes, mode: u32, is_directory: bool) -> Option<(u32, Vec<AclEntry>)> {
+    let default: Vec<&AclEntry> = parent
+        .acl
+        .iter()
+        .filter(|entry| entry.scope == AclScope::Default)
+        .collect();
+    if default.is_empty() {
+        return None;
+    }
+    let has_mask = default.iter().any(|entry| entry.kind == AclEntryType::Mask);
+
+    let mut entries = vec![];
+    for entry in default {
+        let mut access = AclEntry {
+            scope: AclScope::Access,
+            ..entry.clone()
+        };
+        access.permissions &= match (entry.kind, entry.is_named()) {
+            (AclEntryType::User, false) => mode >> 6,
+            (AclEntryType::Group, false) if !has_mask => mode >> 3,
+            (AclEntryType::Mask, _) => mode >> 3,
+            (AclEntryType::Other, _) => mode,
+            _ => 0o7,
+        };
+        entries.push(access);
+        if is_directory {
+            entries.push(entry.clone());
+        }
+    }
+    entries.sort_by(|a, b| a.key().cmp(&b.key()));
+    Some(split(mode, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(scope: AclScope, kind: AclEntryType, name: &str, permissions: u32) -> AclEntry {
+        AclEntry::new(scope, kind, name, permissions)
+    }
+
+    #[test]
+    fn test_modify_and_remove() {
+        let attributes = Attributes {
+            mode: 0o640,
+            ..Default::default()
+        };
+        let bob = entry(AclScope::Access, AclEntryType::User, "bob", 0o7);
+
+        // a named entry turns the group bits into a mask covering it
+        let change = AclChange::Modify(vec![bob.clone()]);
+        let (mode, acl) = apply(&attributes, false, &change).unwrap();
+        assert_eq!(mode, 0o670);
+        let modified = Attributes {
+            mode,
+            acl,
+            ..Default::default()
+        };
+        assert_eq!(
+            full_acl(&modified),
+            vec![
+                entry(AclScope::Access, AclEntryType::User, "", 0o6),
+                bob.clone(),
+                entry(AclScope::Access, AclEntryType::Group, "", 0o4),
+                entry(AclScope::Access, AclEntryType::Mask, "", 0o7),
+                entry(AclScope::Access, AclEntryType::Other, "", 0o0),
+            ]
+        );
+
+        // removing it gives the owning group its own permissions back
+        let (mode, acl) = apply(&modified, false, &AclChange::Remove(vec![bob])).unwrap();
+        assert_eq!((mode, acl), (0o640, vec![]));
+        let (mode, acl) = apply(&modified, false, &AclChange::RemoveAll).unwrap();
+        assert_eq!((mode, acl), (0o640, vec![]));
+
+        let other = entry(AclScope::Access, AclEntryType::Other, "", 0o0);
+        assert!(apply(&modified, false, &AclChange::Remove(vec![other])).is_err());
+        let default = entry(AclScope::Default, AclEntryType::User, "bob", 0o7);
+        assert!(apply(&attributes, false, &AclChange::Modify(vec![default])).is_err());
+    }
+
+    #[test]
+    fn test_inherit() {
+        let parent = Attributes {
+            mode: 0o755,
+            ..Default::default()
+        };
+        assert_eq!(inherit(&parent, 0o666, false), None);
+
+        let default = entry(AclScope::Default, AclEntryType::Group, "staff", 0o7);
+        let change = AclChange::Modify(vec![default.clone()]);
+        let (mode, acl) = apply(&parent, true, &change).unwrap();
+        let parent = Attributes {
+            mode,
+            acl,
+            ..Default::default()
+        };
+
+        // files are limited by their creation mode, and directories pass the default ACL on
+        let (mode, acl) = inherit(&parent, 0o666, false).unwrap();
+        assert_eq!(mode, 0o664);
+        assert!(acl.contains(&AclEntry {
+            scope: AclScope::Access,
+            ..default.clone()
+        }));
+        assert!(acl.iter().all(|entry| entry.scope == AclScope::Access));
+        let (mode, acl) = inherit(&parent, 0o777, true).unwrap();
+        assert_eq!(mode, 0o775);
+        assert!(acl.contains(&default));
+    }
+}
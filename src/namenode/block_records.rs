@@ -1,9 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
 
 /// stores which datanodes each block is stored on
 pub struct BlockRecords {
     /// Mapping from block id to datanode addrs
     block_mappings: HashMap<u64, Vec<String>>,
+
+    /// Mapping from block id to the generation stamp of its current contents
+    generation_stamps: HashMap<u64, u64>,
+
+    /// Mapping from block id to the number of replicas its file keeps of it
+    replication: HashMap<u64, usize>,
+
+    /// Replicas that datanodes have confirmed holding in a block report
+    reported_replicas: HashMap<u64, HashSet<String>>,
+
+    /// Blocks that have lost a replica and need a fresh copy, oldest first
+    replication_queue: VecDeque<u64>,
+
+    /// Time a copy of each queued block was last handed to a datanode
+    replication_scheduled: HashMap<u64, SystemTime>,
+
+    /// Blocks whose writer hasn't confirmed which datanodes hold them yet
+    under_construction: HashSet<u64>,
 }
 
 impl Default for BlockRecords {
@@ -16,30 +35,191 @@ impl BlockRecords {
     pub fn new() -> Self {
         Self {
             block_mappings: HashMap::new(),
+            generation_stamps: HashMap::new(),
+            replication: HashMap::new(),
+            reported_replicas: HashMap::new(),
+            replication_queue: VecDeque::new(),
+            replication_scheduled: HashMap::new(),
+            under_construction: HashSet::new(),
         }
     }
 
-    /// Replaces block if it already exists based on the hashed block_id determines which
-    /// datanode(s) to store on returns the IP address of a datanode to write to
+    /// Adds a new block to the records along with the number of replicas to keep of it and the
+    /// datanodes it will be stored on
     pub fn add_block_to_records(
         &mut self,
         block_id: u64,
+        generation_stamp: u64,
+        replication: usize,
         datanode_addrs: Vec<String>,
     ) -> Result<Vec<String>, &str> {
-        match self.block_mappings.get(&block_id) {
-            Some(addrs) => Ok(addrs.clone()),
-            None => {
-                self.block_mappings.insert(block_id, datanode_addrs.clone());
-                Ok(datanode_addrs.clone())
-            }
+        if self.block_mappings.contains_key(&block_id) {
+            return Err("Block already exists");
         }
+        self.block_mappings.insert(block_id, datanode_addrs.clone());
+        self.generation_stamps.insert(block_id, generation_stamp);
+        self.replication.insert(block_id, replication);
+        self.under_construction.insert(block_id);
+        Ok(datanode_addrs)
     }
 
     /// Removes a block form the records
     pub fn remove_block_from_records(&mut self, block_id: &u64) -> Option<Vec<String>> {
+        self.reported_replicas.remove(block_id);
+        self.generation_stamps.remove(block_id);
+        self.replication.remove(block_id);
+        self.under_construction.remove(block_id);
+        self.dequeue_replication(block_id);
         self.block_mappings.remove(block_id)
     }
 
+    /// Returns the generation stamp of a block's current contents
+    pub fn get_generation_stamp(&self, block_id: &u64) -> Option<u64> {
+        self.generation_stamps.get(block_id).copied()
+    }
+
+    /// Returns the number of replicas to keep of a block
+    pub fn get_replication(&self, block_id: &u64) -> Option<usize> {
+        self.replication.get(block_id).copied()
+    }
+
+    /// Records that a block is being rewritten with a new generation stamp
+    /// Replicas reported before hold the old contents, so they don't count until they're reported
+    /// again
+    pub fn set_generation_stamp(&mut self, block_id: u64, generation_stamp: u64) {
+        if self.generation_stamps.insert(block_id, generation_stamp) != Some(generation_stamp) {
+            self.reported_replicas.remove(&block_id);
+            self.under_construction.insert(block_id);
+        }
+    }
+
+    /// Records that a block's writer is done with it, so the datanodes it is mapped to are
+    /// expected to hold it
+    pub fn finish_construction(&mut self, block_id: &u64) {
+        self.under_construction.remove(block_id);
+    }
+
+    /// Returns true if a block is still being written
+    pub fn is_under_construction(&self, block_id: &u64) -> bool {
+        self.under_construction.contains(block_id)
+    }
+
+    /// Sets the datanodes a block is stored on to the ones its writer confirmed, along with any
+    /// that have reported holding it since, and returns how many there are
+    pub fn confirm_replicas(&mut self, block_id: u64, datanode_addrs: &[String]) -> usize {
+        let mut addrs = datanode_addrs.to_vec();
+        for addr in self.reported_replicas.get(&block_id).into_iter().flatten() {
+            if !addrs.contains(addr) {
+                addrs.push(addr.clone());
+            }
+        }
+        let num_replicas = addrs.len();
+        self.block_mappings.insert(block_id, addrs);
+        self.under_construction.remove(&block_id);
+        num_replicas
+    }
+
+    /// Returns true if a datanode has reported holding the block's current contents
+    pub fn has_reported_replica(&self, block_id: &u64) -> bool {
+        self.reported_replicas
+            .get(block_id)
+            .is_some_and(|addrs| !addrs.is_empty())
+    }
+
+    /// Returns every block the namenode believes has a replica on a datanode
+    pub fn blocks_on(&self, datanode_addr: &str) -> Vec<u64> {
+        self.block_mappings
+            .iter()
+            .filter(|(_, addrs)| addrs.iter().any(|addr| addr == datanode_addr))
+            .map(|(block_id, _)| *block_id)
+            .collect()
+    }
+
+    /// Returns true if the block is in the records
+    pub fn contains_block(&self, block_id: &u64) -> bool {
+        self.block_mappings.contains_key(block_id)
+    }
+
+    /// Records that a datanode reported holding a replica of a known block
+    /// Returns true if the namenode didn't already expect the replica to be there
+    pub fn add_replica(&mut self, block_id: u64, datanode_addr: &str) -> bool {
+        let addrs = match self.block_mappings.get_mut(&block_id) {
+            Some(addrs) => addrs,
+            None => return false,
+        };
+        self.reported_replicas
+            .entry(block_id)
+            .or_default()
+            .insert(datanode_addr.to_string());
+
+        if addrs.iter().any(|addr| addr == datanode_addr) {
+            return false;
+        }
+        addrs.push(datanode_addr.to_string());
+        true
+    }
+
+    /// Returns the blocks a datanode has previously reported holding
+    pub fn reported_blocks_on(&self, datanode_addr: &str) -> Vec<u64> {
+        self.reported_replicas
+            .iter()
+            .filter(|(_, addrs)| addrs.contains(datanode_addr))
+            .map(|(block_id, _)| *block_id)
+            .collect()
+    }
+
+    /// Removes a datanode from the replicas of a block, returning true if it held a replica
+    pub fn remove_replica(&mut self, block_id: &u64, datanode_addr: &str) -> bool {
+        if let Some(addrs) = self.reported_replicas.get_mut(block_id) {
+            addrs.remove(datanode_addr);
+        }
+        match self.block_mappings.get_mut(block_id) {
+            Some(addrs) => {
+                let prev_len = addrs.len();
+                addrs.retain(|addr| addr != datanode_addr);
+                addrs.len() != prev_len
+            }
+            None => false,
+        }
+    }
+
+    /// Queues a block to have a fresh replica made, unless it is already queued
+    pub fn queue_replication(&mut self, block_id: u64) {
+        if !self.replication_queue.contains(&block_id) {
+            self.replication_queue.push_back(block_id);
+        }
+    }
+
+    /// Returns true if a block is waiting for a fresh replica
+    pub fn is_queued_for_replication(&self, block_id: &u64) -> bool {
+        self.replication_queue.contains(block_id)
+    }
+
+    /// Returns the blocks waiting for a fresh replica, oldest first
+    pub fn queued_for_replication(&self) -> Vec<u64> {
+        self.replication_queue.iter().copied().collect()
+    }
+
+    /// Removes a block from the replication queue once it has enough replicas
+    pub fn dequeue_replication(&mut self, block_id: &u64) {
+        self.replication_queue.retain(|id| id != block_id);
+        self.replication_scheduled.remove(block_id);
+    }
+
+    /// Records that a copy of a queued block has been handed to a datanode
+    pub fn mark_replication_scheduled(&mut self, block_id: u64) {
+        self.replication_scheduled
+            .insert(block_id, SystemTime::now());
+    }
+
+    /// Returns true if a copy of the block was handed out less than `timeout` ago
+    pub fn replication_in_progress(&self, block_id: &u64, timeout: Duration) -> bool {
+        match self.replication_scheduled.get(block_id) {
+            Some(time) => time.elapsed().unwrap_or_default() < timeout,
+            None => false,
+        }
+    }
+
     /// Returns a list of datanodes that a block exists on
     pub fn get_block_datanodes(&self, block_id: &u64) -> Result<Vec<String>, &str> {
         match self.block_mappings.get(block_id) {
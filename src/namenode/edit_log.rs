@@ -0,0 +1,217 @@
+use crate::proto::{edit_log_op, EditLogOp, FsImage};
+use prost::Message;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Write-ahead log of changes to the namespace, compacted into fsimage checkpoints
+/// Both live in the namenode's name directory as `edits` and `fsimage`
+pub struct EditLog {
+    /// Directory holding the edit log and the fsimage
+    name_dir: PathBuf,
+
+    /// Edits made since the last checkpoint, as length-delimited `EditLogOp`s
+    edits: File,
+
+    /// Length of the edits file up to the end of the last complete edit
+    edits_len: u64,
+
+    /// Transaction id of the last edit appended
+    last_txid: u64,
+
+    /// Transaction id of the last edit folded into the fsimage
+    checkpoint_txid: u64,
+}
+
+impl EditLog {
+    /// Opens the edit log in `name_dir`, returning it along with the last checkpoint and the
+    /// edits made after it, in order
+    /// A torn edit at the end of the log, left by a crash part way through an append, is cut off,
+    /// while an edit that can't be decoded anywhere else fails the open
+    pub fn open(name_dir: &Path) -> Result<(Self, FsImage, Vec<edit_log_op::Op>), Box<dyn Error>> {
+        fs::create_dir_all(name_dir)?;
+
+        let image_path = name_dir.join("fsimage");
+        let image = match fs::read(&image_path) {
+            Ok(bytes) => FsImage::decode(bytes.as_slice())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FsImage::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let edits_path = name_dir.join("edits");
+        let bytes = match fs::read(&edits_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let mut remaining = bytes.as_slice();
+        let mut last_txid = image.last_txid;
+        let mut ops = vec![];
+        while !remaining.is_empty() {
+            let edit_start = remaining;
+            let edit = match EditLogOp::decode_length_delimited(&mut remaining) {
+                Ok(edit) => edit,
+                Err(e) if runs_past_end(edit_start) => {
+                    println!("Discarding torn edit at the end of the edit log: {}", e);
+                    remaining = edit_start;
+                    break;
+                }
+                Err(e) => {
+                    let offset = bytes.len() - edit_start.len();
+                    return Err(format!("Edit log is corrupt at byte {}: {}", offset, e).into());
+                }
+            };
+            // edits already folded into the fsimage are left behind if a checkpoint is
+            // interrupted before it truncates the log
+            if edit.txid <= last_txid {
+                continue;
+            }
+            last_txid = edit.txid;
+            if let Some(op) = edit.op {
+                ops.push(op);
+            }
+        }
+        let edits_len = (bytes.len() - remaining.len()) as u64;
+
+        let edits = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&edits_path)?;
+        edits.set_len(edits_len)?;
+        println!(
+            "Loaded fsimage at transaction {} and {} edits from {}",
+            image.last_txid,
+            ops.len(),
+            name_dir.display()
+        );
+
+        let edit_log = EditLog {
+            name_dir: name_dir.to_path_buf(),
+            edits,
+            edits_len,
+            last_txid,
+            checkpoint_txid: image.last_txid,
+        };
+        Ok((edit_log, image, ops))
+    }
+
+    /// Appends an edit to the log, returning once it is on disk
+    pub fn append(&mut self, op: edit_log_op::Op) -> Result<(), Box<dyn Error>> {
+        let edit = EditLogOp {
+            txid: self.last_txid + 1,
+            op: Some(op),
+        };
+        let bytes = edit.encode_length_delimited_to_vec();
+        if let Err(e) = self
+            .edits
+            .write_all(&bytes)
+            .and_then(|_| self.edits.sync_data())
+        {
+            // don't leave a partial edit for the next one to be appended after
+            let _ = self.edits.set_len(self.edits_len);
+            return Err(e.into());
+        }
+        self.edits_len += bytes.len() as u64;
+        self.last_txid = edit.txid;
+        Ok(())
+    }
+
+    /// Returns true if edits have been appended since the last checkpoint
+    pub fn has_edits_since_checkpoint(&self) -> bool {
+        self.last_txid > self.checkpoint_txid
+    }
+
+    /// Returns the transaction id of the last edit appended
+    pub fn last_txid(&self) -> u64 {
+        self.last_txid
+    }
+
+    /// Saves `image`, which must include every edit appended so far, as the new fsimage and
+    /// empties the log
+    /// The fsimage is written to a temporary path first so a crash never leaves a torn one
+    pub fn checkpoint(&mut self, mut image: FsImage) -> Result<(), Box<dyn Error>> {
+        image.last_txid = self.last_txid;
+        let image_path = self.name_dir.join("fsimage");
+        let tmp_path = image_path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&image.encode_to_vec())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &image_path)?;
+
+        self.edits.set_len(0)?;
+        self.edits.sync_all()?;
+        self.edits_len = 0;
+        self.checkpoint_txid = self.last_txid;
+        Ok(())
+    }
+}
+
+/// Returns true if the length-delimited edit at the start of `bytes` is cut short by the end of
+/// the log, as an append interrupted by a crash leaves it
+fn runs_past_end(bytes: &[u8]) -> bool {
+    let mut buf = bytes;
+    match prost::encoding::decode_varint(&mut buf) {
+        Ok(len) => len > buf.len() as u64,
+        // every byte of a length that was cut short says another byte follows
+        Err(_) => bytes.len() < 10 && bytes.iter().all(|byte| byte & 0x80 != 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_and_checkpoint() {
+        let name_dir = std::env::temp_dir().join(format!("kldfs_edits_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&name_dir);
+        let delete = |path: &str| edit_log_op::Op::Delete(path.to_string());
+
+        let (mut edit_log, _, ops) = EditLog::open(&name_dir).unwrap();
+        assert!(ops.is_empty());
+        edit_log.append(delete("a")).unwrap();
+        edit_log.append(delete("b")).unwrap();
+        drop(edit_log);
+
+        // a torn edit at the end is dropped, and later edits go after the last complete one
+        let mut edits = OpenOptions::new()
+            .append(true)
+            .open(name_dir.join("edits"))
+            .unwrap();
+        edits.write_all(&[42, 1, 2]).unwrap();
+        let (mut edit_log, _, ops) = EditLog::open(&name_dir).unwrap();
+        assert_eq!(ops, vec![delete("a"), delete("b")]);
+        edit_log.append(delete("c")).unwrap();
+        drop(edit_log);
+
+        let (mut edit_log, image, ops) = EditLog::open(&name_dir).unwrap();
+        assert_eq!(image.last_txid, 0);
+        assert_eq!(ops, vec![delete("a"), delete("b"), delete("c")]);
+
+        // a checkpoint folds the edits into the fsimage
+        edit_log.checkpoint(FsImage::default()).unwrap();
+        assert!(!edit_log.has_edits_since_checkpoint());
+        edit_log.append(delete("d")).unwrap();
+        drop(edit_log);
+
+        let (edit_log, image, ops) = EditLog::open(&name_dir).unwrap();
+        assert_eq!(image.last_txid, 3);
+        assert_eq!(ops, vec![delete("d")]);
+        assert_eq!(edit_log.last_txid(), 4);
+        drop(edit_log);
+
+        // an edit that is corrupt but complete, with edits after it, fails the open rather than
+        // losing them
+        let edits_path = name_dir.join("edits");
+        let mut bytes = fs::read(&edits_path).unwrap();
+        let good = bytes.clone();
+        bytes[1] = 0xff;
+        bytes.extend_from_slice(&good);
+        fs::write(&edits_path, &bytes).unwrap();
+        assert!(EditLog::open(&name_dir).is_err());
+        assert_eq!(fs::read(&edits_path).unwrap(), bytes);
+
+        fs::remove_dir_all(&name_dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use crate::proto::NamespaceEvent;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use tokio::sync::broadcast;
+
+/// Number of past events kept for watchers resuming from an earlier transaction
+const EVENT_HISTORY: usize = 10000;
+
+/// Number of events a watcher can fall behind by before it is cut off
+const WATCHER_CAPACITY: usize = 1024;
+
+/// Events a watcher has missed, and a receiver for the events after them
+#[derive(Debug)]
+pub struct Subscription {
+    /// Transaction the watcher is caught up to once it has seen `history`
+    pub since_txid: u64,
+    pub history: Vec<NamespaceEvent>,
+    pub receiver: broadcast::Receiver<NamespaceEvent>,
+}
+
+/// Recent changes to the namespace, which watchers can replay and then follow as they happen
+pub struct EventLog {
+    /// The last EVENT_HISTORY events, oldest first
+    history: VecDeque<NamespaceEvent>,
+    /// Transaction id of the last event
+    last_txid: u64,
+    sender: broadcast::Sender<NamespaceEvent>,
+}
+
+impl EventLog {
+    /// Creates an empty event log, whose first event comes after transaction `last_txid`
+    pub fn new(last_txid: u64) -> Self {
+        let (sender, _) = broadcast::channel(WATCHER_CAPACITY);
+        Self {
+            history: VecDeque::new(),
+            last_txid,
+            sender,
+        }
+    }
+
+    /// Transaction id of the last event
+    pub fn last_txid(&self) -> u64 {
+        self.last_txid
+    }
+
+    /// Adds an event to the history and sends it to every watcher
+    pub fn publish(&mut self, event: NamespaceEvent) {
+        self.last_txid = event.txid;
+        if self.history.len() == EVENT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(event.clone());
+        // there may be no watchers
+        let _ = self.sender.send(event);
+    }
+
+    /// Returns every event after transaction `since_txid`, or none if it is 0, along with a
+    /// receiver for every event after those
+    pub fn subscribe(&self, since_txid: u64) -> Result<Subscription, Box<dyn Error>> {
+        let receiver = self.sender.subscribe();
+        if since_txid == 0 {
+            return Ok(Subscription {
+                since_txid: self.last_txid,
+                history: vec![],
+                receiver,
+            });
+        }
+        let first_txid = match self.history.front() {
+            Some(event) => event.txid,
+            None => self.last_txid + 1,
+        };
+        if since_txid + 1 < first_txid {
+            return Err(EventsUnavailable(since_txid).into());
+        }
+        let history = self
+            .history
+            .iter()
+            .filter(|event| event.txid > since_txid)
+            .cloned()
+            .collect();
+        Ok(Subscription {
+            since_txid: self.last_txid,
+            history,
+            receiver,
+        })
+    }
+}
+
+/// Returns true if an event changed the file or directory at `prefix`, or anything under it
+pub fn matches(event: &NamespaceEvent, prefix: &str) -> bool {
+    let under =
+        |path: &str| prefix == "/" || path == prefix || path.starts_with(&format!("{}/", prefix));
+    under(&event.path) || (!event.destination.is_empty() && under(&event.destination))
+}
+
+/// Error for a watcher resuming from a transaction whose events are no longer kept
+#[derive(Debug)]
+pub struct EventsUnavailable(u64);
+
+impl fmt::Display for EventsUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Events after transaction {} are no longer available",
+            self.0
+        )
+    }
+}
+
+impl Error for EventsUnavailable {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(txid: u64, path: &str) -> NamespaceEvent {
+        NamespaceEvent {
+            txid,
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut events = EventLog::new(5);
+        assert!(events.subscribe(5).unwrap().history.is_empty());
+        assert!(events.subscribe(4).is_err());
+
+        events.publish(event(6, "/a"));
+        let mut subscription = events.subscribe(0).unwrap();
+        assert!(subscription.history.is_empty());
+        assert_eq!(subscription.since_txid, 6);
+        events.publish(event(7, "/b"));
+        assert_eq!(subscription.receiver.try_recv().unwrap().txid, 7);
+
+        let subscription = events.subscribe(5).unwrap();
+        let txids: Vec<u64> = subscription
+            .history
+            .iter()
+            .map(|event| event.txid)
+            .collect();
+        assert_eq!(txids, vec![6, 7]);
+        assert_eq!(subscription.since_txid, 7);
+
+        for txid in 8..8 + EVENT_HISTORY as u64 {
+            events.publish(event(txid, "/c"));
+        }
+        assert!(events.subscribe(6).is_err());
+        assert_eq!(events.subscribe(7).unwrap().history.len(), EVENT_HISTORY);
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(&event(1, "/a/b"), "/"));
+        assert!(matches(&event(1, "/a/b"), "/a"));
+        assert!(matches(&event(1, "/a"), "/a"));
+        assert!(!matches(&event(1, "/ab"), "/a"));
+        let rename = NamespaceEvent {
+            destination: "/a/c".to_string(),
+            ..event(1, "/d")
+        };
+        assert!(matches(&rename, "/a"));
+    }
+}
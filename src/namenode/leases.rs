@@ -0,0 +1,156 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// The right of one client to write a set of files, which lasts as long as the client keeps
+/// renewing it
+struct Lease {
+    /// Files the client has open for writing
+    paths: HashSet<String>,
+    last_renewed: Instant,
+}
+
+impl Lease {
+    fn is_expired(&self, limit: Duration) -> bool {
+        self.last_renewed.elapsed() >= limit
+    }
+}
+
+/// Keeps track of which client is writing each file, so only one client writes a file at a time
+#[derive(Default)]
+pub struct LeaseManager {
+    /// Maps the name of each client writing files to its lease
+    leases: HashMap<String, Lease>,
+
+    /// Maps each file being written to the client writing it
+    holders: HashMap<String, String>,
+}
+
+impl LeaseManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the client writing a file, if any
+    pub fn holder(&self, path: &str) -> Option<&str> {
+        self.holders.get(path).map(|holder| holder.as_str())
+    }
+
+    /// Returns true if the client writing a file hasn't renewed its lease within `limit`
+    pub fn is_expired(&self, path: &str, limit: Duration) -> bool {
+        match self
+            .holders
+            .get(path)
+            .and_then(|holder| self.leases.get(holder))
+        {
+            Some(lease) => lease.is_expired(limit),
+            None => true,
+        }
+    }
+
+    /// Gives a client the lease on a file, renewing the rest of its lease
+    /// Whoever held the file before loses it
+    pub fn add(&mut self, holder: &str, path: &str) {
+        self.remove(path);
+        let lease = self.leases.entry(holder.to_string()).or_insert(Lease {
+            paths: HashSet::new(),
+            last_renewed: Instant::now(),
+        });
+        lease.paths.insert(path.to_string());
+        lease.last_renewed = Instant::now();
+        self.holders.insert(path.to_string(), holder.to_string());
+    }
+
+    /// Renews a client's lease on every file it is writing, returning false if it has none
+    pub fn renew(&mut self, holder: &str) -> bool {
+        match self.leases.get_mut(holder) {
+            Some(lease) => {
+                lease.last_renewed = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Releases the lease on a file, returning the client that held it
+    pub fn remove(&mut self, path: &str) -> Option<String> {
+        let holder = self.holders.remove(path)?;
+        if let Some(lease) = self.leases.get_mut(&holder) {
+            lease.paths.remove(path);
+            if lease.paths.is_empty() {
+                self.leases.remove(&holder);
+            }
+        }
+        Some(holder)
+    }
+
+    /// Releases the lease on every file at or under `path`
+    pub fn remove_under(&mut self, path: &str) {
+        for leased in self.paths_under(path) {
+            self.remove(&leased);
+        }
+    }
+
+    /// Moves the leases on every file at or under `source` to the same place under `destination`
+    pub fn rename(&mut self, source: &str, destination: &str) {
+        for leased in self.paths_under(source) {
+            let moved = format!("{}{}", destination, &leased[source.len()..]);
+            if let Some(holder) = self.holders.remove(&leased) {
+                if let Some(lease) = self.leases.get_mut(&holder) {
+                    lease.paths.remove(&leased);
+                    lease.paths.insert(moved.clone());
+                }
+                self.holders.insert(moved, holder);
+            }
+        }
+    }
+
+    /// Returns every file written by a client that hasn't renewed its lease within `limit`
+    pub fn expired(&self, limit: Duration) -> Vec<String> {
+        self.leases
+            .values()
+            .filter(|lease| lease.is_expired(limit))
+            .flat_map(|lease| lease.paths.iter().cloned())
+            .collect()
+    }
+
+    /// Returns every leased file at or under `path`
+    fn paths_under(&self, path: &str) -> Vec<String> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        self.holders
+            .keys()
+            .filter(|leased| *leased == path || leased.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leases() {
+        let mut leases = LeaseManager::new();
+        leases.add("client1", "/a/f");
+        leases.add("client1", "/a/g");
+        leases.add("client2", "/b");
+        assert_eq!(leases.holder("/a/f"), Some("client1"));
+        assert!(!leases.is_expired("/a/f", Duration::from_secs(60)));
+        assert!(leases.is_expired("/a/f", Duration::ZERO));
+        assert!(leases.is_expired("/c", Duration::from_secs(60)));
+
+        leases.rename("/a", "/d");
+        assert_eq!(leases.holder("/a/f"), None);
+        assert_eq!(leases.holder("/d/g"), Some("client1"));
+
+        // a new writer takes the file over
+        leases.add("client2", "/d/f");
+        assert_eq!(leases.holder("/d/f"), Some("client2"));
+
+        leases.remove_under("/d");
+        assert_eq!(leases.remove("/b"), Some("client2".to_string()));
+        assert!(leases.holders.is_empty());
+        assert!(leases.leases.is_empty());
+        assert!(!leases.renew("client1"));
+    }
+}
@@ -1,8 +1,24 @@
+pub mod acl;
 pub mod block_records;
+pub mod edit_log;
+pub mod events;
+pub mod leases;
+pub mod namespace;
+pub mod permission;
 pub mod records;
 pub mod server;
 
 pub use block_records::BlockRecords;
+pub use edit_log::EditLog;
+pub use events::EventLog;
+pub use leases::LeaseManager;
+pub use namespace::{Access, Attributes, INode, INodeStatus, Namespace};
+pub use permission::Caller;
 pub use records::DataNodeInfo;
+pub use records::DataNodeState;
+pub use records::FileBlock;
+pub use records::FileLayout;
+pub use records::FileRecord;
 pub use records::NameNodeRecords;
+pub use records::ReplicationTask;
 pub use server::NameNodeServer;
@@ -0,0 +1,691 @@
+use crate::namenode::acl::AclEntry;
+use crate::namenode::permission::{Caller, EXECUTE};
+use crate::namenode::records::FileRecord;
+use crate::proto::INodeAttributes;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+/// Maps the name of each child of a directory to the child
+pub type Children = BTreeMap<String, INode>;
+
+/// Ownership, permissions and times of a file or directory
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attributes {
+    pub owner: String,
+    pub group: String,
+    /// Permission bits, such as 0o644
+    pub mode: u32,
+    /// Time the inode was created, in milliseconds since the Unix epoch
+    pub ctime: u64,
+    /// Time the contents were last written, in milliseconds since the Unix epoch
+    pub mtime: u64,
+    /// Time the contents were last read, in milliseconds since the Unix epoch
+    pub atime: u64,
+    /// ACL entries beyond the ones the permission bits stand for, see `acl::full_acl`
+    pub acl: Vec<AclEntry>,
+}
+
+impl From<&INodeAttributes> for Attributes {
+    fn from(val: &INodeAttributes) -> Self {
+        Attributes {
+            owner: val.owner.clone(),
+            group: val.group.clone(),
+            mode: val.mode,
+            ctime: val.ctime,
+            mtime: val.mtime,
+            atime: val.atime,
+            acl: val.acl.iter().map(AclEntry::from).collect(),
+        }
+    }
+}
+
+impl From<&Attributes> for INodeAttributes {
+    fn from(val: &Attributes) -> Self {
+        INodeAttributes {
+            owner: val.owner.clone(),
+            group: val.group.clone(),
+            mode: val.mode,
+            ctime: val.ctime,
+            mtime: val.mtime,
+            atime: val.atime,
+            acl: val.acl.iter().map(|entry| entry.into()).collect(),
+        }
+    }
+}
+
+/// A directory along with the files and directories in it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directory {
+    pub attributes: Attributes,
+    pub children: Children,
+}
+
+impl Directory {
+    pub fn new(attributes: Attributes) -> Self {
+        Self {
+            attributes,
+            children: Children::new(),
+        }
+    }
+}
+
+/// A file or directory in the namespace
+#[derive(Clone, Debug, PartialEq)]
+pub enum INode {
+    File(FileRecord),
+    Directory(Directory),
+}
+
+impl INode {
+    pub fn attributes(&self) -> &Attributes {
+        match self {
+            INode::File(record) => &record.attributes,
+            INode::Directory(dir) => &dir.attributes,
+        }
+    }
+
+    fn attributes_mut(&mut self) -> &mut Attributes {
+        match self {
+            INode::File(record) => &mut record.attributes,
+            INode::Directory(dir) => &mut dir.attributes,
+        }
+    }
+
+    /// Returns every file at or under `path`, along with its full path
+    pub fn files(&self, path: &str) -> Vec<(String, &FileRecord)> {
+        match self {
+            INode::File(record) => vec![(path.to_string(), record)],
+            INode::Directory(dir) => dir
+                .children
+                .iter()
+                .flat_map(|(name, child)| child.files(&join(path, name)))
+                .collect(),
+        }
+    }
+
+    /// Returns every directory at or under `path` along with its attributes, parents before their
+    /// children
+    pub fn directories(&self, path: &str) -> Vec<(String, &Attributes)> {
+        match self {
+            INode::File(_) => vec![],
+            INode::Directory(dir) => std::iter::once((path.to_string(), &dir.attributes))
+                .chain(
+                    dir.children
+                        .iter()
+                        .flat_map(|(name, child)| child.directories(&join(path, name))),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// What the namenode knows about a file or directory, as shown by `stat` and `ls`
+#[derive(Clone, Debug, PartialEq)]
+pub struct INodeStatus {
+    pub name: String,
+    pub is_directory: bool,
+    /// Length of the file in bytes, or 0 for a directory
+    pub length: usize,
+    /// Block size of the file, or 0 for a directory
+    pub block_size: usize,
+    /// Number of replicas kept of each block of the file, or 0 for a directory
+    pub replication: usize,
+    pub attributes: Attributes,
+}
+
+impl INodeStatus {
+    fn new(name: &str, inode: &INode) -> Self {
+        let (length, block_size, replication) = match inode {
+            INode::File(record) => (record.length, record.block_size, record.replication),
+            INode::Directory(_) => (0, 0, 0),
+        };
+        Self {
+            name: name.to_string(),
+            is_directory: matches!(inode, INode::Directory(_)),
+            length,
+            block_size,
+            replication,
+            attributes: inode.attributes().clone(),
+        }
+    }
+}
+
+/// Permissions a request needs along a path, checked by `Namespace::check_permission`
+/// Every directory leading to the path also needs to be executable
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Access {
+    /// Needed to the deepest existing directory above the path
+    pub ancestor: u32,
+    /// Needed to the directory the path is in
+    pub parent: u32,
+    /// Needed to the file or directory at the path, if there is one
+    pub inode: u32,
+    /// Needed to every directory at or under the path
+    pub subtree: u32,
+    /// Whether the caller needs to own the file or directory at the path
+    pub owner: bool,
+}
+
+/// Tree of the directories and files in the filesystem, rooted at `/`
+pub struct Namespace {
+    root: INode,
+}
+
+/// Splits a path into the names along it, so `a/b`, `/a/b` and `/a//b/` are the same path
+pub fn components(path: &str) -> Result<Vec<&str>, Box<dyn Error>> {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .collect();
+    if components.contains(&"..") {
+        return Err("Paths can't contain '..'".into());
+    }
+    Ok(components)
+}
+
+/// Returns the absolute form of a path, e.g. `/a/b` for `a//b/`
+pub fn normalize(path: &str) -> Result<String, Box<dyn Error>> {
+    Ok(format!("/{}", components(path)?.join("/")))
+}
+
+/// Returns the path of `name` inside the directory at `path`
+fn join(path: &str, name: &str) -> String {
+    format!("{}/{}", path.trim_end_matches('/'), name)
+}
+
+impl Namespace {
+    pub fn new(root_attributes: Attributes) -> Self {
+        Self {
+            root: INode::Directory(Directory::new(root_attributes)),
+        }
+    }
+
+    /// Returns the file or directory at a path, or None if there's nothing there
+    pub fn get(&self, path: &str) -> Result<Option<&INode>, Box<dyn Error>> {
+        let mut inode = &self.root;
+        for name in components(path)? {
+            inode = match inode {
+                INode::Directory(dir) => match dir.children.get(name) {
+                    Some(child) => child,
+                    None => return Ok(None),
+                },
+                INode::File(_) => return Ok(None),
+            };
+        }
+        Ok(Some(inode))
+    }
+
+    /// Returns the file or directory at each prefix of a path that exists, starting with the root,
+    /// along with the prefixes
+    fn walk(&self, components: &[&str]) -> Vec<(String, &INode)> {
+        let mut inodes = vec![("/".to_string(), &self.root)];
+        for name in components {
+            let (path, inode) = inodes.last().unwrap();
+            let child = match inode {
+                INode::Directory(dir) => dir.children.get(*name),
+                INode::File(_) => None,
+            };
+            match child {
+                Some(child) => inodes.push((join(path, name), child)),
+                None => break,
+            }
+        }
+        inodes
+    }
+
+    /// Returns the deepest existing directory above a path
+    pub fn ancestor(&self, path: &str) -> Result<&INode, Box<dyn Error>> {
+        let components = components(path)?;
+        let mut inodes = self.walk(&components);
+        inodes.truncate(components.len().max(1));
+        Ok(inodes.pop().unwrap().1)
+    }
+
+    /// Checks that `caller` has the permissions in `access` along a path
+    /// Parts of the path that don't exist aren't checked, which is left to the request itself
+    pub fn check_permission(
+        &self,
+        path: &str,
+        caller: &Caller,
+        access: &Access,
+    ) -> Result<(), Box<dyn Error>> {
+        let components = components(path)?;
+        let inodes = self.walk(&components);
+        let exists = inodes.len() == components.len() + 1;
+
+        // every directory leading to the path must be searchable
+        for (dir_path, inode) in inodes.iter().take(components.len()) {
+            if let INode::Directory(dir) = inode {
+                caller.check(dir_path, &dir.attributes, EXECUTE)?;
+            }
+        }
+        if access.ancestor != 0 {
+            let deepest = (inodes.len() - 1).min(components.len().saturating_sub(1));
+            let (ancestor_path, ancestor) = &inodes[deepest];
+            caller.check(ancestor_path, ancestor.attributes(), access.ancestor)?;
+        }
+        if access.parent != 0 && !components.is_empty() && inodes.len() >= components.len() {
+            let (parent_path, parent) = &inodes[components.len() - 1];
+            caller.check(parent_path, parent.attributes(), access.parent)?;
+        }
+        if !exists {
+            return Ok(());
+        }
+
+        let (path, inode) = inodes.last().unwrap();
+        if access.owner {
+            caller.check_owner(path, inode.attributes())?;
+        }
+        if access.inode != 0 {
+            caller.check(path, inode.attributes(), access.inode)?;
+        }
+        if access.subtree != 0 {
+            for (dir_path, attributes) in inode.directories(path) {
+                caller.check(&dir_path, attributes, access.subtree)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the file or directory at a path
+    fn get_mut(&mut self, path: &str) -> Result<Option<&mut INode>, Box<dyn Error>> {
+        let mut inode = &mut self.root;
+        for name in components(path)? {
+            inode = match inode {
+                INode::Directory(dir) => match dir.children.get_mut(name) {
+                    Some(child) => child,
+                    None => return Ok(None),
+                },
+                INode::File(_) => return Ok(None),
+            };
+        }
+        Ok(Some(inode))
+    }
+
+    /// Returns the file at a path
+    pub fn get_file(&self, path: &str) -> Result<&FileRecord, Box<dyn Error>> {
+        match self.get(path)? {
+            Some(INode::File(record)) => Ok(record),
+            Some(INode::Directory(_)) => Err("Is a directory".into()),
+            None => Err(NotFound("File does not exist").into()),
+        }
+    }
+
+    /// Checks that the directory a path would be created in exists
+    pub fn check_parent(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let components = components(path)?;
+        let (_, parent) = components
+            .split_last()
+            .ok_or("Path is the root directory")?;
+        let mut inode = &self.root;
+        for name in parent {
+            inode = match inode {
+                INode::Directory(dir) => dir
+                    .children
+                    .get(*name)
+                    .ok_or(NotFound("Parent directory does not exist"))?,
+                INode::File(_) => return Err("Parent is not a directory".into()),
+            };
+        }
+        match inode {
+            INode::Directory(_) => Ok(()),
+            INode::File(_) => Err("Parent is not a directory".into()),
+        }
+    }
+
+    /// Returns the directory a path would be created in, along with the path's name within it
+    fn parent_mut<'a>(
+        &mut self,
+        path: &'a str,
+    ) -> Result<(&mut Children, &'a str), Box<dyn Error>> {
+        self.check_parent(path)?;
+        let components = components(path)?;
+        let (name, parent) = components
+            .split_last()
+            .ok_or("Path is the root directory")?;
+        let mut children = match &mut self.root {
+            INode::Directory(dir) => &mut dir.children,
+            INode::File(_) => unreachable!("the root is always a directory"),
+        };
+        for dir_name in parent {
+            children = match children.get_mut(*dir_name) {
+                Some(INode::Directory(dir)) => &mut dir.children,
+                _ => unreachable!("the parent was checked"),
+            };
+        }
+        Ok((children, name))
+    }
+
+    /// Creates or replaces the file at a path, returning the file it replaced
+    pub fn set_file(
+        &mut self,
+        path: &str,
+        record: FileRecord,
+    ) -> Result<Option<FileRecord>, Box<dyn Error>> {
+        let (children, name) = self.parent_mut(path)?;
+        if let Some(INode::Directory(_)) = children.get(name) {
+            return Err("Is a directory".into());
+        }
+        match children.insert(name.to_string(), INode::File(record)) {
+            Some(INode::File(prev_record)) => Ok(Some(prev_record)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Checks that a directory can be created at a path
+    /// With `parents`, missing parents are created along with it and an existing directory is
+    /// left as it is
+    pub fn check_mkdir(&self, path: &str, parents: bool) -> Result<(), Box<dyn Error>> {
+        let components = components(path)?;
+        let mut inode = &self.root;
+        for (i, name) in components.iter().enumerate() {
+            let is_last = i + 1 == components.len();
+            let children = match inode {
+                INode::Directory(dir) => &dir.children,
+                INode::File(_) => return Err("Parent is not a directory".into()),
+            };
+            inode = match children.get(*name) {
+                Some(INode::File(_)) if is_last => {
+                    return Err(AlreadyExists("File already exists").into())
+                }
+                Some(child) => child,
+                None if is_last || parents => return Ok(()),
+                None => return Err(NotFound("Parent directory does not exist").into()),
+            };
+        }
+        if parents {
+            Ok(())
+        } else {
+            Err(AlreadyExists("Directory already exists").into())
+        }
+    }
+
+    /// Creates a directory at a path, along with any missing parents if `parents` is set
+    /// Every directory created is given `attributes`
+    pub fn mkdir(
+        &mut self,
+        path: &str,
+        parents: bool,
+        attributes: &Attributes,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_mkdir(path, parents)?;
+        let mut inode = &mut self.root;
+        for name in components(path)? {
+            inode = match inode {
+                INode::Directory(dir) => dir
+                    .children
+                    .entry(name.to_string())
+                    .or_insert_with(|| INode::Directory(Directory::new(attributes.clone()))),
+                INode::File(_) => unreachable!("the path was checked"),
+            };
+        }
+        Ok(())
+    }
+
+    /// Returns the status of the file or directory at a path
+    pub fn status(&self, path: &str) -> Result<INodeStatus, Box<dyn Error>> {
+        let inode = self.get(path)?.ok_or(NotFound("File does not exist"))?;
+        let name = components(path)?.last().copied().unwrap_or("/");
+        Ok(INodeStatus::new(name, inode))
+    }
+
+    /// Lists the contents of the directory at a path, in name order
+    pub fn list(&self, path: &str) -> Result<Vec<INodeStatus>, Box<dyn Error>> {
+        let dir = match self.get(path)? {
+            Some(INode::Directory(dir)) => dir,
+            Some(INode::File(_)) => return Err("Not a directory".into()),
+            None => return Err(NotFound("Directory does not exist").into()),
+        };
+        Ok(dir
+            .children
+            .iter()
+            .map(|(name, child)| INodeStatus::new(name, child))
+            .collect())
+    }
+
+    /// Sets the times a file or directory was last modified and last read
+    pub fn set_times(&mut self, path: &str, mtime: u64, atime: u64) -> Result<(), Box<dyn Error>> {
+        let inode = self.get_mut(path)?.ok_or(NotFound("File does not exist"))?;
+        let attributes = inode.attributes_mut();
+        attributes.mtime = mtime;
+        attributes.atime = atime;
+        Ok(())
+    }
+
+    /// Sets the permission bits of a file or directory
+    pub fn set_permission(&mut self, path: &str, mode: u32) -> Result<(), Box<dyn Error>> {
+        let inode = self.get_mut(path)?.ok_or(NotFound("File does not exist"))?;
+        inode.attributes_mut().mode = mode;
+        Ok(())
+    }
+
+    /// Sets the permission bits and ACL of a file or directory
+    pub fn set_acl(
+        &mut self,
+        path: &str,
+        mode: u32,
+        acl: Vec<AclEntry>,
+    ) -> Result<(), Box<dyn Error>> {
+        let inode = self.get_mut(path)?.ok_or(NotFound("File does not exist"))?;
+        let attributes = inode.attributes_mut();
+        attributes.mode = mode;
+        attributes.acl = acl;
+        Ok(())
+    }
+
+    /// Sets the owner and group of a file or directory, leaving either alone if it is None
+    pub fn set_owner(
+        &mut self,
+        path: &str,
+        owner: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let inode = self.get_mut(path)?.ok_or(NotFound("File does not exist"))?;
+        let attributes = inode.attributes_mut();
+        if let Some(owner) = owner {
+            attributes.owner = owner.to_string();
+        }
+        if let Some(group) = group {
+            attributes.group = group.to_string();
+        }
+        Ok(())
+    }
+
+    /// Removes the file or directory at a path, along with everything under it
+    pub fn remove(&mut self, path: &str) -> Result<INode, Box<dyn Error>> {
+        let (children, name) = self.parent_mut(path)?;
+        children
+            .remove(name)
+            .ok_or(NotFound("File does not exist").into())
+    }
+
+    /// Checks that the file or directory at `source` can be moved to `destination`
+    /// With `overwrite`, a file can replace a file and a directory can replace an empty directory
+    pub fn check_rename(
+        &self,
+        source: &str,
+        destination: &str,
+        overwrite: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let source_components = components(source)?;
+        let destination_components = components(destination)?;
+        if source_components.is_empty() {
+            return Err("Can't move the root directory".into());
+        }
+        let inode = self.get(source)?.ok_or(NotFound("Source does not exist"))?;
+        if source_components == destination_components {
+            return Ok(());
+        }
+        if destination_components.starts_with(&source_components) {
+            return Err("Can't move a directory inside itself".into());
+        }
+        self.check_parent(destination)?;
+
+        match (inode, self.get(destination)?) {
+            (_, None) => Ok(()),
+            (_, Some(_)) if !overwrite => Err(AlreadyExists("Destination already exists").into()),
+            (INode::File(_), Some(INode::File(_))) => Ok(()),
+            (INode::Directory(_), Some(INode::Directory(dir))) if dir.children.is_empty() => Ok(()),
+            (INode::Directory(_), Some(INode::Directory(_))) => {
+                Err("Destination is a directory that isn't empty".into())
+            }
+            (INode::File(_), Some(INode::Directory(_))) => {
+                Err("Can't overwrite a directory with a file".into())
+            }
+            (INode::Directory(_), Some(INode::File(_))) => {
+                Err("Can't overwrite a file with a directory".into())
+            }
+        }
+    }
+
+    /// Moves the file or directory at `source`, along with everything under it, to `destination`
+    /// Returns whatever was at `destination` before, which is replaced
+    pub fn rename(
+        &mut self,
+        source: &str,
+        destination: &str,
+    ) -> Result<Option<INode>, Box<dyn Error>> {
+        self.check_rename(source, destination, true)?;
+        if components(source)? == components(destination)? {
+            return Ok(None);
+        }
+        let inode = self.remove(source)?;
+        let (children, name) = self.parent_mut(destination)?;
+        Ok(children.insert(name.to_string(), inode))
+    }
+
+    /// Returns every file in the namespace, along with its full path
+    pub fn files(&self) -> Vec<(String, &FileRecord)> {
+        self.root.files("/")
+    }
+
+    /// Returns every directory in the namespace other than the root along with its attributes,
+    /// parents first
+    pub fn directories(&self) -> Vec<(String, &Attributes)> {
+        self.root.directories("/").into_iter().skip(1).collect()
+    }
+}
+
+/// Error for a path, or the directory it would go in, that doesn't exist
+#[derive(Debug)]
+pub struct NotFound(pub &'static str);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for NotFound {}
+
+/// Error for creating a file or directory at a path something is already at
+#[derive(Debug)]
+pub struct AlreadyExists(pub &'static str);
+
+impl fmt::Display for AlreadyExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AlreadyExists {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(length: usize) -> FileRecord {
+        FileRecord {
+            length,
+            block_size: 4096,
+            block_ids: vec![],
+            replication: 1,
+            attributes: Attributes::default(),
+            under_construction: None,
+        }
+    }
+
+    #[test]
+    fn test_directory_tree() {
+        let mut namespace = Namespace::new(Attributes::default());
+        namespace
+            .mkdir("/a", false, &Attributes::default())
+            .unwrap();
+        assert!(namespace
+            .mkdir("/a", false, &Attributes::default())
+            .is_err());
+        assert!(namespace
+            .mkdir("/b/c", false, &Attributes::default())
+            .is_err());
+        namespace
+            .mkdir("b/c/", true, &Attributes::default())
+            .unwrap();
+        namespace.mkdir("/b", true, &Attributes::default()).unwrap();
+
+        // files need an existing directory to go in, and paths are normalised
+        namespace.set_file("/a/f", file(10)).unwrap();
+        assert!(namespace.set_file("/x/f", file(10)).is_err());
+        assert!(namespace.set_file("/a/f/g", file(10)).is_err());
+        assert!(namespace.set_file("/b", file(10)).is_err());
+        assert!(namespace
+            .mkdir("/a/f", true, &Attributes::default())
+            .is_err());
+        assert!(namespace.get("/a/../b").is_err());
+        assert_eq!(namespace.get_file("a//f").unwrap(), &file(10));
+
+        let entries = namespace.list("/").unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert!(entries.iter().all(|entry| entry.is_directory));
+        assert_eq!(namespace.list("/a").unwrap()[0].length, 10);
+        assert_eq!(namespace.status("/a/f").unwrap().name, "f");
+        assert_eq!(namespace.status("/").unwrap().name, "/");
+        let directories: Vec<String> = namespace
+            .directories()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(directories, vec!["/a", "/b", "/b/c"]);
+        assert_eq!(namespace.files(), vec![("/a/f".to_string(), &file(10))]);
+
+        // removing a directory takes everything under it along
+        let removed = namespace.remove("/a").unwrap();
+        assert_eq!(removed.files("/a"), vec![("/a/f".to_string(), &file(10))]);
+        assert!(namespace.get("/a/f").unwrap().is_none());
+        assert!(namespace.remove("/").is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut namespace = Namespace::new(Attributes::default());
+        namespace
+            .mkdir("/a/b", true, &Attributes::default())
+            .unwrap();
+        namespace
+            .mkdir("/c", false, &Attributes::default())
+            .unwrap();
+        namespace.set_file("/a/b/f", file(10)).unwrap();
+        namespace.set_file("/g", file(20)).unwrap();
+
+        assert!(namespace.check_rename("/a", "/a/b/a", false).is_err());
+        assert!(namespace.check_rename("/x", "/y", false).is_err());
+        assert!(namespace.check_rename("/a", "/x/a", false).is_err());
+        assert!(namespace.check_rename("/", "/x", false).is_err());
+
+        // nothing is replaced without overwrite, and a directory only replaces an empty one
+        assert!(namespace.check_rename("/g", "/a/b/f", false).is_err());
+        assert!(namespace.check_rename("/g", "/a/b/f", true).is_ok());
+        assert!(namespace.check_rename("/g", "/c", true).is_err());
+        assert!(namespace.check_rename("/a", "/c", true).is_ok());
+        assert!(namespace.check_rename("/c", "/a", true).is_err());
+
+        // moving a directory takes everything under it along
+        namespace.rename("/a", "/c").unwrap();
+        assert!(namespace.get("/a").unwrap().is_none());
+        assert_eq!(namespace.get_file("/c/b/f").unwrap(), &file(10));
+        let replaced = namespace.rename("/g", "/c/b/f").unwrap();
+        assert_eq!(replaced, Some(INode::File(file(10))));
+        assert_eq!(namespace.files(), vec![("/c/b/f".to_string(), &file(20))]);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::namenode::acl::{AclEntryType, AclScope};
+use crate::namenode::namespace::Attributes;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Permission to read a file or list a directory
+pub const READ: u32 = 0o4;
+
+/// Permission to write a file or add and remove entries in a directory
+pub const WRITE: u32 = 0o2;
+
+/// Permission to look up entries in a directory
+pub const EXECUTE: u32 = 0o1;
+
+/// Every permission
+pub const ALL: u32 = READ | WRITE | EXECUTE;
+
+/// Members of this group are superusers, along with the user the namenode runs as
+pub const SUPERGROUP: &str = "supergroup";
+
+/// File users' groups are looked up in when the namenode isn't given one
+pub const DEFAULT_GROUPS_FILE: &str = "/etc/group";
+
+/// The user making a request, and the groups they belong to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Caller {
+    pub user: String,
+    pub groups: Vec<String>,
+}
+
+impl Caller {
+    pub fn new(user: &str, groups: &[&str]) -> Self {
+        Self {
+            user: user.to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    pub fn is_member(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g == group)
+    }
+
+    /// Returns true if the caller has every permission in `access` to a file or directory
+    /// The owner gets the owner bits, then a named user entry applies, then the owning group and
+    /// named group entries the caller is in, any of which can grant access, and otherwise the
+    /// other bits; named and group entries are limited by the mask
+    fn allows(&self, attributes: &Attributes, access: u32) -> bool {
+        let mode = attributes.mode;
+        if self.user == attributes.owner {
+            return (mode >> 6) & access == access;
+        }
+
+        let acl: Vec<_> = attributes
+            .acl
+            .iter()
+            .filter(|entry| entry.scope == AclScope::Access)
+            .collect();
+        if acl.is_empty() {
+            return if self.is_member(&attributes.group) {
+                (mode >> 3) & access == access
+            } else {
+                mode & access == access
+            };
+        }
+
+        let mask = (mode >> 3) & ALL;
+        let named_user = acl
+            .iter()
+            .find(|entry| entry.kind == AclEntryType::User && entry.name == self.user);
+        if let Some(entry) = named_user {
+            return entry.permissions & mask & access == access;
+        }
+        let groups: Vec<_> = acl
+            .iter()
+            .filter(|entry| entry.kind == AclEntryType::Group)
+            .filter(|entry| match entry.name.as_str() {
+                "" => self.is_member(&attributes.group),
+                name => self.is_member(name),
+            })
+            .collect();
+        if !groups.is_empty() {
+            return groups
+                .iter()
+                .any(|entry| entry.permissions & mask & access == access);
+        }
+        mode & access == access
+    }
+
+    /// Checks that the caller has every permission in `access` to the file or directory at `path`
+    pub fn check(
+        &self,
+        path: &str,
+        attributes: &Attributes,
+        access: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.allows(attributes, access) {
+            Ok(())
+        } else {
+            Err(PermissionDenied::new(self, path, &access_string(access)).into())
+        }
+    }
+
+    /// Checks that the caller owns the file or directory at `path`
+    pub fn check_owner(&self, path: &str, attributes: &Attributes) -> Result<(), Box<dyn Error>> {
+        if self.user == attributes.owner {
+            Ok(())
+        } else {
+            Err(PermissionDenied::new(self, path, "owner").into())
+        }
+    }
+}
+
+/// Groups each user belongs to, as the namenode sees them
+/// Clients only say which user they act as and their groups are looked up here, so a client can't
+/// claim to be in a group it isn't, such as the supergroup
+#[derive(Debug, Default)]
+pub struct GroupMapping {
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl GroupMapping {
+    /// Reads the groups of each user from a file in the format of `/etc/group`, where each line is
+    /// `group:password:gid:user,user,...`
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read groups from {}: {}", path.display(), e))?;
+        Ok(Self::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Self {
+        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (group, members) = match line.split(':').collect::<Vec<_>>()[..] {
+                [group, _, _, members, ..] => (group, members),
+                _ => continue,
+            };
+            for user in members.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+                groups
+                    .entry(user.to_string())
+                    .or_default()
+                    .push(group.to_string());
+            }
+        }
+        Self { groups }
+    }
+
+    /// Returns the caller acting as `user`, in the groups the mapping gives them
+    pub fn caller(&self, user: &str) -> Caller {
+        Caller {
+            user: user.to_string(),
+            groups: self.groups.get(user).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Formats a set of permissions like `ls -l` does for one class, e.g. `r-x`
+fn access_string(access: u32) -> String {
+    [(READ, 'r'), (WRITE, 'w'), (EXECUTE, 'x')]
+        .iter()
+        .map(|(bit, c)| if access & bit != 0 { *c } else { '-' })
+        .collect()
+}
+
+/// Error for a request the caller doesn't have permission to make
+#[derive(Debug)]
+pub struct PermissionDenied(String);
+
+impl PermissionDenied {
+    pub fn new(caller: &Caller, path: &str, access: &str) -> Self {
+        Self(format!(
+            "Permission denied: user={}, access={}, path={}",
+            caller.user, access, path
+        ))
+    }
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for PermissionDenied {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::namenode::acl::AclEntry;
+
+    #[test]
+    fn test_check() {
+        let attributes = Attributes {
+            owner: "alice".to_string(),
+            group: "staff".to_string(),
+            mode: 0o751,
+            ..Default::default()
+        };
+        let owner = Caller::new("alice", &[]);
+        let member = Caller::new("bob", &["staff"]);
+        let other = Caller::new("carol", &["users"]);
+
+        assert!(owner.check("/f", &attributes, ALL).is_ok());
+        assert!(member.check("/f", &attributes, READ | EXECUTE).is_ok());
+        assert!(member.check("/f", &attributes, WRITE).is_err());
+        assert!(other.check("/f", &attributes, EXECUTE).is_ok());
+        assert!(other.check("/f", &attributes, READ).is_err());
+        assert!(owner.check_owner("/f", &attributes).is_ok());
+        assert!(member.check_owner("/f", &attributes).is_err());
+
+        assert_eq!(access_string(READ | EXECUTE), "r-x");
+    }
+
+    #[test]
+    fn test_group_mapping() {
+        let mapping = GroupMapping::parse(
+            "# groups\nstaff:x:50:alice,bob\nsupergroup:x:51:alice\nusers:x:100:\nbad line\n",
+        );
+        assert_eq!(
+            mapping.caller("alice"),
+            Caller::new("alice", &["staff", SUPERGROUP])
+        );
+        assert_eq!(mapping.caller("bob"), Caller::new("bob", &["staff"]));
+        assert_eq!(mapping.caller("carol"), Caller::new("carol", &[]));
+    }
+
+    #[test]
+    fn test_check_acl() {
+        let entry = |kind, name: &str, permissions| {
+            AclEntry::new(AclScope::Access, kind, name, permissions)
+        };
+        // the mask in the group bits limits carol's entry to reading
+        let attributes = Attributes {
+            owner: "alice".to_string(),
+            group: "staff".to_string(),
+            mode: 0o640,
+            acl: vec![
+                entry(AclEntryType::User, "carol", ALL),
+                entry(AclEntryType::Group, "", READ),
+                entry(AclEntryType::Group, "admins", READ | WRITE),
+            ],
+            ..Default::default()
+        };
+        let carol = Caller::new("carol", &[]);
+        let admin = Caller::new("dave", &["staff", "admins"]);
+        let member = Caller::new("bob", &["staff"]);
+        let other = Caller::new("erin", &[]);
+
+        assert!(carol.check("/f", &attributes, READ).is_ok());
+        assert!(carol.check("/f", &attributes, WRITE).is_err());
+        assert!(admin.check("/f", &attributes, READ).is_ok());
+        assert!(admin.check("/f", &attributes, WRITE).is_err());
+        assert!(member.check("/f", &attributes, READ).is_ok());
+        assert!(other.check("/f", &attributes, READ).is_err());
+
+        let attributes = Attributes {
+            mode: 0o660,
+            ..attributes
+        };
+        assert!(admin.check("/f", &attributes, READ | WRITE).is_ok());
+        assert!(member.check("/f", &attributes, WRITE).is_err());
+    }
+}
@@ -1,20 +1,215 @@
+use crate::namenode::acl::{self, AclChange, AclEntry};
 use crate::namenode::block_records::BlockRecords;
-use std::collections::HashMap;
+use crate::namenode::edit_log::EditLog;
+use crate::namenode::events::{self, EventLog, Subscription};
+use crate::namenode::leases::LeaseManager;
+use crate::namenode::namespace::{
+    self, Access, AlreadyExists, Attributes, INode, INodeStatus, Namespace, NotFound,
+};
+use crate::namenode::permission::{
+    Caller, PermissionDenied, ALL, EXECUTE, READ, SUPERGROUP, WRITE,
+};
+use crate::proto::namespace_event::EventType;
+use crate::proto::{
+    edit_log_op, BlockEntry, DirectoryEntry, FileEntry, FileVersion, FsImage, NamespaceEvent,
+    RenameOp, SetAclOp, SetOwnerOp, SetPermissionOp, SetTimesOp, UnderConstruction,
+};
+use std::collections::{HashMap, HashSet};
 // for atomic counter for id generation
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
-use std::collections::hash_map::DefaultHasher;
 use std::error::Error;
-use std::hash::{Hash, Hasher};
-use std::sync::atomic::AtomicUsize;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 use std::sync::{atomic, Mutex, RwLock};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time to wait for a scheduled copy of a block to show up before scheduling another one
+const REPLICATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Permission bits of new files
+const FILE_MODE: u32 = 0o644;
+
+/// Permission bits of new directories
+const DIRECTORY_MODE: u32 = 0o755;
+
+/// Permission bits new files and directories are created with in a directory with a default ACL,
+/// which limit the permissions they inherit from it
+const FILE_CREATE_MODE: u32 = 0o666;
+const DIRECTORY_CREATE_MODE: u32 = 0o777;
+
+/// How far a file's access time may fall behind before a read updates it, so that every read
+/// doesn't have to be written to the edit log
+const ACCESS_TIME_PRECISION: Duration = Duration::from_secs(60 * 60);
+
+/// Time a client can go without renewing its lease before another client can take over the files
+/// it is writing
+const LEASE_SOFT_LIMIT: Duration = Duration::from_secs(60);
+
+/// Returns the current time in milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Error for a file that needs new blocks while no datanode is live to store them
+#[derive(Debug)]
+pub struct NoLiveDatanodes;
+
+impl fmt::Display for NoLiveDatanodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No live datanodes to store the file's blocks on")
+    }
+}
+
+impl Error for NoLiveDatanodes {}
+
+/// Liveness of a datanode, judged from how long ago it last sent a heartbeat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataNodeState {
+    Live,
+    /// Missed a few heartbeats; no new blocks are placed on it
+    Stale,
+    /// Missed heartbeats for long enough that it is considered gone
+    Dead,
+}
 
 #[derive(Clone)]
 pub struct DataNodeInfo {
     pub addr: String,
-    pub alive: bool,
+    pub state: DataNodeState,
+}
+
+/// A block that a datanode has been asked to copy to other datanodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicationTask {
+    pub block_id: u64,
+    pub targets: Vec<String>,
+}
+
+/// A block of a file, along with the datanodes holding it
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileBlock {
+    pub block_id: u64,
+    /// Generation stamp of the block's current contents
+    pub generation_stamp: u64,
+    /// Offset of the block's first byte in the file
+    pub offset: usize,
+    pub length: usize,
+    pub addrs: Vec<String>,
+}
+
+/// The blocks a file is made of
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileRecord {
+    /// Length of the file in bytes
+    pub length: usize,
+    /// Size of every block of the file but the last
+    pub block_size: usize,
+    /// Ids of the file's blocks, in order
+    pub block_ids: Vec<u64>,
+    /// Number of replicas kept of each block
+    pub replication: usize,
+    pub attributes: Attributes,
+    /// The client writing the file, until it completes it
+    pub under_construction: Option<UnderConstruction>,
+}
+
+impl FileRecord {
+    /// Returns the offset and length of the block at `index`
+    fn block_range(&self, index: usize) -> (usize, usize) {
+        let offset = index * self.block_size;
+        let length = std::cmp::min(self.block_size, self.length.saturating_sub(offset));
+        (offset, length)
+    }
+
+    /// Returns the file as it is persisted, with the current generation stamp of each block
+    fn entry(&self, path: &str, block_records: &BlockRecords) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            length: self.length as i64,
+            block_size: self.block_size as i64,
+            blocks: self
+                .block_ids
+                .iter()
+                .map(|block_id| BlockEntry {
+                    block_id: *block_id,
+                    generation_stamp: block_records
+                        .get_generation_stamp(block_id)
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            replication: self.replication as u32,
+            attributes: Some((&self.attributes).into()),
+            under_construction: self.under_construction.clone(),
+        }
+    }
+
+    /// Returns the file's length and the current generation stamp of each of its blocks
+    fn version(&self, block_records: &BlockRecords) -> FileVersion {
+        let entry = self.entry("", block_records);
+        FileVersion {
+            length: entry.length,
+            blocks: entry.blocks,
+        }
+    }
+
+    /// Returns the version of the file its writer's changes are undone to if it abandons them
+    fn previous(&self) -> Option<&FileVersion> {
+        self.under_construction
+            .as_ref()
+            .and_then(|under_construction| under_construction.previous.as_ref())
+    }
+
+    /// Returns the id, offset and length of every block the file holds on to, which are its own
+    /// blocks followed by any other blocks of its previous version
+    fn held_blocks(&self) -> Vec<(u64, usize, usize)> {
+        let mut blocks: Vec<(u64, usize, usize)> = self
+            .block_ids
+            .iter()
+            .enumerate()
+            .map(|(i, block_id)| {
+                let (offset, length) = self.block_range(i);
+                (*block_id, offset, length)
+            })
+            .collect();
+        if let Some(previous) = self.previous() {
+            for (i, block) in previous.blocks.iter().enumerate() {
+                if !self.block_ids.contains(&block.block_id) {
+                    let (offset, length) = version_block_range(previous, self.block_size, i);
+                    blocks.push((block.block_id, offset, length));
+                }
+            }
+        }
+        blocks
+    }
+
+    fn layout(&self, blocks: Vec<FileBlock>) -> FileLayout {
+        FileLayout {
+            length: self.length,
+            block_size: self.block_size,
+            blocks,
+        }
+    }
+}
+
+/// Returns the offset and length of the block at `index` of a version of a file
+fn version_block_range(version: &FileVersion, block_size: usize, index: usize) -> (usize, usize) {
+    let offset = index * block_size;
+    let length = std::cmp::min(block_size, (version.length as usize).saturating_sub(offset));
+    (offset, length)
+}
+
+/// A file's blocks along with the datanodes holding each of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileLayout {
+    pub length: usize,
+    pub block_size: usize,
+    pub blocks: Vec<FileBlock>,
 }
 
 /// recordkeeper/bookkeeper for namenode information
@@ -32,14 +227,47 @@ pub struct NameNodeRecords {
     block_records: RwLock<BlockRecords>,
 
     datanode_id_counter: AtomicUsize,
+
+    /// next block id to hand out
+    block_id_counter: AtomicU64,
+
+    /// next generation stamp to hand out, shared by every block
+    generation_stamp_counter: AtomicU64,
+
     /// map from datanode ip address to time of last message
     heartbeat_records: Mutex<HashMap<String, SystemTime>>,
 
-    /// map from file path to block ids
-    file_records: Mutex<HashMap<String, usize>>,
+    /// directory tree of every file and the blocks it is made of
+    namespace: Mutex<Namespace>,
+
+    /// map from datanode ip address to the copies it should make on its next heartbeat
+    replication_tasks: Mutex<HashMap<String, Vec<ReplicationTask>>>,
+
+    /// map from datanode ip address to the blocks it should delete on its next heartbeat
+    invalidated_blocks: Mutex<HashMap<String, Vec<u64>>>,
+
+    /// map from datanode ip address to the blocks clients failed to read off it, which it should
+    /// verify on its next heartbeat
+    suspect_blocks: Mutex<HashMap<String, Vec<u64>>>,
+
+    /// Which client is writing each file
+    leases: Mutex<LeaseManager>,
+
+    /// Time a client can go without renewing its lease before its files can be taken over
+    lease_soft_limit: Duration,
 
     /// Number of replicas to store for each block
     replication_count: usize,
+
+    /// User the namenode runs as, which owns the root directory and passes every permission check
+    superuser: String,
+
+    /// Write-ahead log of namespace changes, or None if the namespace is kept in memory only
+    /// Held for the whole of every namespace change so checkpoints never see one half applied
+    edit_log: Mutex<Option<EditLog>>,
+
+    /// Recent namespace changes, sent on to everyone watching the namespace
+    events: Mutex<EventLog>,
 }
 
 impl Default for NameNodeRecords {
@@ -50,16 +278,116 @@ impl Default for NameNodeRecords {
 
 impl NameNodeRecords {
     pub fn new(replication_count: usize, block_size: usize) -> Self {
+        let superuser = std::env::var("USER").unwrap_or_else(|_| "root".to_string());
+        let root_attributes = Self::attributes_for(&superuser, SUPERGROUP, DIRECTORY_MODE);
         Self {
             block_size,
             datanodes: Mutex::new(HashMap::new()),
             datanode_ids: Mutex::new(HashMap::new()),
             block_records: RwLock::new(BlockRecords::new()),
             datanode_id_counter: AtomicUsize::new(0),
+            block_id_counter: AtomicU64::new(1),
+            generation_stamp_counter: AtomicU64::new(1),
             heartbeat_records: Mutex::new(HashMap::new()),
-            file_records: Mutex::new(HashMap::new()),
+            namespace: Mutex::new(Namespace::new(root_attributes)),
+            replication_tasks: Mutex::new(HashMap::new()),
+            invalidated_blocks: Mutex::new(HashMap::new()),
+            suspect_blocks: Mutex::new(HashMap::new()),
+            leases: Mutex::new(LeaseManager::new()),
+            lease_soft_limit: LEASE_SOFT_LIMIT,
             replication_count,
+            superuser,
+            edit_log: Mutex::new(None),
+            events: Mutex::new(EventLog::new(0)),
+        }
+    }
+
+    /// Opens a namespace persisted in `name_dir`, rebuilding it from the last checkpoint and the
+    /// edits made after it
+    /// Block locations aren't persisted, and come back as datanodes send their block reports
+    pub fn open(
+        replication_count: usize,
+        block_size: usize,
+        name_dir: &Path,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (edit_log, image, edits) = EditLog::open(name_dir)?;
+        let records = Self::new(replication_count, block_size);
+        records
+            .block_id_counter
+            .fetch_max(image.next_block_id, atomic::Ordering::SeqCst);
+        records
+            .generation_stamp_counter
+            .fetch_max(image.next_generation_stamp, atomic::Ordering::SeqCst);
+        for dir in image.directories {
+            records.apply_mkdir(dir)?;
+        }
+        for file in image.files {
+            records.set_file(file, &[])?;
+        }
+        for edit in edits {
+            match edit {
+                edit_log_op::Op::SetFile(file) => {
+                    records.set_file(file, &[])?;
+                }
+                edit_log_op::Op::Delete(path) => {
+                    records.delete(&path)?;
+                }
+                edit_log_op::Op::Mkdir(dir) => {
+                    records.apply_mkdir(dir)?;
+                }
+                edit_log_op::Op::Rename(RenameOp {
+                    source,
+                    destination,
+                }) => {
+                    records.move_path(&source, &destination)?;
+                }
+                edit_log_op::Op::SetTimes(SetTimesOp { path, mtime, atime }) => {
+                    records
+                        .namespace
+                        .lock()
+                        .unwrap()
+                        .set_times(&path, mtime, atime)?;
+                }
+                edit_log_op::Op::SetPermission(SetPermissionOp { path, mode }) => {
+                    records
+                        .namespace
+                        .lock()
+                        .unwrap()
+                        .set_permission(&path, mode)?;
+                }
+                edit_log_op::Op::SetOwner(op) => {
+                    records.apply_set_owner(&op)?;
+                }
+                edit_log_op::Op::SetAcl(SetAclOp { path, mode, acl }) => {
+                    records.namespace.lock().unwrap().set_acl(
+                        &path,
+                        mode,
+                        acl.iter().map(AclEntry::from).collect(),
+                    )?;
+                }
+            }
+        }
+        let namespace = records.namespace.lock().unwrap();
+        // clients writing files when the namenode stopped get until the hard limit to complete them
+        let mut leases = records.leases.lock().unwrap();
+        for (path, record) in namespace.files() {
+            if let Some(under_construction) = &record.under_construction {
+                leases.add(&under_construction.client_name, &path);
+            }
         }
+        println!(
+            "Recovered {} files from {}",
+            namespace.files().len(),
+            name_dir.display()
+        );
+        drop(leases);
+        drop(namespace);
+
+        // events carry the transaction ids of their edits, so watchers can resume across restarts
+        *records.events.lock().unwrap() = EventLog::new(edit_log.last_txid());
+        *records.edit_log.lock().unwrap() = Some(edit_log);
+        records.checkpoint()?;
+        Ok(records)
     }
 
     pub async fn get_datanode_statuses(&self) -> Vec<DataNodeInfo> {
@@ -68,148 +396,1276 @@ impl NameNodeRecords {
         statuses
     }
 
-    /// returns hash(file_name || uid)
-    fn get_file_id(file_name: &str) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        file_name.hash(&mut hasher);
-        hasher.finish()
+    /// Returns the addresses of the datanodes new blocks can be placed on
+    async fn live_datanodes(&self) -> Vec<String> {
+        self.get_datanode_statuses()
+            .await
+            .into_iter()
+            .filter(|datanode| datanode.state == DataNodeState::Live)
+            .map(|datanode| datanode.addr)
+            .collect()
+    }
+
+    /// Returns a fresh block id, never handed out before
+    fn next_block_id(&self) -> u64 {
+        self.block_id_counter.fetch_add(1, atomic::Ordering::SeqCst)
+    }
+
+    /// Returns a fresh generation stamp, larger than any handed out before
+    fn next_generation_stamp(&self) -> u64 {
+        self.generation_stamp_counter
+            .fetch_add(1, atomic::Ordering::SeqCst)
+    }
+
+    /// Returns the attributes of a file or directory created now by `owner`
+    fn attributes_for(owner: &str, group: &str, mode: u32) -> Attributes {
+        let time = now();
+        Attributes {
+            owner: owner.to_string(),
+            group: group.to_string(),
+            mode,
+            ctime: time,
+            mtime: time,
+            atime: time,
+            acl: vec![],
+        }
+    }
+
+    /// Returns true if the caller passes every permission check
+    fn is_superuser(&self, caller: &Caller) -> bool {
+        caller.user == self.superuser || caller.is_member(SUPERGROUP)
+    }
+
+    /// Checks that `caller` has the permissions in `access` along a path
+    fn check_permission(
+        &self,
+        namespace: &Namespace,
+        path: &str,
+        caller: &Caller,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.is_superuser(caller) {
+            return Ok(());
+        }
+        namespace.check_permission(path, caller, &access)
+    }
+
+    /// Returns the attributes of a file or directory `caller` creates at a path, which belongs to
+    /// the group of the directory it's created in and inherits its default ACL
+    fn attributes_of_new(
+        namespace: &Namespace,
+        path: &str,
+        caller: &Caller,
+        is_directory: bool,
+    ) -> Result<Attributes, Box<dyn Error>> {
+        let parent = namespace.ancestor(path)?.attributes();
+        let (mode, create_mode) = match is_directory {
+            true => (DIRECTORY_MODE, DIRECTORY_CREATE_MODE),
+            false => (FILE_MODE, FILE_CREATE_MODE),
+        };
+        let mut attributes = Self::attributes_for(&caller.user, &parent.group, mode);
+        if let Some((mode, acl)) = acl::inherit(parent, create_mode, is_directory) {
+            attributes.mode = mode;
+            attributes.acl = acl;
+        }
+        Ok(attributes)
+    }
+
+    /// Appends a namespace change to the edit log, if the namespace is persisted
+    fn log_edit(edit_log: &mut Option<EditLog>, op: edit_log_op::Op) -> Result<(), Box<dyn Error>> {
+        match edit_log {
+            Some(edit_log) => edit_log.append(op),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells watchers about a change just made to the namespace, under the transaction id it was
+    /// logged with
+    fn publish(
+        &self,
+        edit_log: &Option<EditLog>,
+        event_type: EventType,
+        path: &str,
+        destination: Option<&str>,
+        is_directory: bool,
+    ) {
+        let mut events = self.events.lock().unwrap();
+        let txid = match edit_log {
+            Some(edit_log) => edit_log.last_txid(),
+            None => events.last_txid() + 1,
+        };
+        let normalize =
+            |path: &str| namespace::normalize(path).unwrap_or_else(|_| path.to_string());
+        events.publish(NamespaceEvent {
+            txid,
+            r#type: event_type.into(),
+            path: normalize(path),
+            destination: destination.map(normalize).unwrap_or_default(),
+            is_directory,
+            time: now(),
+        });
+    }
+
+    /// Returns every change at or under a path after transaction `since_txid`, or none if it is 0,
+    /// along with the normalized path and a receiver for every change after those, which may be
+    /// anywhere
+    pub async fn watch(
+        &self,
+        path: &str,
+        since_txid: u64,
+        caller: &Caller,
+    ) -> Result<(String, Subscription), Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            inode: READ,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        drop(namespace);
+        let path = namespace::normalize(path)?;
+        let mut subscription = self.events.lock().unwrap().subscribe(since_txid)?;
+        subscription
+            .history
+            .retain(|event| events::matches(event, &path));
+        Ok((path, subscription))
     }
 
-    /// Adds a file to the system, and returns the addresses of the datanodes its chunks live on
-    /// The index is the index of the block in the file
-    /// i.e. the String at index 0 is a list of addresses that the first block lives on
+    /// Adds a file to the system, and returns the layout of its blocks along with the datanodes to
+    /// store them on
+    /// The file is under construction, and the client `holder` has the lease on it, until the
+    /// client completes it
     pub async fn add_file(
         &self,
         file_path: &str,
         file_size: usize,
-    ) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
-        let num_blocks = (file_size + (self.block_size - 1)) / self.block_size;
-        let mut addrs = Vec::<Vec<String>>::with_capacity(num_blocks);
+        caller: &Caller,
+        holder: &str,
+    ) -> Result<FileLayout, Box<dyn Error>> {
+        let live_datanodes = self.live_datanodes().await;
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            parent: WRITE,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, file_path, caller, access)?;
+        if namespace.get(file_path)?.is_some() {
+            return Err(AlreadyExists("File already exists").into());
+        }
+        namespace.check_parent(file_path)?;
+        let attributes = Self::attributes_of_new(&namespace, file_path, caller, false)?;
+        drop(namespace);
+
+        let num_blocks = file_size.div_ceil(self.block_size);
+        if num_blocks > 0 && live_datanodes.is_empty() {
+            return Err(NoLiveDatanodes.into());
+        }
+        let file = FileEntry {
+            path: file_path.to_string(),
+            length: file_size as i64,
+            block_size: self.block_size as i64,
+            blocks: (0..num_blocks)
+                .map(|_| BlockEntry {
+                    block_id: self.next_block_id(),
+                    generation_stamp: self.next_generation_stamp(),
+                })
+                .collect(),
+            replication: self.replication_count as u32,
+            attributes: Some((&attributes).into()),
+            under_construction: Some(UnderConstruction {
+                client_name: holder.to_string(),
+                new_file: true,
+                previous: None,
+            }),
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        let (layout, _) = self.set_file(file, &live_datanodes)?;
+        let path = namespace::normalize(file_path)?;
+        self.leases.lock().unwrap().add(holder, &path);
+        self.publish(&edit_log, EventType::Create, &path, None, false);
+        Ok(layout)
+    }
+
+    /// Updates a file in the system and returns the layout of its blocks along with the datanodes
+    /// to write them to, and the blocks of an earlier unfinished write that should be deleted from
+    /// their datanodes
+    /// The file is rewritten to new blocks, so its last completed version is kept until the
+    /// rewrite is completed and the file goes back to it if the rewrite is abandoned. The file
+    /// keeps the block size it was created with
+    /// The file is under construction, and the client `holder` has the lease on it, until the
+    /// client completes it, which fails while another client holds the lease
+    pub async fn update_file(
+        &self,
+        file_path: &str,
+        file_size: usize,
+        caller: &Caller,
+        holder: &str,
+    ) -> Result<(FileLayout, Vec<FileBlock>), Box<dyn Error>> {
+        let live_datanodes = self.live_datanodes().await;
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        // a missing file is created, which needs the same permissions as creating it directly
+        let access = match namespace.get(file_path)? {
+            Some(_) => Access {
+                inode: WRITE,
+                ..Default::default()
+            },
+            None => Access {
+                parent: WRITE,
+                ..Default::default()
+            },
+        };
+        self.check_permission(&namespace, file_path, caller, access)?;
+        drop(namespace);
+        let path = namespace::normalize(file_path)?;
+        self.take_over_lease(&mut edit_log, &path, holder)?;
+
+        let namespace = self.namespace.lock().unwrap();
+        let event_type = match namespace.get(file_path)? {
+            Some(_) => EventType::Update,
+            None => EventType::Create,
+        };
+        let prev_record = match namespace.get(file_path)? {
+            Some(INode::File(record)) => {
+                let mut record = record.clone();
+                record.attributes.mtime = now();
+                // a file that was never completed stays a new file, and is deleted if abandoned,
+                // while a file being rewritten again keeps the version it goes back to
+                let previous = match &record.under_construction {
+                    Some(prev) => prev.previous.clone(),
+                    None => Some(record.version(&self.block_records.read().unwrap())),
+                };
+                record.under_construction = Some(UnderConstruction {
+                    client_name: holder.to_string(),
+                    new_file: record
+                        .under_construction
+                        .as_ref()
+                        .is_some_and(|prev| prev.new_file),
+                    previous,
+                });
+                record
+            }
+            Some(INode::Directory(_)) => return Err("Is a directory".into()),
+            None => {
+                namespace.check_parent(file_path)?;
+                FileRecord {
+                    length: 0,
+                    block_size: self.block_size,
+                    block_ids: vec![],
+                    replication: self.replication_count,
+                    attributes: Self::attributes_of_new(&namespace, file_path, caller, false)?,
+                    under_construction: Some(UnderConstruction {
+                        client_name: holder.to_string(),
+                        new_file: true,
+                        previous: None,
+                    }),
+                }
+            }
+        };
+        drop(namespace);
+        let num_blocks = file_size.div_ceil(prev_record.block_size);
+        if num_blocks > 0 && live_datanodes.is_empty() {
+            return Err(NoLiveDatanodes.into());
+        }
+
+        let file = FileEntry {
+            path: file_path.to_string(),
+            length: file_size as i64,
+            block_size: prev_record.block_size as i64,
+            blocks: (0..num_blocks)
+                .map(|_| BlockEntry {
+                    block_id: self.next_block_id(),
+                    generation_stamp: self.next_generation_stamp(),
+                })
+                .collect(),
+            replication: prev_record.replication as u32,
+            attributes: Some((&prev_record.attributes).into()),
+            under_construction: prev_record.under_construction,
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        let result = self.set_file(file, &live_datanodes)?;
+        self.invalidate_blocks(&result.1);
+        self.leases.lock().unwrap().add(holder, &path);
+        self.publish(&edit_log, event_type, &path, None, false);
+        Ok(result)
+    }
+
+    /// Gives the client `holder` the lease on an existing file it is about to append to, and
+    /// returns the layout of all its blocks so the client can read in a partly filled last block
+    /// The file keeps only its full blocks, which the client adds to with `allocate_block`,
+    /// starting with the contents of the partly filled block. The file goes back to its whole
+    /// previous version if the append is abandoned
+    /// The file is under construction until the client completes it, and the append fails while
+    /// another client holds the lease
+    pub async fn append_file(
+        &self,
+        file_path: &str,
+        caller: &Caller,
+        holder: &str,
+    ) -> Result<FileLayout, Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            inode: READ | WRITE,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, file_path, caller, access)?;
+        namespace.get_file(file_path)?;
+        drop(namespace);
+        let path = namespace::normalize(file_path)?;
+        self.take_over_lease(&mut edit_log, &path, holder)?;
+
+        // a file taken over from a client creating it has been deleted
+        let mut record = self.namespace.lock().unwrap().get_file(&path)?.clone();
+        if record.under_construction.is_some() {
+            return Err(format!("{} is already being written", path).into());
+        }
+        let block_records = self.block_records.read().unwrap();
+        let mut blocks = Vec::<FileBlock>::with_capacity(record.block_ids.len());
+        for (i, block_id) in record.block_ids.iter().enumerate() {
+            let (offset, length) = record.block_range(i);
+            blocks.push(FileBlock {
+                block_id: *block_id,
+                generation_stamp: block_records
+                    .get_generation_stamp(block_id)
+                    .unwrap_or_default(),
+                offset,
+                length,
+                addrs: block_records.get_block_datanodes(block_id)?,
+            });
+        }
+        let mut layout = record.layout(blocks);
+        record.under_construction = Some(UnderConstruction {
+            client_name: holder.to_string(),
+            new_file: false,
+            previous: Some(record.version(&block_records)),
+        });
+        let full_blocks = record.length / record.block_size;
+        record.block_ids.truncate(full_blocks);
+        record.length = full_blocks * record.block_size;
+        let file = record.entry(&path, &block_records);
+        drop(block_records);
+
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        self.set_file(file, &[])?;
+        self.leases.lock().unwrap().add(holder, &path);
+        self.publish(&edit_log, EventType::Update, &path, None, false);
+        drop(edit_log);
+
+        for block in &mut layout.blocks {
+            self.sort_by_state(&mut block.addrs);
+        }
+        Ok(layout)
+    }
+
+    /// Adds a block of `length` bytes to the end of a file the client `holder` is writing, and
+    /// returns it along with the datanodes to write it to
+    /// Every block of the file before it must be full
+    pub async fn allocate_block(
+        &self,
+        file_path: &str,
+        length: usize,
+        holder: &str,
+    ) -> Result<FileBlock, Box<dyn Error>> {
+        let live_datanodes = self.live_datanodes().await;
+        let path = namespace::normalize(file_path)?;
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let record = match self.namespace.lock().unwrap().get(&path)? {
+            Some(INode::File(record)) => record.clone(),
+            Some(INode::Directory(_)) => return Err("Is a directory".into()),
+            None => return Err(NotFound("File does not exist").into()),
+        };
+        match &record.under_construction {
+            Some(under_construction) if under_construction.client_name == holder => {}
+            _ => return Err(format!("{} does not hold the lease on {}", holder, path).into()),
+        }
+        if length == 0 || length > record.block_size {
+            return Err(format!(
+                "Blocks of {} hold 1 to {} bytes, not {}",
+                path, record.block_size, length
+            )
+            .into());
+        }
+        if record.length != record.block_ids.len() * record.block_size {
+            return Err(format!("{} already ends in a partly filled block", path).into());
+        }
+        if live_datanodes.is_empty() {
+            return Err(NoLiveDatanodes.into());
+        }
+
+        let mut file = record.entry(&path, &self.block_records.read().unwrap());
+        file.blocks.push(BlockEntry {
+            block_id: self.next_block_id(),
+            generation_stamp: self.next_generation_stamp(),
+        });
+        file.length += length as i64;
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        let (mut layout, _) = self.set_file(file, &live_datanodes)?;
+        Ok(layout.blocks.pop().expect("File has the block just added"))
+    }
+
+    /// Checks that no other client is writing a file before `holder` starts writing it
+    /// A client that hasn't renewed its lease within the soft limit has its write to the file
+    /// recovered, so a crashed writer doesn't lock the file until the hard limit
+    fn take_over_lease(
+        &self,
+        edit_log: &mut Option<EditLog>,
+        path: &str,
+        holder: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let leases = self.leases.lock().unwrap();
+        let current = match leases.holder(path) {
+            Some(current) if current != holder => current.to_string(),
+            _ => return Ok(()),
+        };
+        if !leases.is_expired(path, self.lease_soft_limit) {
+            return Err(format!("{} is being written by {}", path, current).into());
+        }
+        drop(leases);
+
+        println!("Taking over the lease of {} on {}", current, path);
+        self.recover_file(edit_log, path)
+    }
+
+    /// Completes a file a client has finished writing, given every block as it was written along
+    /// with the datanodes that acknowledged it, which must match the file's layout
+    /// The file can be read once it is completed, and the client's lease on it is released
+    pub async fn complete_file(
+        &self,
+        file_path: &str,
+        holder: &str,
+        blocks: &[FileBlock],
+    ) -> Result<(), Box<dyn Error>> {
+        let path = namespace::normalize(file_path)?;
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        let mut record = match namespace.get(&path)? {
+            Some(INode::File(record)) => record.clone(),
+            Some(INode::Directory(_)) => return Err("Is a directory".into()),
+            None => return Err(NotFound("File does not exist").into()),
+        };
+        drop(namespace);
+        match &record.under_construction {
+            Some(under_construction) if under_construction.client_name == holder => {}
+            _ => return Err(format!("{} does not hold the lease on {}", holder, path).into()),
+        }
+        if blocks.len() != record.block_ids.len() {
+            return Err(format!(
+                "{} has {} blocks but {} were written",
+                path,
+                record.block_ids.len(),
+                blocks.len()
+            )
+            .into());
+        }
+
+        let block_records = self.block_records.read().unwrap();
+        for (i, (block, block_id)) in blocks.iter().zip(&record.block_ids).enumerate() {
+            let generation_stamp = block_records.get_generation_stamp(block_id);
+            if block.block_id != *block_id || Some(block.generation_stamp) != generation_stamp {
+                return Err(format!("Block {} of {} was not written", block_id, path).into());
+            }
+            let (_, length) = record.block_range(i);
+            if block.length != length {
+                return Err(format!(
+                    "Block {} is {} bytes long but {} were written",
+                    block_id, length, block.length
+                )
+                .into());
+            }
+            if block.addrs.is_empty() {
+                return Err(format!("No replica of block {} was written", block_id).into());
+            }
+        }
+        record.under_construction = None;
+        let file = record.entry(&path, &block_records);
+        drop(block_records);
+
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        let (_, removed_blocks) = self.set_file(file, &[])?;
+        self.invalidate_blocks(&removed_blocks);
+        let mut block_records = self.block_records.write().unwrap();
+        for block in blocks {
+            if block_records.confirm_replicas(block.block_id, &block.addrs) < record.replication {
+                block_records.queue_replication(block.block_id);
+            }
+        }
+        drop(block_records);
+        self.leases.lock().unwrap().remove(&path);
+        self.publish(&edit_log, EventType::Close, &path, None, false);
+        Ok(())
+    }
+
+    /// Renews a client's lease on every file it is writing, returning false if it has none
+    pub async fn renew_lease(&self, holder: &str) -> bool {
+        self.leases.lock().unwrap().renew(holder)
+    }
+
+    /// Recovers every file whose writer hasn't renewed its lease within `hard_limit`
+    pub async fn recover_expired_leases(&self, hard_limit: Duration) {
+        let expired = self.leases.lock().unwrap().expired(hard_limit);
+        for path in expired {
+            println!("Lease on {} has expired", path);
+            let mut edit_log = self.edit_log.lock().unwrap();
+            if let Err(e) = self.recover_file(&mut edit_log, &path) {
+                println!("Failed to recover {}: {}", path, e);
+            }
+        }
+    }
+
+    /// Closes a file whose writer lost its lease before completing it, releasing the lease
+    /// A file that was being created is deleted, so it's never seen half written, while a file that
+    /// was being rewritten goes back to the version it was last completed with
+    /// A file rewritten before previous versions were kept, whose blocks were rewritten in place,
+    /// is instead cut off before the first block no datanode has reported holding at its current
+    /// generation stamp
+    /// The blocks removed are deleted from their datanodes on their next heartbeat
+    fn recover_file(
+        &self,
+        edit_log: &mut Option<EditLog>,
+        path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        self.leases.lock().unwrap().remove(path);
+        let mut record = match self.namespace.lock().unwrap().get_file(path) {
+            Ok(record) => record.clone(),
+            Err(_) => return Ok(()),
+        };
+        let under_construction = match record.under_construction.take() {
+            Some(under_construction) => under_construction,
+            None => return Ok(()),
+        };
+        let new_file = under_construction.new_file;
 
-        for i in 0..num_blocks {
-            let block_path = format!("{}_{}", file_path, i);
-            let addr = self.add_block(block_path).await?;
-            addrs.push(addr);
+        if new_file {
+            println!("Deleting {}, which was never completed", path);
+            Self::log_edit(edit_log, edit_log_op::Op::Delete(path.to_string()))?;
+            let removed_blocks = self.delete(path)?;
+            self.invalidate_blocks(&removed_blocks);
+            self.publish(edit_log, EventType::Delete, path, None, false);
+            return Ok(());
         }
 
-        let mut file_records = self.file_records.lock().unwrap();
-        file_records.insert(file_path.to_string(), num_blocks);
-        drop(file_records);
+        let block_records = self.block_records.read().unwrap();
+        let mut file = record.entry(path, &block_records);
+        drop(block_records);
+        if let Some(previous) = under_construction.previous {
+            println!(
+                "Returning {} to the {} bytes it was last completed with",
+                path, previous.length
+            );
+            file.length = previous.length;
+            file.blocks = previous.blocks;
+            Self::log_edit(edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+            let (_, removed_blocks) = self.set_file(file, &[])?;
+            self.invalidate_blocks(&removed_blocks);
+            self.publish(edit_log, EventType::Close, path, None, false);
+            return Ok(());
+        }
+
+        let block_records = self.block_records.read().unwrap();
+        let written = record
+            .block_ids
+            .iter()
+            .take_while(|block_id| block_records.has_reported_replica(block_id))
+            .count();
+        drop(block_records);
+        let length = std::cmp::min(record.length, written * record.block_size);
+        file.blocks.truncate(written);
+        file.length = length as i64;
+        println!(
+            "Closing {} at {} bytes, where its last complete block ends",
+            path, length
+        );
+
+        Self::log_edit(edit_log, edit_log_op::Op::SetFile(file.clone()))?;
+        let (_, removed_blocks) = self.set_file(file, &[])?;
+        self.invalidate_blocks(&removed_blocks);
+        self.publish(edit_log, EventType::Close, path, None, false);
+        Ok(())
+    }
+
+    /// Asks the datanodes holding each block to delete it on their next heartbeat
+    fn invalidate_blocks(&self, blocks: &[FileBlock]) {
+        let mut invalidated_blocks = self.invalidated_blocks.lock().unwrap();
+        for block in blocks {
+            for addr in &block.addrs {
+                invalidated_blocks
+                    .entry(addr.clone())
+                    .or_default()
+                    .push(block.block_id);
+            }
+        }
+    }
+
+    /// Returns and clears the blocks a datanode has been asked to verify
+    pub async fn take_suspect_blocks(&self, address: &str) -> Vec<u64> {
+        let mut suspect_blocks = self.suspect_blocks.lock().unwrap();
+        suspect_blocks.remove(address).unwrap_or_default()
+    }
 
-        Ok(addrs)
+    /// Returns and clears the blocks a datanode has been asked to delete
+    pub async fn take_invalidated_blocks(&self, address: &str) -> Vec<u64> {
+        let mut invalidated_blocks = self.invalidated_blocks.lock().unwrap();
+        invalidated_blocks.remove(address).unwrap_or_default()
     }
 
-    /// Adds a new block to block_records and returns a list of datanode addresses to store the block on
-    async fn add_block(&self, block_path: String) -> Result<Vec<String>, Box<dyn Error>> {
-        let file_id = Self::get_file_id(&block_path);
-        let datanodes = self.get_datanode_statuses().await;
+    /// Applies a created or rewritten file to the records, placing any blocks that are new on
+    /// `live_datanodes`, and returns its layout along with the blocks it no longer holds on to
+    fn set_file(
+        &self,
+        file: FileEntry,
+        live_datanodes: &[String],
+    ) -> Result<(FileLayout, Vec<FileBlock>), Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        namespace.check_parent(&file.path)?;
+        let prev_record = namespace.get_file(&file.path).ok().cloned();
+        drop(namespace);
+        let record = FileRecord {
+            length: file.length as usize,
+            block_size: file.block_size as usize,
+            block_ids: file.blocks.iter().map(|block| block.block_id).collect(),
+            replication: file.replication as usize,
+            attributes: file
+                .attributes
+                .as_ref()
+                .map(Attributes::from)
+                .unwrap_or_default(),
+            under_construction: file.under_construction.clone(),
+        };
+
+        let mut blocks = Vec::<FileBlock>::with_capacity(file.blocks.len());
+        for (i, block) in file.blocks.iter().enumerate() {
+            // replayed edits must not hand out their ids and stamps again
+            self.block_id_counter
+                .fetch_max(block.block_id + 1, atomic::Ordering::SeqCst);
+            self.generation_stamp_counter
+                .fetch_max(block.generation_stamp + 1, atomic::Ordering::SeqCst);
+
+            let (offset, length) = record.block_range(i);
+            let exists = self
+                .block_records
+                .read()
+                .unwrap()
+                .contains_block(&block.block_id);
+            blocks.push(if exists {
+                self.restamp_block(block.block_id, block.generation_stamp, offset, length)?
+            } else {
+                self.add_block(
+                    block.block_id,
+                    block.generation_stamp,
+                    offset,
+                    length,
+                    record.replication,
+                    live_datanodes,
+                )?
+            });
+        }
+        if record.under_construction.is_none() {
+            let mut block_records = self.block_records.write().unwrap();
+            for block_id in &record.block_ids {
+                block_records.finish_construction(block_id);
+            }
+        }
+        // the blocks of the version the file goes back to are already in the records, unless
+        // the file is being loaded from a checkpoint
+        for block in record
+            .previous()
+            .map_or(&[][..], |previous| &previous.blocks)
+        {
+            self.block_id_counter
+                .fetch_max(block.block_id + 1, atomic::Ordering::SeqCst);
+            self.generation_stamp_counter
+                .fetch_max(block.generation_stamp + 1, atomic::Ordering::SeqCst);
+            let mut block_records = self.block_records.write().unwrap();
+            if !block_records.contains_block(&block.block_id) {
+                block_records.add_block_to_records(
+                    block.block_id,
+                    block.generation_stamp,
+                    record.replication,
+                    vec![],
+                )?;
+                block_records.finish_construction(&block.block_id);
+            }
+        }
+
+        let mut removed_blocks = vec![];
+        if let Some(prev_record) = prev_record {
+            let held_blocks = record.held_blocks();
+            for (block_id, offset, length) in prev_record.held_blocks() {
+                if !held_blocks.iter().any(|(held, _, _)| *held == block_id) {
+                    removed_blocks.push(self.remove_block(block_id, offset, length)?);
+                }
+            }
+        }
+
+        let mut namespace = self.namespace.lock().unwrap();
+        namespace.set_file(&file.path, record.clone())?;
+        drop(namespace);
+
+        Ok((record.layout(blocks), removed_blocks))
+    }
 
+    /// Adds a new block to the records and chooses the `replication` datanodes to store it on
+    fn add_block(
+        &self,
+        block_id: u64,
+        generation_stamp: u64,
+        offset: usize,
+        length: usize,
+        replication: usize,
+        live_datanodes: &[String],
+    ) -> Result<FileBlock, Box<dyn Error>> {
         // randomly select datanodes to store the block on
-        let mut rng = StdRng::seed_from_u64(file_id);
-        let mut shuffled_datanodes = datanodes.clone();
+        let mut rng = StdRng::seed_from_u64(block_id);
+        let mut shuffled_datanodes = live_datanodes.to_vec();
         shuffled_datanodes.shuffle(&mut rng);
-        let selected_datanodes = shuffled_datanodes
-            .into_iter()
-            .take(self.replication_count)
-            .map(|datanode| datanode.addr)
-            .collect();
+        shuffled_datanodes.truncate(replication);
 
         let mut block_records = self.block_records.write().map_err(|e| e.to_string())?;
-        let datanodes = block_records.add_block_to_records(file_id, selected_datanodes)?;
-        Ok(datanodes)
+        let addrs = block_records.add_block_to_records(
+            block_id,
+            generation_stamp,
+            replication,
+            shuffled_datanodes,
+        )?;
+        Ok(FileBlock {
+            block_id,
+            generation_stamp,
+            offset,
+            length,
+            addrs,
+        })
     }
 
-    /// Updates a file in the system and returns the datanode addresses to edit
-    pub async fn update_file(
+    /// Gives an existing block a new generation stamp ahead of it being rewritten
+    fn restamp_block(
+        &self,
+        block_id: u64,
+        generation_stamp: u64,
+        offset: usize,
+        length: usize,
+    ) -> Result<FileBlock, Box<dyn Error>> {
+        let mut block_records = self.block_records.write().unwrap();
+        let addrs = block_records.get_block_datanodes(&block_id)?;
+        block_records.set_generation_stamp(block_id, generation_stamp);
+        Ok(FileBlock {
+            block_id,
+            generation_stamp,
+            offset,
+            length,
+            addrs,
+        })
+    }
+
+    /// Removes a file from the system and returns the blocks it was made of
+    /// The blocks are also queued for their datanodes to delete, in case the client doesn't
+    pub async fn remove_file(
         &self,
         file_path: &str,
-        file_size: usize,
-    ) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
-        let num_blocks = (file_size + (self.block_size - 1)) / self.block_size;
-        let mut addrs = Vec::<Vec<String>>::with_capacity(num_blocks);
+        caller: &Caller,
+    ) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            parent: WRITE | EXECUTE,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, file_path, caller, access)?;
+        match namespace.get(file_path)? {
+            Some(INode::File(_)) => {}
+            Some(INode::Directory(_)) => return Err("Is a directory".into()),
+            None => return Ok(vec![]),
+        }
+        drop(namespace);
+
+        Self::log_edit(
+            &mut edit_log,
+            edit_log_op::Op::Delete(file_path.to_string()),
+        )?;
+        let blocks = self.delete(file_path)?;
+        self.invalidate_blocks(&blocks);
+        let path = namespace::normalize(file_path)?;
+        self.leases.lock().unwrap().remove(&path);
+        self.publish(&edit_log, EventType::Delete, &path, None, false);
+        Ok(blocks)
+    }
+
+    /// Creates a directory, along with any missing parents if `parents` is set
+    pub async fn mkdir(
+        &self,
+        path: &str,
+        parents: bool,
+        caller: &Caller,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        // directories are added to the deepest one that already exists
+        let exists = namespace.get(path)?.is_some();
+        let access = match exists {
+            true => Access::default(),
+            false => Access {
+                ancestor: WRITE,
+                ..Default::default()
+            },
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        namespace.check_mkdir(path, parents)?;
+        // an existing directory is left as it is, without an edit
+        if exists {
+            return Ok(());
+        }
+        let attributes = Self::attributes_of_new(&namespace, path, caller, true)?;
+        drop(namespace);
+
+        let dir = DirectoryEntry {
+            path: path.to_string(),
+            attributes: Some((&attributes).into()),
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::Mkdir(dir.clone()))?;
+        self.apply_mkdir(dir)?;
+        self.publish(&edit_log, EventType::Create, path, None, true);
+        Ok(())
+    }
+
+    /// Creates a directory and any missing parents with the attributes in `dir`
+    fn apply_mkdir(&self, dir: DirectoryEntry) -> Result<(), Box<dyn Error>> {
+        let attributes = dir
+            .attributes
+            .as_ref()
+            .map(Attributes::from)
+            .unwrap_or_default();
+        self.namespace
+            .lock()
+            .unwrap()
+            .mkdir(&dir.path, true, &attributes)
+    }
+
+    /// Lists the files and directories in a directory
+    pub async fn list_directory(
+        &self,
+        path: &str,
+        caller: &Caller,
+    ) -> Result<Vec<INodeStatus>, Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            inode: READ | EXECUTE,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        namespace.list(path)
+    }
+
+    /// Returns the metadata of a file or directory
+    pub async fn stat(&self, path: &str, caller: &Caller) -> Result<INodeStatus, Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        self.check_permission(&namespace, path, caller, Access::default())?;
+        namespace.status(path)
+    }
+
+    /// Sets the permission bits of a file or directory, which only its owner can do
+    pub async fn set_permission(
+        &self,
+        path: &str,
+        mode: u32,
+        caller: &Caller,
+    ) -> Result<(), Box<dyn Error>> {
+        if mode > 0o777 {
+            return Err(format!("Invalid mode {:o}", mode).into());
+        }
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let mut namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            owner: true,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        let inode = namespace
+            .get(path)?
+            .ok_or(NotFound("File does not exist"))?;
+        let is_directory = matches!(inode, INode::Directory(_));
+
+        let op = SetPermissionOp {
+            path: path.to_string(),
+            mode,
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetPermission(op))?;
+        namespace.set_permission(path, mode)?;
+        self.publish(&edit_log, EventType::Metadata, path, None, is_directory);
+        Ok(())
+    }
+
+    /// Changes the ACL of a file or directory, which only its owner can do
+    pub async fn set_acl(
+        &self,
+        path: &str,
+        change: &AclChange,
+        caller: &Caller,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let mut namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            owner: true,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        let inode = namespace
+            .get(path)?
+            .ok_or(NotFound("File does not exist"))?;
+        let is_directory = matches!(inode, INode::Directory(_));
+        let (mode, acl) = acl::apply(inode.attributes(), is_directory, change)?;
+
+        let op = SetAclOp {
+            path: path.to_string(),
+            mode,
+            acl: acl.iter().map(|entry| entry.into()).collect(),
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetAcl(op))?;
+        namespace.set_acl(path, mode, acl)?;
+        self.publish(&edit_log, EventType::Metadata, path, None, is_directory);
+        Ok(())
+    }
+
+    /// Returns the owner, group and every entry of the ACL of a file or directory
+    pub async fn get_acl(
+        &self,
+        path: &str,
+        caller: &Caller,
+    ) -> Result<(String, String, Vec<AclEntry>), Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        self.check_permission(&namespace, path, caller, Access::default())?;
+        let attributes = namespace
+            .get(path)?
+            .ok_or(NotFound("File does not exist"))?
+            .attributes();
+        Ok((
+            attributes.owner.clone(),
+            attributes.group.clone(),
+            acl::full_acl(attributes),
+        ))
+    }
 
-        for i in 0..num_blocks {
-            let block_path = format!("{}_{}", file_path, i);
-            let addr = self.add_block(block_path).await?;
-            addrs.push(addr);
+    /// Sets the owner and group of a file or directory, leaving either alone if it is empty
+    /// Only the superuser can give a file away, and its owner can only hand it to one of their
+    /// own groups
+    pub async fn set_owner(
+        &self,
+        path: &str,
+        owner: &str,
+        group: &str,
+        caller: &Caller,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        self.check_permission(&namespace, path, caller, Access::default())?;
+        let inode = namespace
+            .get(path)?
+            .ok_or(NotFound("File does not exist"))?;
+        let is_directory = matches!(inode, INode::Directory(_));
+        let attributes = inode.attributes();
+        if !self.is_superuser(caller) {
+            if !owner.is_empty() && owner != attributes.owner {
+                return Err(PermissionDenied::new(caller, path, "superuser").into());
+            }
+            caller.check_owner(path, attributes)?;
+            if !group.is_empty() && !caller.is_member(group) {
+                return Err(
+                    PermissionDenied::new(caller, path, &format!("group {}", group)).into(),
+                );
+            }
         }
+        drop(namespace);
 
-        let mut file_records = self.file_records.lock().unwrap();
-        let prev_block_count = file_records
-            .insert(file_path.to_string(), num_blocks)
-            .unwrap_or(0usize);
-        drop(file_records);
+        let op = SetOwnerOp {
+            path: path.to_string(),
+            owner: owner.to_string(),
+            group: group.to_string(),
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetOwner(op.clone()))?;
+        self.apply_set_owner(&op)?;
+        self.publish(&edit_log, EventType::Metadata, path, None, is_directory);
+        Ok(())
+    }
 
-        println!("Previous block count: {}", prev_block_count);
-        println!("Current block count: {}", num_blocks);
+    /// Sets the owner and group of a file or directory, leaving either alone if it is empty
+    fn apply_set_owner(&self, op: &SetOwnerOp) -> Result<(), Box<dyn Error>> {
+        let non_empty = |name: &str| Some(name.to_string()).filter(|name| !name.is_empty());
+        self.namespace.lock().unwrap().set_owner(
+            &op.path,
+            non_empty(&op.owner).as_deref(),
+            non_empty(&op.group).as_deref(),
+        )
+    }
 
-        for i in num_blocks..prev_block_count {
-            let block_path = format!("{}_{}", file_path, i);
-            let addr = self
-                .remove_block(&block_path)
-                .expect("Block does not exist");
-            addrs.push(addr);
+    /// Removes a directory from the system, and returns the blocks of every file that was in it
+    /// Unless `recursive` is set, only an empty directory can be removed
+    pub async fn remove_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+        caller: &Caller,
+    ) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        if namespace::components(path)?.is_empty() {
+            return Err("Can't delete the root directory".into());
+        }
+        let namespace = self.namespace.lock().unwrap();
+        // everything under the directory goes with it, so the caller needs to be able to empty
+        // every directory in it
+        let access = Access {
+            parent: WRITE | EXECUTE,
+            subtree: if recursive { ALL } else { 0 },
+            ..Default::default()
+        };
+        self.check_permission(&namespace, path, caller, access)?;
+        match namespace.get(path)? {
+            Some(INode::Directory(dir)) if !dir.children.is_empty() && !recursive => {
+                return Err("Directory is not empty".into())
+            }
+            Some(INode::Directory(_)) => {}
+            Some(INode::File(_)) => return Err("Not a directory".into()),
+            None => return Err(NotFound("Directory does not exist").into()),
         }
+        drop(namespace);
+
+        Self::log_edit(&mut edit_log, edit_log_op::Op::Delete(path.to_string()))?;
+        let blocks = self.delete(path)?;
+        self.invalidate_blocks(&blocks);
+        // files being written under the directory go with it
+        let path = namespace::normalize(path)?;
+        self.leases.lock().unwrap().remove_under(&path);
+        self.publish(&edit_log, EventType::Delete, &path, None, true);
+        Ok(blocks)
+    }
+
+    /// Moves a file or directory to a new path, and returns the blocks of the file it replaced
+    /// The blocks of the moved files keep their ids, so nothing changes on the datanodes
+    /// Without `overwrite`, the move fails if there's already something at `destination`
+    pub async fn rename(
+        &self,
+        source: &str,
+        destination: &str,
+        overwrite: bool,
+        caller: &Caller,
+    ) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            parent: WRITE | EXECUTE,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, source, caller, access)?;
+        self.check_permission(&namespace, destination, caller, access)?;
+        namespace.check_rename(source, destination, overwrite)?;
+        let is_directory = matches!(namespace.get(source)?, Some(INode::Directory(_)));
+        drop(namespace);
 
-        Ok(addrs)
+        let op = edit_log_op::Op::Rename(RenameOp {
+            source: source.to_string(),
+            destination: destination.to_string(),
+        });
+        Self::log_edit(&mut edit_log, op)?;
+        let blocks = self.move_path(source, destination)?;
+        self.invalidate_blocks(&blocks);
+        // whoever was writing a file keeps writing it at its new path
+        let (source, destination) = (
+            namespace::normalize(source)?,
+            namespace::normalize(destination)?,
+        );
+        if source != destination {
+            let mut leases = self.leases.lock().unwrap();
+            leases.remove_under(&destination);
+            leases.rename(&source, &destination);
+        }
+        self.publish(
+            &edit_log,
+            EventType::Rename,
+            &source,
+            Some(&destination),
+            is_directory,
+        );
+        Ok(blocks)
     }
 
-    /// Removes a file from the system and returns the datanode addresses it lived on
-    pub async fn remove_file(&self, file_path: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
-        let mut file_records = self.file_records.lock().unwrap();
-        let num_blocks = file_records.remove(file_path).unwrap_or(0usize);
+    /// Moves a file or directory in the records, and returns the blocks of the file it replaced
+    fn move_path(&self, source: &str, destination: &str) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let replaced = self.namespace.lock().unwrap().rename(source, destination)?;
+        match replaced {
+            Some(inode) => self.remove_files(&inode, destination),
+            None => Ok(vec![]),
+        }
+    }
 
-        let mut addrs = Vec::<Vec<String>>::with_capacity(num_blocks);
+    /// Removes a file or directory from the records, and returns the blocks of every file that
+    /// was removed
+    fn delete(&self, path: &str) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let inode = self.namespace.lock().unwrap().remove(path)?;
+        self.remove_files(&inode, path)
+    }
 
-        for i in 0..num_blocks {
-            let block_path = format!("{}_{}", file_path, i);
-            let addr = self
-                .remove_block(&block_path)
-                .expect("Block does not exist");
-            addrs.push(addr);
+    /// Removes the blocks of every file at or under an inode taken out of the namespace
+    fn remove_files(&self, inode: &INode, path: &str) -> Result<Vec<FileBlock>, Box<dyn Error>> {
+        let mut blocks = vec![];
+        for (_, record) in inode.files(path) {
+            for (block_id, offset, length) in record.held_blocks() {
+                blocks.push(self.remove_block(block_id, offset, length)?);
+            }
         }
 
-        Ok(addrs)
+        Ok(blocks)
     }
 
-    // Removes a block from block_records, amd returns the datanode addresses it lived on
-    fn remove_block(&self, file_path: &str) -> Result<Vec<String>, &str> {
-        let file_id = Self::get_file_id(file_path);
+    /// Removes a block from block_records, and returns the datanode addresses it lived on
+    fn remove_block(
+        &self,
+        block_id: u64,
+        offset: usize,
+        length: usize,
+    ) -> Result<FileBlock, Box<dyn Error>> {
         let mut block_records = self.block_records.write().unwrap();
-        block_records
-            .remove_block_from_records(&file_id)
-            .ok_or("Block does not exist")
+        let generation_stamp = block_records
+            .get_generation_stamp(&block_id)
+            .unwrap_or_default();
+        let addrs = block_records
+            .remove_block_from_records(&block_id)
+            .ok_or("Block does not exist")?;
+        Ok(FileBlock {
+            block_id,
+            generation_stamp,
+            offset,
+            length,
+            addrs,
+        })
+    }
+
+    /// Writes the whole namespace to a new fsimage and empties the edit log, if the namespace is
+    /// persisted and has changed since the last checkpoint
+    pub fn checkpoint(&self) -> Result<(), Box<dyn Error>> {
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let edit_log = match edit_log.as_mut() {
+            Some(edit_log) if edit_log.has_edits_since_checkpoint() => edit_log,
+            _ => return Ok(()),
+        };
+
+        let namespace = self.namespace.lock().unwrap();
+        let block_records = self.block_records.read().unwrap();
+        let files = namespace
+            .files()
+            .into_iter()
+            .map(|(path, record)| record.entry(&path, &block_records))
+            .collect();
+        let directories = namespace
+            .directories()
+            .into_iter()
+            .map(|(path, attributes)| DirectoryEntry {
+                path,
+                attributes: Some(attributes.into()),
+            })
+            .collect();
+        let image = FsImage {
+            last_txid: edit_log.last_txid(),
+            next_block_id: self.block_id_counter.load(atomic::Ordering::SeqCst),
+            next_generation_stamp: self.generation_stamp_counter.load(atomic::Ordering::SeqCst),
+            files,
+            directories,
+        };
+        drop(block_records);
+        drop(namespace);
+
+        edit_log.checkpoint(image)?;
+        println!(
+            "Checkpointed namespace at transaction {}",
+            edit_log.last_txid()
+        );
+        Ok(())
     }
 
-    /// Returns a 2d vector of datanode addresses that each block of the file lives on
-    pub async fn get_file_addresses(
+    /// Returns the layout of a file's blocks along with the datanodes holding each of them
+    pub async fn get_file_blocks(
         &self,
         file_path: &str,
-    ) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
-        let file_records = self.file_records.lock().unwrap();
-        let num_blocks = file_records.get(file_path).copied().unwrap_or(0usize);
-        let mut addrs = Vec::<Vec<String>>::with_capacity(num_blocks);
+        caller: &Caller,
+    ) -> Result<FileLayout, Box<dyn Error>> {
+        let namespace = self.namespace.lock().unwrap();
+        let access = Access {
+            inode: READ,
+            ..Default::default()
+        };
+        self.check_permission(&namespace, file_path, caller, access)?;
+        let record = namespace.get_file(file_path)?.clone();
+        drop(namespace);
+        // its blocks may not have reached any datanode yet
+        if record.under_construction.is_some() {
+            return Err("File is under construction".into());
+        }
+        if let Err(e) = self.touch(file_path, &record.attributes) {
+            println!("Failed to update access time of {}: {}", file_path, e);
+        }
+
+        let mut blocks = Vec::<FileBlock>::with_capacity(record.block_ids.len());
+        for (i, block_id) in record.block_ids.iter().enumerate() {
+            let (offset, length) = record.block_range(i);
+            blocks.push(self.get_block(*block_id, offset, length)?);
+        }
+
+        Ok(record.layout(blocks))
+    }
 
-        for i in 0..num_blocks {
-            let block_path = format!("{}_{}", file_path, i);
-            let addr = self
-                .get_block_addresses(block_path)
-                .expect("Block does not exist");
-            addrs.push(addr);
+    /// Sets the access time of a file being read to now, unless it was already accessed recently
+    fn touch(&self, file_path: &str, attributes: &Attributes) -> Result<(), Box<dyn Error>> {
+        let atime = now();
+        if atime < attributes.atime + ACCESS_TIME_PRECISION.as_millis() as u64 {
+            return Ok(());
         }
 
-        Ok(addrs)
+        let mut edit_log = self.edit_log.lock().unwrap();
+        let op = SetTimesOp {
+            path: file_path.to_string(),
+            mtime: attributes.mtime,
+            atime,
+        };
+        Self::log_edit(&mut edit_log, edit_log_op::Op::SetTimes(op))?;
+        self.namespace
+            .lock()
+            .unwrap()
+            .set_times(file_path, attributes.mtime, atime)?;
+        self.publish(&edit_log, EventType::Metadata, file_path, None, false);
+        Ok(())
     }
 
-    /// Returns a vector of datanode addresses that the file lives on
-    fn get_block_addresses(&self, file_path: String) -> Result<Vec<String>, &str> {
-        let file_id = Self::get_file_id(&file_path);
+    /// Returns a block along with the datanodes holding it, healthiest first
+    fn get_block(
+        &self,
+        block_id: u64,
+        offset: usize,
+        length: usize,
+    ) -> Result<FileBlock, Box<dyn Error>> {
         let block_records = self.block_records.read().unwrap();
+        let mut addrs = block_records.get_block_datanodes(&block_id)?;
+        let generation_stamp = block_records
+            .get_generation_stamp(&block_id)
+            .unwrap_or_default();
+        drop(block_records);
 
-        match block_records.get_block_datanodes(&file_id) {
-            Ok(datanodes) => Ok(datanodes),
-            Err(_) => Err("Block Not in Records"),
-        }
+        self.sort_by_state(&mut addrs);
+        Ok(FileBlock {
+            block_id,
+            generation_stamp,
+            offset,
+            length,
+            addrs,
+        })
+    }
+
+    /// Orders datanode addresses so live datanodes come before stale ones, and stale before dead
+    fn sort_by_state(&self, addrs: &mut [String]) {
+        let datanodes = self.datanodes.lock().unwrap();
+        let datanode_ids = self.datanode_ids.lock().unwrap();
+        addrs.sort_by_key(
+            |addr| match datanode_ids.get(addr).and_then(|id| datanodes.get(id)) {
+                Some(info) => info.state as u8,
+                None => DataNodeState::Dead as u8,
+            },
+        );
     }
 
     /// Adds datanode to records
@@ -226,34 +1682,353 @@ impl NameNodeRecords {
             .fetch_add(1, atomic::Ordering::SeqCst) as u64;
         let info = DataNodeInfo {
             addr: addr.to_string(),
-            alive: true,
+            state: DataNodeState::Live,
         };
         datanode_ids.insert(addr.to_string(), new_id);
         datanodes.insert(new_id, info);
     }
 
-    pub async fn record_heartbeat(&self, address: &str) {
+    /// Drops the replicas a datanode reported as corrupt and queues their blocks for a fresh copy
+    pub async fn report_bad_blocks(&self, address: &str, block_ids: &[u64]) {
+        let mut block_records = self.block_records.write().unwrap();
+        for block_id in block_ids {
+            if block_records.remove_replica(block_id, address) {
+                println!(
+                    "Dropped corrupt replica of block {} on {}",
+                    block_id, address
+                );
+                block_records.queue_replication(*block_id);
+            }
+        }
+    }
+
+    /// Reconciles the block records against every replica a datanode reports holding, given as
+    /// pairs of block id and generation stamp
+    /// Replicas the namenode didn't know about are added, and replicas it expected on the datanode
+    /// that are no longer there are dropped and queued for a fresh copy
+    /// Blocks still being written are only expected once the datanode has reported them
+    pub async fn process_block_report(&self, address: &str, replicas: &[(u64, u64)]) {
+        let reported_ids: HashSet<u64> = replicas.iter().map(|(block_id, _)| *block_id).collect();
+        let mut block_records = self.block_records.write().unwrap();
+
+        for (block_id, generation_stamp) in replicas {
+            self.add_reported_replica(&mut block_records, address, *block_id, *generation_stamp);
+        }
+
+        let mut expected_ids: HashSet<u64> = block_records
+            .reported_blocks_on(address)
+            .into_iter()
+            .collect();
+        expected_ids.extend(
+            block_records
+                .blocks_on(address)
+                .into_iter()
+                .filter(|block_id| !block_records.is_under_construction(block_id)),
+        );
+        for block_id in expected_ids {
+            if !reported_ids.contains(&block_id) {
+                println!("Replica of block {} is missing from {}", block_id, address);
+                block_records.remove_replica(&block_id, address);
+                block_records.queue_replication(block_id);
+            }
+        }
+    }
+
+    /// Handles a replica a client failed to read
+    /// A corrupt replica is only dropped once its datanode has verified it and reported it bad, so
+    /// it is queued for the datanode to verify on its next heartbeat. An unreachable datanode is
+    /// marked stale so it is tried last until its next heartbeat
+    pub async fn report_failed_replica(&self, address: &str, block_id: u64, corrupt: bool) {
+        if corrupt {
+            let held = self
+                .block_records
+                .read()
+                .unwrap()
+                .get_block_datanodes(&block_id)
+                .is_ok_and(|addrs| addrs.iter().any(|addr| addr == address));
+            if !held {
+                return;
+            }
+            let mut suspect_blocks = self.suspect_blocks.lock().unwrap();
+            let blocks = suspect_blocks.entry(address.to_string()).or_default();
+            if !blocks.contains(&block_id) {
+                println!(
+                    "Client failed to read block {} on {}, which it will verify",
+                    block_id, address
+                );
+                blocks.push(block_id);
+            }
+        } else if self.get_datanode_state(address) == Some(DataNodeState::Live) {
+            println!("Client could not reach datanode {}", address);
+            self.set_datanode_state(address, DataNodeState::Stale);
+        }
+    }
+
+    /// Records that a datanode stored a block since its last report
+    pub async fn record_block_received(&self, address: &str, block_id: u64, generation_stamp: u64) {
+        let mut block_records = self.block_records.write().unwrap();
+        self.add_reported_replica(&mut block_records, address, block_id, generation_stamp);
+    }
+
+    /// Records a replica a datanode reported holding
+    /// A replica older than the block's current generation stamp missed a rewrite, so it is
+    /// dropped and the block queued for a fresh copy instead, while a block that isn't part of any
+    /// file is queued for the datanode to delete
+    fn add_reported_replica(
+        &self,
+        block_records: &mut BlockRecords,
+        address: &str,
+        block_id: u64,
+        generation_stamp: u64,
+    ) {
+        match block_records.get_generation_stamp(&block_id) {
+            None => {
+                println!("Datanode {} holds unknown block {}", address, block_id);
+                self.invalidated_blocks
+                    .lock()
+                    .unwrap()
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(block_id);
+            }
+            Some(current) if generation_stamp < current => {
+                println!(
+                    "Datanode {} holds an out of date replica of block {}",
+                    address, block_id
+                );
+                if block_records.remove_replica(&block_id, address) {
+                    block_records.queue_replication(block_id);
+                }
+            }
+            Some(_) => {
+                if block_records.add_replica(block_id, address) {
+                    println!("Discovered replica of block {} on {}", block_id, address);
+                }
+            }
+        }
+    }
+
+    /// Records that a datanode deleted a block since its last report
+    /// If the block is still part of a file, it is queued for a fresh copy
+    pub async fn record_block_deleted(&self, address: &str, block_id: u64) {
+        let mut block_records = self.block_records.write().unwrap();
+        if block_records.remove_replica(&block_id, address) {
+            println!("Replica of block {} was deleted from {}", block_id, address);
+            block_records.queue_replication(block_id);
+        }
+    }
+
+    /// Records a heartbeat from a datanode, returning true if the datanode wasn't known before
+    pub async fn record_heartbeat(&self, address: &str) -> bool {
         let mut heartbeats = self.heartbeat_records.lock().unwrap();
-        if !heartbeats.contains_key(address) {
+        let is_new = !heartbeats.contains_key(address);
+        if is_new {
             println!("New datanode at address: {}", address);
             // process new datanode by adding it to system
             self.add_datanode(address);
+        } else if self.set_datanode_state(address, DataNodeState::Live) {
+            println!("Datanode {} is live again", address);
         }
         // update heartbeat time record
         heartbeats.insert(address.to_string(), SystemTime::now());
+        is_new
+    }
+
+    /// Marks datanodes stale or dead if their last heartbeat is older than the given timeouts
+    /// Returns the addresses of the datanodes that have just been marked dead
+    pub async fn check_heartbeats(
+        &self,
+        stale_timeout: Duration,
+        dead_timeout: Duration,
+    ) -> Vec<String> {
+        let heartbeats = self.heartbeat_records.lock().unwrap();
+        let mut newly_dead = vec![];
+
+        for (address, last_heartbeat) in heartbeats.iter() {
+            let elapsed = last_heartbeat.elapsed().unwrap_or_default();
+            let state = if elapsed >= dead_timeout {
+                DataNodeState::Dead
+            } else if elapsed >= stale_timeout {
+                DataNodeState::Stale
+            } else {
+                DataNodeState::Live
+            };
+
+            // only heartbeats bring a datanode back, so a client's report isn't overwritten here
+            if state == DataNodeState::Live {
+                continue;
+            }
+            if self.set_datanode_state(address, state) {
+                println!("Datanode {} is now {:?}", address, state);
+                if state == DataNodeState::Dead {
+                    newly_dead.push(address.clone());
+                }
+            }
+        }
+
+        newly_dead
+    }
+
+    /// Drops every replica on a dead datanode and queues its blocks for a fresh copy
+    pub async fn remove_dead_datanode(&self, address: &str) {
+        let mut block_records = self.block_records.write().unwrap();
+        let block_ids = block_records.blocks_on(address);
+        for block_id in &block_ids {
+            block_records.remove_replica(block_id, address);
+            block_records.queue_replication(*block_id);
+        }
+        println!(
+            "Queued {} blocks from dead datanode {} for re-replication",
+            block_ids.len(),
+            address
+        );
+
+        // the datanode won't be around to pick up its tasks
+        let mut replication_tasks = self.replication_tasks.lock().unwrap();
+        replication_tasks.remove(address);
+    }
+
+    /// Hands each under-replicated block to a live datanode holding it, to be copied to live
+    /// datanodes that don't. Blocks stay queued until they are back to the number of
+    /// replicas their file keeps, and are handed out again if a copy doesn't show up within `REPLICATION_TIMEOUT`
+    pub async fn schedule_replication(&self) {
+        let live_datanodes = self.live_datanodes().await;
+
+        let mut block_records = self.block_records.write().unwrap();
+        let mut replication_tasks = self.replication_tasks.lock().unwrap();
+
+        for block_id in block_records.queued_for_replication() {
+            let replicas = match block_records.get_block_datanodes(&block_id) {
+                Ok(replicas) => replicas,
+                Err(_) => {
+                    // the block's file was deleted
+                    block_records.dequeue_replication(&block_id);
+                    continue;
+                }
+            };
+
+            let replication = block_records
+                .get_replication(&block_id)
+                .unwrap_or(self.replication_count);
+            if replicas.len() >= replication {
+                block_records.dequeue_replication(&block_id);
+                continue;
+            }
+            if block_records.replication_in_progress(&block_id, REPLICATION_TIMEOUT) {
+                continue;
+            }
+
+            let source = match replicas.iter().find(|addr| live_datanodes.contains(addr)) {
+                Some(source) => source.clone(),
+                None => continue,
+            };
+            let mut targets: Vec<String> = live_datanodes
+                .iter()
+                .filter(|addr| !replicas.contains(addr))
+                .cloned()
+                .collect();
+            targets.shuffle(&mut rand::thread_rng());
+            targets.truncate(replication - replicas.len());
+            if targets.is_empty() {
+                continue;
+            }
+
+            println!(
+                "Asking {} to copy block {} to {:?}",
+                source, block_id, targets
+            );
+            replication_tasks
+                .entry(source)
+                .or_default()
+                .push(ReplicationTask { block_id, targets });
+            block_records.mark_replication_scheduled(block_id);
+        }
+    }
+
+    /// Returns and clears the copies a datanode has been asked to make
+    pub async fn take_replication_tasks(&self, address: &str) -> Vec<ReplicationTask> {
+        let mut replication_tasks = self.replication_tasks.lock().unwrap();
+        replication_tasks.remove(address).unwrap_or_default()
+    }
+
+    fn get_datanode_state(&self, addr: &str) -> Option<DataNodeState> {
+        let datanodes = self.datanodes.lock().unwrap();
+        let datanode_ids = self.datanode_ids.lock().unwrap();
+        let id = datanode_ids.get(addr)?;
+        datanodes.get(id).map(|info| info.state)
+    }
+
+    /// Sets the state of a datanode, returning true if the state changed
+    fn set_datanode_state(&self, addr: &str, state: DataNodeState) -> bool {
+        let mut datanodes = self.datanodes.lock().unwrap();
+        let datanode_ids = self.datanode_ids.lock().unwrap();
+
+        let info = match datanode_ids.get(addr).and_then(|id| datanodes.get_mut(id)) {
+            Some(info) => info,
+            None => return false,
+        };
+        let changed = info.state != state;
+        info.state = state;
+        changed
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::namenode::acl::{AclEntryType, AclScope};
+
+    /// Name of the client writing files in tests
+    const CLIENT: &str = "client";
+
+    /// Returns a caller that passes every permission check
+    fn superuser() -> Caller {
+        Caller::new("admin", &[SUPERGROUP])
+    }
+
+    /// Creates a file and completes it, as if every block had been written
+    async fn create(
+        records: &NameNodeRecords,
+        file_path: &str,
+        file_size: usize,
+        caller: &Caller,
+    ) -> Result<FileLayout, Box<dyn Error>> {
+        let layout = records
+            .add_file(file_path, file_size, caller, CLIENT)
+            .await?;
+        records
+            .complete_file(file_path, CLIENT, &layout.blocks)
+            .await?;
+        Ok(layout)
+    }
+
+    /// Rewrites a file and completes it, as if every block had been written
+    async fn update(
+        records: &NameNodeRecords,
+        file_path: &str,
+        file_size: usize,
+        caller: &Caller,
+    ) -> Result<(FileLayout, Vec<FileBlock>), Box<dyn Error>> {
+        let (layout, removed_blocks) = records
+            .update_file(file_path, file_size, caller, CLIENT)
+            .await?;
+        records
+            .complete_file(file_path, CLIENT, &layout.blocks)
+            .await?;
+        Ok((layout, removed_blocks))
+    }
+
+    /// Returns the datanode addresses of each block
+    fn addrs_of(blocks: &[FileBlock]) -> Vec<Vec<String>> {
+        blocks.iter().map(|block| block.addrs.clone()).collect()
+    }
 
     #[tokio::test]
     async fn test_add_datanode() {
         let records = NameNodeRecords::new(1, 4096);
         let datanode = "127.0.0.1:5000";
 
-        records.add_datanode(&datanode);
+        records.add_datanode(datanode);
 
         let datanodes = records.datanodes.lock().unwrap();
         let datanode_ids = records.datanode_ids.lock().unwrap();
@@ -269,33 +2044,37 @@ mod tests {
     async fn test_add_read_remove_file_1() {
         let records = NameNodeRecords::new(1, 4096);
         let datanode = "127.0.0.1:5000";
-        records.add_datanode(&datanode);
+        records.add_datanode(datanode);
 
         let file_path = "test_file";
-        let owner_uid = 123;
+        let file_size = 100;
 
         // test adding a file returns the correct datanode address
-        let result = records.add_file(file_path, owner_uid).await;
+        let result = create(&records, file_path, file_size, &superuser()).await;
         assert!(result.is_ok());
-        let datanode_addr = result.unwrap();
-        assert_eq!(datanode_addr, datanode);
+        let datanode_addrs = result.unwrap();
+        assert_eq!(addrs_of(&datanode_addrs.blocks), vec![vec![datanode]]);
 
         // test reading/getting file returns correct datanode address
-        let addresses = records.get_file_addresses(file_path, owner_uid).await;
+        let addresses = records.get_file_blocks(file_path, &superuser()).await;
         assert!(addresses.is_ok());
         let addrs = addresses.unwrap();
-        assert_eq!(addrs.len(), 1);
-        assert_eq!(addrs, vec![datanode]);
+        assert_eq!(addrs.blocks.len(), 1);
+        assert_eq!(addrs_of(&addrs.blocks), vec![vec![datanode]]);
 
         // test removing file returns correct datanode address
-        let removal_result: Result<Vec<String>, &str> =
-            records.remove_file(file_path, owner_uid).await;
+        let removal_result = records.remove_file(file_path, &superuser()).await;
         assert!(removal_result.is_ok());
         let remove_addr = removal_result.unwrap();
-        assert_eq!(remove_addr, vec![datanode]);
+        assert_eq!(addrs_of(&remove_addr), vec![vec![datanode]]);
+        // the datanode is asked to delete the block even if the client doesn't get to it
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            vec![remove_addr[0].block_id]
+        );
 
         // test file is actually removed after removal
-        let addresses_after_removal = records.get_file_addresses(file_path, owner_uid).await;
+        let addresses_after_removal = records.get_file_blocks(file_path, &superuser()).await;
         assert!(addresses_after_removal.is_err());
     }
 
@@ -306,48 +2085,1189 @@ mod tests {
         let datanode1 = "127.0.0.1:5000";
         let datanode2 = "127.0.0.1:5001";
         let datanode3 = "127.0.0.1:5002";
-        records.add_datanode(&datanode1);
-        records.add_datanode(&datanode2);
-        records.add_datanode(&datanode3);
+        records.add_datanode(datanode1);
+        records.add_datanode(datanode2);
+        records.add_datanode(datanode3);
 
         let file_path_0 = "test_file";
         let file_path_1 = "test_file_1";
-        let owner_uid = 123;
+        let file_size = 100;
 
         // test adding files
-        let result = records.add_file(file_path_0, owner_uid).await;
+        let result = create(&records, file_path_0, file_size, &superuser()).await;
         assert!(result.is_ok());
         let datanode_0 = result.unwrap();
-        let result_1 = records.add_file(file_path_1, owner_uid).await;
+        let result_1 = create(&records, file_path_1, file_size, &superuser()).await;
         assert!(result_1.is_ok());
         let datanode_1 = result_1.unwrap();
 
         // test reading files
-        let read_result = records
-            .get_file_addresses(file_path_0, owner_uid as i64)
-            .await;
+        let read_result = records.get_file_blocks(file_path_0, &superuser()).await;
         assert!(read_result.is_ok());
-        assert_eq!(read_result.unwrap(), vec![datanode_0.clone()]);
+        assert_eq!(read_result.unwrap(), datanode_0.clone());
 
-        let read_result_1 = records
-            .get_file_addresses(file_path_1, owner_uid as i64)
-            .await;
+        let read_result_1 = records.get_file_blocks(file_path_1, &superuser()).await;
         assert!(read_result_1.is_ok());
-        assert_eq!(read_result_1.unwrap(), vec![datanode_1.clone()]);
-        // println!("{}, {}", datanode_0, datanode_1);
+        assert_eq!(read_result_1.unwrap(), datanode_1.clone());
 
         // testing deletes
-        let removal_result: Result<Vec<String>, &str> =
-            records.remove_file(file_path_0, owner_uid).await;
+        let removal_result = records.remove_file(file_path_0, &superuser()).await;
         assert!(removal_result.is_ok());
         let remove_addr = removal_result.unwrap();
-        assert_eq!(remove_addr, vec![datanode_0]);
+        assert_eq!(remove_addr, datanode_0.blocks);
 
-        let removal_result: Result<Vec<String>, &str> =
-            records.remove_file(file_path_1, owner_uid).await;
+        let removal_result = records.remove_file(file_path_1, &superuser()).await;
         assert!(removal_result.is_ok());
         let remove_addr = removal_result.unwrap();
-        assert_eq!(remove_addr, vec![datanode_1]);
+        assert_eq!(remove_addr, datanode_1.blocks);
+    }
+
+    #[tokio::test]
+    async fn test_report_bad_blocks() {
+        let records = NameNodeRecords::new(2, 4096);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        records.add_datanode(datanode1);
+        records.add_datanode(datanode2);
+
+        let file_path = "test_file";
+        let block_id = create(&records, file_path, 100, &superuser())
+            .await
+            .unwrap()
+            .blocks[0]
+            .block_id;
+        records.report_bad_blocks(datanode1, &[block_id]).await;
+
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![datanode2]]);
+
+        let block_records = records.block_records.read().unwrap();
+        assert!(block_records.is_queued_for_replication(&block_id));
+    }
+
+    #[tokio::test]
+    async fn test_report_failed_replica() {
+        let records = NameNodeRecords::new(3, 4096);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        let datanode3 = "127.0.0.1:5002";
+        records.record_heartbeat(datanode1).await;
+        records.record_heartbeat(datanode2).await;
+        records.record_heartbeat(datanode3).await;
+
+        let file_path = "test_file";
+        let blocks = create(&records, file_path, 100, &superuser())
+            .await
+            .unwrap()
+            .blocks;
+        let block_id = blocks[0].block_id;
+        let addrs = &blocks[0].addrs;
+        let (first, second, third) = (addrs[0].as_str(), addrs[1].as_str(), addrs[2].as_str());
+
+        // an unreachable datanode is moved to the back, even after its heartbeat is checked
+        records.report_failed_replica(first, block_id, false).await;
+        records
+            .check_heartbeats(Duration::from_secs(10), Duration::from_secs(30))
+            .await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![second, third, first]]);
+
+        // a corrupt replica is kept until its datanode verifies it, and only the datanode's own
+        // report drops it
+        records.report_failed_replica(second, block_id, true).await;
+        records.report_failed_replica(second, block_id, true).await;
+        records.report_failed_replica(second, 999, true).await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![second, third, first]]);
+        assert_eq!(records.take_suspect_blocks(second).await, vec![block_id]);
+        assert!(records.take_suspect_blocks(second).await.is_empty());
+        records.report_bad_blocks(second, &[block_id]).await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![third, first]]);
+
+        // the next heartbeat brings the unreachable datanode back to the front
+        records.record_heartbeat(first).await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![first, third]]);
+    }
+
+    #[tokio::test]
+    async fn test_block_reports() {
+        let records = NameNodeRecords::new(1, 4096);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        records.add_datanode(datanode1);
+
+        let file_path = "test_file";
+        let block = create(&records, file_path, 100, &superuser())
+            .await
+            .unwrap()
+            .blocks
+            .remove(0);
+        let (block_id, generation_stamp) = (block.block_id, block.generation_stamp);
+
+        // a replica the namenode didn't assign is added to the records
+        records
+            .record_block_received(datanode1, block_id, generation_stamp)
+            .await;
+        records
+            .process_block_report(datanode2, &[(block_id, generation_stamp)])
+            .await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![datanode1, datanode2]]);
+
+        // a replica that disappears from a full report is dropped and queued for a fresh copy
+        records.process_block_report(datanode1, &[]).await;
+        let blocks = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![datanode2]]);
+        assert!(records
+            .block_records
+            .read()
+            .unwrap()
+            .is_queued_for_replication(&block_id));
+
+        // so is a replica its writer confirmed that the datanode never reported
+        let block = create(&records, "other_file", 100, &superuser())
+            .await
+            .unwrap()
+            .blocks
+            .remove(0);
+        assert_eq!(block.addrs, vec![datanode1]);
+        records
+            .process_block_report(datanode1, &[(block_id, generation_stamp)])
+            .await;
+        let blocks = records
+            .get_file_blocks("other_file", &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![Vec::<String>::new()]);
+
+        // a block that isn't part of any file is deleted from the datanode that reported it
+        records
+            .process_block_report(datanode2, &[(block_id, generation_stamp), (999, 1)])
+            .await;
+        assert_eq!(records.take_invalidated_blocks(datanode2).await, vec![999]);
+    }
+
+    #[tokio::test]
+    async fn test_update_file() {
+        let records = NameNodeRecords::new(1, 4096);
+        let datanode = "127.0.0.1:5000";
+        records.add_datanode(datanode);
+
+        let file_path = "test_file";
+        let layout = create(&records, file_path, 5000, &superuser())
+            .await
+            .unwrap();
+        let blocks = layout.blocks;
+        assert_eq!((layout.length, layout.block_size), (5000, 4096));
+        assert_eq!((blocks[0].offset, blocks[0].length), (0, 4096));
+        assert_eq!((blocks[1].offset, blocks[1].length), (4096, 904));
+        assert_ne!(blocks[0].block_id, blocks[1].block_id);
+        assert!(create(&records, file_path, 5000, &superuser())
+            .await
+            .is_err());
+
+        // rewriting the file puts it in new blocks, and the old ones are deleted once it's completed
+        let (updated, removed) = update(&records, file_path, 100, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(updated.length, 100);
+        assert_eq!(updated.blocks.len(), 1);
+        assert!(!blocks
+            .iter()
+            .any(|block| block.block_id == updated.blocks[0].block_id));
+        assert_eq!(updated.blocks[0].length, 100);
+        assert!(removed.is_empty());
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            vec![blocks[0].block_id, blocks[1].block_id]
+        );
+        assert_eq!(
+            records
+                .get_file_blocks(file_path, &superuser())
+                .await
+                .unwrap(),
+            updated
+        );
+
+        // a replica at an older generation stamp than the block's is dropped and queued for a
+        // fresh copy
+        let block_id = updated.blocks[0].block_id;
+        records
+            .process_block_report(
+                datanode,
+                &[(block_id, updated.blocks[0].generation_stamp - 1)],
+            )
+            .await;
+        let layout = records
+            .get_file_blocks(file_path, &superuser())
+            .await
+            .unwrap();
+        assert!(layout.blocks[0].addrs.is_empty());
+        assert!(records
+            .block_records
+            .read()
+            .unwrap()
+            .is_queued_for_replication(&block_id));
+    }
+
+    #[tokio::test]
+    async fn test_leases() {
+        let mut records = NameNodeRecords::new(1, 4);
+        let datanode = "127.0.0.1:5000";
+        records.add_datanode(datanode);
+
+        // only the client holding the lease can write the file until it completes it
+        let layout = records
+            .add_file("/f", 10, &superuser(), "client1")
+            .await
+            .unwrap();
+        assert!(records
+            .update_file("f", 10, &superuser(), "client2")
+            .await
+            .is_err());
+        assert!(records
+            .complete_file("/f", "client2", &layout.blocks)
+            .await
+            .is_err());
+        assert!(records.renew_lease("client1").await);
+        records
+            .complete_file("f", "client1", &layout.blocks)
+            .await
+            .unwrap();
+        assert!(!records.renew_lease("client1").await);
+        let completed = layout;
+
+        // a lease follows its file when the file is moved
+        let (layout, _) = records
+            .update_file("/f", 10, &superuser(), "client2")
+            .await
+            .unwrap();
+        records
+            .rename("/f", "/g", false, &superuser())
+            .await
+            .unwrap();
+        assert!(records
+            .complete_file("/f", "client2", &layout.blocks)
+            .await
+            .is_err());
+
+        // past the soft limit another client can take the file over, which undoes the rewrite
+        // that was under way, even the blocks of it that were written
+        let first = &layout.blocks[0];
+        records
+            .record_block_received(datanode, first.block_id, first.generation_stamp)
+            .await;
+        records.lease_soft_limit = Duration::ZERO;
+        records
+            .update_file("/g", 10, &superuser(), "client1")
+            .await
+            .unwrap();
+        let block_ids = |blocks: &[FileBlock]| -> Vec<u64> {
+            blocks.iter().map(|block| block.block_id).collect()
+        };
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            block_ids(&layout.blocks)
+        );
+        assert!(records
+            .complete_file("/g", "client2", &layout.blocks)
+            .await
+            .is_err());
+
+        // past the hard limit the namenode recovers the file itself, back to how it was completed
+        records
+            .recover_expired_leases(Duration::from_secs(60))
+            .await;
+        assert!(records.get_file_blocks("/g", &superuser()).await.is_err());
+        records.recover_expired_leases(Duration::ZERO).await;
+        let recovered = records.get_file_blocks("/g", &superuser()).await.unwrap();
+        assert_eq!(recovered.length, 10);
+        assert_eq!(block_ids(&recovered.blocks), block_ids(&completed.blocks));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_rewrite() {
+        let records = NameNodeRecords::new(1, 4);
+        let datanode = "127.0.0.1:5000";
+        records.add_datanode(datanode);
+        let completed = create(&records, "/f", 10, &superuser()).await.unwrap();
+        for block in &completed.blocks {
+            records
+                .record_block_received(datanode, block.block_id, block.generation_stamp)
+                .await;
+        }
+
+        // a rewrite goes to new blocks, so the file's data is untouched until it is completed
+        let (layout, removed) = records
+            .update_file("/f", 6, &superuser(), CLIENT)
+            .await
+            .unwrap();
+        assert!(removed.is_empty());
+        assert!(layout.blocks.iter().all(|block| !completed
+            .blocks
+            .iter()
+            .any(|old| old.block_id == block.block_id)));
+
+        // a writer that disappears before writing anything leaves the file as it was completed
+        records.recover_expired_leases(Duration::ZERO).await;
+        let recovered = records.get_file_blocks("/f", &superuser()).await.unwrap();
+        assert_eq!(recovered, completed);
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            vec![layout.blocks[0].block_id, layout.blocks[1].block_id]
+        );
+
+        // a completed rewrite replaces the file's blocks, which are then deleted
+        let (layout, _) = update(&records, "/f", 6, &superuser()).await.unwrap();
+        assert_eq!(
+            records
+                .get_file_blocks("/f", &superuser())
+                .await
+                .unwrap()
+                .length,
+            6
+        );
+        assert_eq!(layout.blocks.len(), 2);
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            completed
+                .blocks
+                .iter()
+                .map(|block| block.block_id)
+                .collect::<Vec<u64>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_file() {
+        let mut records = NameNodeRecords::new(1, 4);
+        let datanode = "127.0.0.1:5000";
+        records.add_datanode(datanode);
+        let layout = records
+            .add_file("/f", 6, &superuser(), "client1")
+            .await
+            .unwrap();
+        for block in &layout.blocks {
+            records
+                .record_block_received(datanode, block.block_id, block.generation_stamp)
+                .await;
+        }
+        records
+            .complete_file("/f", "client1", &layout.blocks)
+            .await
+            .unwrap();
+
+        // an append returns every block but keeps only the full ones, and keeps other clients from
+        // writing the file
+        let appended = records
+            .append_file("/f", &superuser(), "client1")
+            .await
+            .unwrap();
+        assert_eq!(appended.blocks, layout.blocks);
+        let added = records.allocate_block("/f", 4, "client1").await.unwrap();
+        assert_eq!((added.offset, added.length), (4, 4));
+        assert_ne!(added.block_id, layout.blocks[1].block_id);
+        assert!(records.allocate_block("/f", 4, "client2").await.is_err());
+        assert!(records.allocate_block("/f", 5, "client1").await.is_err());
+        assert!(records
+            .append_file("/f", &superuser(), "client2")
+            .await
+            .is_err());
+        assert!(records
+            .update_file("/f", 10, &superuser(), "client2")
+            .await
+            .is_err());
+        assert!(records.get_file_blocks("/f", &superuser()).await.is_err());
+
+        // an abandoned append leaves the file as it was
+        records.recover_expired_leases(Duration::ZERO).await;
+        assert_eq!(records.stat("/f", &superuser()).await.unwrap().length, 6);
+        assert_eq!(
+            records.get_file_blocks("/f", &superuser()).await.unwrap(),
+            layout
+        );
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            vec![added.block_id]
+        );
+
+        // the partly filled block is replaced by the blocks added after the full ones
+        records
+            .append_file("/f", &superuser(), "client1")
+            .await
+            .unwrap();
+        let mut blocks = vec![layout.blocks[0].clone()];
+        blocks.push(records.allocate_block("/f", 4, "client1").await.unwrap());
+        blocks.push(records.allocate_block("/f", 1, "client1").await.unwrap());
+        assert!(records.allocate_block("/f", 1, "client1").await.is_err());
+        records
+            .complete_file("/f", "client1", &blocks)
+            .await
+            .unwrap();
+        assert_eq!(records.stat("/f", &superuser()).await.unwrap().length, 9);
+        assert_eq!(
+            records
+                .get_file_blocks("/f", &superuser())
+                .await
+                .unwrap()
+                .blocks,
+            blocks
+        );
+        assert_eq!(
+            records.take_invalidated_blocks(datanode).await,
+            vec![layout.blocks[1].block_id]
+        );
+
+        let (layout, _) = records
+            .update_file("/f", 10, &superuser(), "client2")
+            .await
+            .unwrap();
+        records.lease_soft_limit = Duration::ZERO;
+        assert!(records
+            .append_file("/f", &superuser(), "client1")
+            .await
+            .is_ok());
+        assert!(records
+            .complete_file("/f", "client2", &layout.blocks)
+            .await
+            .is_err());
+        assert!(records
+            .append_file("/missing", &superuser(), "client1")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_complete_file() {
+        let records = NameNodeRecords::new(2, 4);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        records.add_datanode(datanode1);
+        records.add_datanode(datanode2);
+
+        // a file can't be read until its writer confirms every block
+        let layout = records
+            .add_file("/f", 6, &superuser(), CLIENT)
+            .await
+            .unwrap();
+        assert!(records.get_file_blocks("/f", &superuser()).await.is_err());
+        let mut blocks = layout.blocks.clone();
+        blocks[1].length = 4;
+        assert!(records.complete_file("/f", CLIENT, &blocks).await.is_err());
+        assert!(records
+            .complete_file("/f", CLIENT, &layout.blocks[..1])
+            .await
+            .is_err());
+
+        // only the replicas that acknowledged a block are kept, and it's queued for more
+        let mut blocks = layout.blocks.clone();
+        blocks[0].addrs = vec![datanode1.to_string()];
+        records.complete_file("/f", CLIENT, &blocks).await.unwrap();
+        let read = records.get_file_blocks("/f", &superuser()).await.unwrap();
+        assert_eq!(addrs_of(&read.blocks)[0], vec![datanode1]);
+        assert!(records
+            .block_records
+            .read()
+            .unwrap()
+            .is_queued_for_replication(&blocks[0].block_id));
+
+        // a new file that's abandoned is deleted, blocks and all
+        let layout = records
+            .add_file("/g", 6, &superuser(), CLIENT)
+            .await
+            .unwrap();
+        records.recover_expired_leases(Duration::ZERO).await;
+        assert!(records.stat("/g", &superuser()).await.is_err());
+        let invalidated = records.take_invalidated_blocks(datanode1).await;
+        assert_eq!(invalidated.len(), layout.blocks.len());
+    }
+
+    #[tokio::test]
+    async fn test_recover_namespace() {
+        let name_dir = std::env::temp_dir().join(format!("kldfs_name_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&name_dir);
+        let datanode = "127.0.0.1:5000";
+
+        let records = NameNodeRecords::open(1, 4096, &name_dir).unwrap();
+        records.add_datanode(datanode);
+        create(&records, "test_file", 5000, &superuser())
+            .await
+            .unwrap();
+        let removed = create(&records, "test_file_2", 100, &superuser())
+            .await
+            .unwrap();
+        records.checkpoint().unwrap();
+        let (updated, _) = update(&records, "test_file", 100, &superuser())
+            .await
+            .unwrap();
+        records
+            .remove_file("test_file_2", &superuser())
+            .await
+            .unwrap();
+        let added = create(&records, "test_file_3", 10, &superuser())
+            .await
+            .unwrap();
+        records
+            .mkdir("/project/data", true, &superuser())
+            .await
+            .unwrap();
+        // making a directory that exists logs no edit
+        let last_txid = |records: &NameNodeRecords| {
+            let edit_log = records.edit_log.lock().unwrap();
+            edit_log.as_ref().unwrap().last_txid()
+        };
+        let txid = last_txid(&records);
+        records
+            .mkdir("/project/data", true, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(last_txid(&records), txid);
+        create(&records, "/project/data/file", 10, &superuser())
+            .await
+            .unwrap();
+        create(&records, "/project/file", 10, &superuser())
+            .await
+            .unwrap();
+        records
+            .rename("/project", "/archive", false, &superuser())
+            .await
+            .unwrap();
+        records
+            .rename("/archive/data", "/project", false, &superuser())
+            .await
+            .unwrap();
+        records
+            .set_permission("test_file", 0o600, &superuser())
+            .await
+            .unwrap();
+        records
+            .set_owner("/archive", "alice", "staff", &superuser())
+            .await
+            .unwrap();
+        let entry = AclEntry::new(AclScope::Default, AclEntryType::User, "bob", READ);
+        records
+            .set_acl("/archive", &AclChange::Modify(vec![entry]), &superuser())
+            .await
+            .unwrap();
+        records
+            .add_file("/open", 10, &superuser(), CLIENT)
+            .await
+            .unwrap();
+        let file_status = records.stat("test_file", &superuser()).await.unwrap();
+        let dir_status = records.stat("/archive", &superuser()).await.unwrap();
+        drop(records);
+
+        // files come back from the checkpoint and the edits after it, without their locations
+        let records = NameNodeRecords::open(1, 4096, &name_dir).unwrap();
+        assert_eq!(
+            records.stat("test_file", &superuser()).await.unwrap(),
+            file_status
+        );
+        assert_eq!(
+            records.stat("/archive", &superuser()).await.unwrap(),
+            dir_status
+        );
+        // a file that wasn't completed is still under construction, and its writer's lease is back
+        assert!(records
+            .get_file_blocks("/open", &superuser())
+            .await
+            .is_err());
+        assert_eq!(records.leases.lock().unwrap().holder("/open"), Some(CLIENT));
+        let without_addrs = |mut layout: FileLayout| {
+            layout
+                .blocks
+                .iter_mut()
+                .for_each(|block| block.addrs.clear());
+            layout
+        };
+        assert_eq!(
+            records
+                .get_file_blocks("test_file", &superuser())
+                .await
+                .unwrap(),
+            without_addrs(updated.clone())
+        );
+        assert!(records
+            .get_file_blocks("test_file_2", &superuser())
+            .await
+            .is_err());
+        assert_eq!(
+            records
+                .get_file_blocks("test_file_3", &superuser())
+                .await
+                .unwrap(),
+            without_addrs(added.clone())
+        );
+        assert!(records
+            .get_file_blocks("/project/file", &superuser())
+            .await
+            .is_ok());
+        assert!(records
+            .get_file_blocks("/archive/file", &superuser())
+            .await
+            .is_ok());
+
+        // ids and generation stamps of deleted blocks aren't handed out again
+        records.add_datanode("127.0.0.1:5000");
+        let block = &records
+            .add_file("test_file_4", 10, &superuser(), CLIENT)
+            .await
+            .unwrap()
+            .blocks[0];
+        assert!(block.block_id > removed.blocks[0].block_id);
+        assert!(block.block_id > added.blocks[0].block_id);
+        assert!(block.generation_stamp > added.blocks[0].generation_stamp);
+
+        // a block report restores the locations
+        let block = &updated.blocks[0];
+        records
+            .process_block_report(datanode, &[(block.block_id, block.generation_stamp)])
+            .await;
+        let layout = records
+            .get_file_blocks("test_file", &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&layout.blocks), vec![vec![datanode]]);
+
+        // a rewrite under way when the namenode stops can still be undone, even from a checkpoint
+        records
+            .update_file("test_file_3", 20, &superuser(), CLIENT)
+            .await
+            .unwrap();
+        records.checkpoint().unwrap();
+        drop(records);
+        let records = NameNodeRecords::open(1, 4096, &name_dir).unwrap();
+        records.recover_expired_leases(Duration::ZERO).await;
+        assert_eq!(
+            records
+                .get_file_blocks("test_file_3", &superuser())
+                .await
+                .unwrap(),
+            without_addrs(added)
+        );
+
+        std::fs::remove_dir_all(&name_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_directories() {
+        let records = NameNodeRecords::new(1, 4096);
+        records.add_datanode("127.0.0.1:5000");
+
+        // files and directories can only be created in an existing directory
+        assert!(create(&records, "/project/file", 100, &superuser())
+            .await
+            .is_err());
+        assert!(records
+            .mkdir("/project/data", false, &superuser())
+            .await
+            .is_err());
+        records
+            .mkdir("/project/data", true, &superuser())
+            .await
+            .unwrap();
+        let layout = create(&records, "/project/file", 100, &superuser())
+            .await
+            .unwrap();
+        create(&records, "/project/data/file", 100, &superuser())
+            .await
+            .unwrap();
+        assert!(update(&records, "/project/data", 100, &superuser())
+            .await
+            .is_err());
+        assert!(records.remove_file("/project", &superuser()).await.is_err());
+
+        let entries = records
+            .list_directory("/project", &superuser())
+            .await
+            .unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["data", "file"]);
+        assert!(entries[0].is_directory);
+        assert_eq!(entries[1].length, 100);
+
+        // a directory that isn't empty is only removed along with its contents
+        assert!(records
+            .remove_directory("/project", false, &superuser())
+            .await
+            .is_err());
+        assert!(records
+            .remove_directory("/project/file", true, &superuser())
+            .await
+            .is_err());
+        assert!(records
+            .remove_directory("/", true, &superuser())
+            .await
+            .is_err());
+        let removed = records
+            .remove_directory("/project", true, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(removed.contains(&layout.blocks[0]));
+        assert!(records
+            .list_directory("/", &superuser())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_metadata() {
+        let records = NameNodeRecords::new(2, 4096);
+        records.add_datanode("127.0.0.1:5000");
+        create(&records, "/file", 5000, &superuser()).await.unwrap();
+        records.mkdir("/dir", false, &superuser()).await.unwrap();
+
+        let status = records.stat("/file", &superuser()).await.unwrap();
+        assert!(!status.is_directory);
+        assert_eq!(
+            (status.length, status.block_size, status.replication),
+            (5000, 4096, 2)
+        );
+        assert_eq!(status.attributes.owner, "admin");
+        assert_eq!(status.attributes.group, SUPERGROUP);
+        assert_eq!(status.attributes.mode, FILE_MODE);
+
+        let status = records.stat("/dir", &superuser()).await.unwrap();
+        assert!(status.is_directory);
+        assert_eq!(status.attributes.mode, DIRECTORY_MODE);
+        assert_eq!(records.stat("/", &superuser()).await.unwrap().name, "/");
+        assert!(records.stat("/missing", &superuser()).await.is_err());
+
+        // an update keeps the creation time and moves the modification time forward
+        let created = records
+            .stat("/file", &superuser())
+            .await
+            .unwrap()
+            .attributes;
+        update(&records, "/file", 10, &superuser()).await.unwrap();
+        let updated = records
+            .stat("/file", &superuser())
+            .await
+            .unwrap()
+            .attributes;
+        assert_eq!(updated.ctime, created.ctime);
+        assert!(updated.mtime >= created.mtime);
+
+        // a read only updates an access time that is out of date
+        records
+            .namespace
+            .lock()
+            .unwrap()
+            .set_times("/file", updated.mtime, 0)
+            .unwrap();
+        records
+            .get_file_blocks("/file", &superuser())
+            .await
+            .unwrap();
+        let read = records
+            .stat("/file", &superuser())
+            .await
+            .unwrap()
+            .attributes;
+        assert!(read.atime >= updated.mtime);
+        assert_eq!(read.mtime, updated.mtime);
+    }
+
+    #[tokio::test]
+    async fn test_permissions() {
+        let records = NameNodeRecords::new(1, 4096);
+        records.add_datanode("127.0.0.1:5000");
+        let alice = Caller::new("alice", &["staff"]);
+        let bob = Caller::new("bob", &["users"]);
+        let denied =
+            |result: Result<(), Box<dyn Error>>| result.unwrap_err().is::<PermissionDenied>();
+        records
+            .mkdir("/home/alice", true, &superuser())
+            .await
+            .unwrap();
+        records
+            .set_owner("/home/alice", "alice", "staff", &superuser())
+            .await
+            .unwrap();
+
+        // only the owner of a directory can write to it by default, but anyone can read it
+        create(&records, "/home/alice/f", 10, &alice).await.unwrap();
+        let status = records.stat("/home/alice/f", &alice).await.unwrap();
+        assert_eq!(status.attributes.owner, "alice");
+        assert_eq!(status.attributes.group, "staff");
+        assert!(denied(
+            create(&records, "/home/alice/g", 10, &bob)
+                .await
+                .map(|_| ())
+        ));
+        assert!(denied(records.mkdir("/home/bob", false, &bob).await));
+        assert!(denied(
+            records.remove_file("/home/alice/f", &bob).await.map(|_| ())
+        ));
+        assert!(denied(
+            update(&records, "/home/alice/f", 5, &bob).await.map(|_| ())
+        ));
+        assert!(records.get_file_blocks("/home/alice/f", &bob).await.is_ok());
+        assert!(denied(
+            records
+                .remove_directory("/home/alice", true, &alice)
+                .await
+                .map(|_| ())
+        ));
+
+        // only the owner can change the permissions, and only to groups they are in
+        assert!(denied(
+            records.set_permission("/home/alice/f", 0o600, &bob).await
+        ));
+        records
+            .set_permission("/home/alice/f", 0o600, &alice)
+            .await
+            .unwrap();
+        assert!(denied(
+            records
+                .get_file_blocks("/home/alice/f", &bob)
+                .await
+                .map(|_| ())
+        ));
+        assert!(denied(
+            records.set_owner("/home/alice/f", "bob", "", &alice).await
+        ));
+        assert!(denied(
+            records
+                .set_owner("/home/alice/f", "", "users", &alice)
+                .await
+        ));
+        assert!(records
+            .set_permission("/home/alice/f", 0o1777, &alice)
+            .await
+            .is_err());
+
+        // a directory that can't be searched hides everything in it
+        records
+            .set_permission("/home/alice", 0o700, &alice)
+            .await
+            .unwrap();
+        assert!(denied(
+            records.stat("/home/alice/f", &bob).await.map(|_| ())
+        ));
+        assert!(denied(
+            records
+                .list_directory("/home/alice", &bob)
+                .await
+                .map(|_| ())
+        ));
+        assert!(records.list_directory("/home/alice", &alice).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_acls() {
+        let records = NameNodeRecords::new(1, 4096);
+        records.add_datanode("127.0.0.1:5000");
+        let alice = Caller::new("alice", &[]);
+        let bob = Caller::new("bob", &[]);
+        let carol = Caller::new("carol", &["staff"]);
+        let erin = Caller::new("erin", &[]);
+        records.mkdir("/shared", false, &superuser()).await.unwrap();
+        records
+            .set_owner("/shared", "alice", "staff", &superuser())
+            .await
+            .unwrap();
+        records
+            .set_permission("/shared", 0o750, &alice)
+            .await
+            .unwrap();
+
+        // a named user entry lets bob write to alice's directory, and only alice can change it
+        let entries = vec![
+            AclEntry::new(AclScope::Access, AclEntryType::User, "bob", ALL),
+            AclEntry::new(AclScope::Default, AclEntryType::Group, "staff", ALL),
+        ];
+        let change = AclChange::Modify(entries);
+        assert!(records.set_acl("/shared", &change, &bob).await.is_err());
+        records.set_acl("/shared", &change, &alice).await.unwrap();
+        create(&records, "/shared/f", 10, &bob).await.unwrap();
+        assert!(create(&records, "/shared/g", 10, &erin).await.is_err());
+
+        // new files inherit the default ACL, limited by the mode they are created with
+        let (owner, _, acl) = records.get_acl("/shared/f", &bob).await.unwrap();
+        assert_eq!(owner, "bob");
+        let staff = AclEntry::new(AclScope::Access, AclEntryType::Group, "staff", ALL);
+        assert!(acl.contains(&staff));
+        assert!(acl.contains(&AclEntry::new(
+            AclScope::Access,
+            AclEntryType::Mask,
+            "",
+            READ | WRITE
+        )));
+        assert!(update(&records, "/shared/f", 5, &carol).await.is_ok());
+        assert!(records.get_file_blocks("/shared/f", &erin).await.is_err());
+
+        // removing the ACL leaves the permission bits as they were before
+        records
+            .set_acl("/shared", &AclChange::RemoveAll, &alice)
+            .await
+            .unwrap();
+        let status = records.stat("/shared", &alice).await.unwrap();
+        assert_eq!(status.attributes.mode, 0o750);
+        assert!(status.attributes.acl.is_empty());
+        assert!(create(&records, "/shared/g", 10, &bob).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let records = NameNodeRecords::new(1, 4096);
+        records.add_datanode("127.0.0.1:5000");
+        records
+            .mkdir("/project", false, &superuser())
+            .await
+            .unwrap();
+        let file = create(&records, "/project/file", 100, &superuser())
+            .await
+            .unwrap();
+        let other = create(&records, "/other", 100, &superuser()).await.unwrap();
+
+        // moving a directory keeps the blocks of the files in it
+        records
+            .rename("/project", "/archive", false, &superuser())
+            .await
+            .unwrap();
+        assert!(records
+            .get_file_blocks("/project/file", &superuser())
+            .await
+            .is_err());
+        assert_eq!(
+            records
+                .get_file_blocks("/archive/file", &superuser())
+                .await
+                .unwrap(),
+            file
+        );
+
+        // replacing a file hands back the replaced file's blocks to delete
+        assert!(records
+            .rename("/other", "/archive/file", false, &superuser())
+            .await
+            .is_err());
+        let removed = records
+            .rename("/other", "/archive/file", true, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(removed, file.blocks);
+        assert_eq!(
+            records
+                .get_file_blocks("/archive/file", &superuser())
+                .await
+                .unwrap(),
+            other
+        );
+        assert!(!records
+            .block_records
+            .read()
+            .unwrap()
+            .contains_block(&file.blocks[0].block_id));
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let records = NameNodeRecords::new(1, 4096);
+        records.add_datanode("127.0.0.1:5000");
+        records
+            .mkdir("/project", false, &superuser())
+            .await
+            .unwrap();
+        create(&records, "/project/file", 100, &superuser())
+            .await
+            .unwrap();
+        create(&records, "/other", 100, &superuser()).await.unwrap();
+        let (_, mut subscription) = records.watch("/project", 0, &superuser()).await.unwrap();
+        records
+            .rename("/other", "/project/moved", false, &superuser())
+            .await
+            .unwrap();
+        records
+            .set_permission("/project", 0o700, &superuser())
+            .await
+            .unwrap();
+        records
+            .remove_file("/project/file", &superuser())
+            .await
+            .unwrap();
+
+        // replaying from the start only gives the changes under the path
+        let (path, replay) = records.watch("/project/", 1, &superuser()).await.unwrap();
+        assert_eq!(path, "/project");
+        let replayed: Vec<(u64, EventType, &str)> = replay
+            .history
+            .iter()
+            .map(|event| (event.txid, event.r#type(), event.path.as_str()))
+            .collect();
+        assert_eq!(
+            replayed,
+            vec![
+                (2, EventType::Create, "/project/file"),
+                (3, EventType::Close, "/project/file"),
+                (6, EventType::Rename, "/other"),
+                (7, EventType::Metadata, "/project"),
+                (8, EventType::Delete, "/project/file"),
+            ]
+        );
+
+        // a watcher gets every change as it happens, left to filter itself
+        let txids: Vec<u64> = std::iter::from_fn(|| subscription.receiver.try_recv().ok())
+            .map(|event| event.txid)
+            .collect();
+        assert_eq!(txids, vec![6, 7, 8]);
+        assert_eq!(subscription.since_txid, 5);
+
+        let denied = records.watch("/project", 0, &Caller::new("bob", &[])).await;
+        assert!(denied.unwrap_err().is::<PermissionDenied>());
+    }
+
+    #[tokio::test]
+    async fn test_check_heartbeats() {
+        let records = NameNodeRecords::new(1, 4096);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        records.record_heartbeat(datanode1).await;
+        records.record_heartbeat(datanode2).await;
+
+        let stale_timeout = Duration::from_secs(10);
+        let dead_timeout = Duration::from_secs(30);
+        let set_last_heartbeat = |addr: &str, secs_ago: u64| {
+            let mut heartbeats = records.heartbeat_records.lock().unwrap();
+            let time = SystemTime::now() - Duration::from_secs(secs_ago);
+            heartbeats.insert(addr.to_string(), time);
+        };
+        let state_of = |addr: &str| {
+            let datanode_ids = records.datanode_ids.lock().unwrap();
+            let datanodes = records.datanodes.lock().unwrap();
+            datanodes[&datanode_ids[addr]].state
+        };
+
+        // a stale datanode is not dead yet, and neither is used for new blocks
+        set_last_heartbeat(datanode1, 15);
+        set_last_heartbeat(datanode2, 45);
+        let newly_dead = records.check_heartbeats(stale_timeout, dead_timeout).await;
+        assert_eq!(newly_dead, vec![datanode2]);
+        assert_eq!(state_of(datanode1), DataNodeState::Stale);
+        assert_eq!(state_of(datanode2), DataNodeState::Dead);
+        let err = records
+            .add_file("test_file", 100, &superuser(), CLIENT)
+            .await
+            .unwrap_err();
+        assert!(err.is::<NoLiveDatanodes>());
+
+        // datanodes are only reported dead once
+        let newly_dead = records.check_heartbeats(stale_timeout, dead_timeout).await;
+        assert!(newly_dead.is_empty());
+
+        // a heartbeat brings a datanode back
+        records.record_heartbeat(datanode2).await;
+        assert_eq!(state_of(datanode2), DataNodeState::Live);
+        let blocks = create(&records, "test_file_2", 100, &superuser())
+            .await
+            .unwrap();
+        assert_eq!(addrs_of(&blocks.blocks), vec![vec![datanode2]]);
+    }
+
+    #[tokio::test]
+    async fn test_re_replication() {
+        let records = NameNodeRecords::new(2, 4096);
+        let datanode1 = "127.0.0.1:5000";
+        let datanode2 = "127.0.0.1:5001";
+        let datanode3 = "127.0.0.1:5002";
+        records.add_datanode(datanode1);
+        records.add_datanode(datanode2);
+
+        let file_path = "test_file";
+        let block = create(&records, file_path, 100, &superuser())
+            .await
+            .unwrap()
+            .blocks
+            .remove(0);
+        let block_id = block.block_id;
+
+        // losing a datanode queues its blocks, but there's nowhere to copy them to yet
+        records.set_datanode_state(datanode2, DataNodeState::Dead);
+        records.remove_dead_datanode(datanode2).await;
+        records.schedule_replication().await;
+        assert!(records.take_replication_tasks(datanode1).await.is_empty());
+
+        // once a new datanode joins, the surviving replica is copied to it
+        records.add_datanode(datanode3);
+        records.schedule_replication().await;
+        let tasks = records.take_replication_tasks(datanode1).await;
+        assert_eq!(
+            tasks,
+            vec![ReplicationTask {
+                block_id,
+                targets: vec![datanode3.to_string()],
+            }]
+        );
+
+        // the copy isn't handed out twice while it's in progress
+        records.schedule_replication().await;
+        assert!(records.take_replication_tasks(datanode1).await.is_empty());
+
+        // the block leaves the queue once the new replica is reported
+        records
+            .record_block_received(datanode3, block_id, block.generation_stamp)
+            .await;
+        records.schedule_replication().await;
+        assert!(!records
+            .block_records
+            .read()
+            .unwrap()
+            .is_queued_for_replication(&block_id));
+
+        // a block is copied until its own file has enough replicas, which may be fewer than the
+        // namenode keeps of new files
+        let file = FileEntry {
+            path: "/single".to_string(),
+            length: 100,
+            block_size: 4096,
+            blocks: vec![BlockEntry {
+                block_id: 1000,
+                generation_stamp: 1,
+            }],
+            replication: 1,
+            attributes: None,
+            under_construction: None,
+        };
+        let (layout, _) = records.set_file(file, &[datanode1.to_string()]).unwrap();
+        assert_eq!(addrs_of(&layout.blocks), vec![vec![datanode1]]);
+        records
+            .block_records
+            .write()
+            .unwrap()
+            .queue_replication(1000);
+        records.schedule_replication().await;
+        assert!(records.take_replication_tasks(datanode1).await.is_empty());
+        assert!(!records
+            .block_records
+            .read()
+            .unwrap()
+            .is_queued_for_replication(&1000));
     }
 
     #[tokio::test]
@@ -356,32 +3276,34 @@ mod tests {
         let datanode1 = "127.0.0.1:5000";
         let datanode2 = "127.0.0.1:5001";
         let datanode3 = "127.0.0.1:5002";
-        records.add_datanode(&datanode1);
+        records.add_datanode(datanode1);
 
         // testing replication when replication factor > number of datanodes
         let file_path = "test_file";
-        let owner_uid = 123;
-        let datanode_ips = records.add_file_replicas(file_path, owner_uid).await;
+        let file_size = 100;
+        let datanode_ips = create(&records, file_path, file_size, &superuser()).await;
         assert!(datanode_ips.is_ok());
-        assert_eq!(datanode_ips.unwrap(), vec![datanode1.clone()]);
+        assert_eq!(
+            addrs_of(&datanode_ips.unwrap().blocks),
+            vec![vec![datanode1]]
+        );
 
         // testing replication when replication factor = number of datanodes
         let file_path_2 = "test_file_2";
-        records.add_datanode(&datanode2);
-        let datanode_ips = records.add_file_replicas(file_path_2, owner_uid).await;
+        records.add_datanode(datanode2);
+        let datanode_ips = create(&records, file_path_2, file_size, &superuser()).await;
         assert!(datanode_ips.is_ok());
-        let d_ips = datanode_ips.unwrap();
+        let d_ips = &datanode_ips.unwrap().blocks[0].addrs;
         assert_eq!(d_ips.len(), 2);
         assert!(d_ips.contains(&datanode1.to_string()));
         assert!(d_ips.contains(&datanode2.to_string()));
 
         // testing replication when replication factor < number of datanodes
         let file_path_3 = "test_file_3";
-        records.add_datanode(&datanode3);
-        let datanode_ips = records.add_file_replicas(file_path_3, owner_uid).await;
+        records.add_datanode(datanode3);
+        let datanode_ips = create(&records, file_path_3, file_size, &superuser()).await;
         assert!(datanode_ips.is_ok());
-        let d_ips = datanode_ips.unwrap();
+        let d_ips = &datanode_ips.unwrap().blocks[0].addrs;
         assert_eq!(d_ips.len(), 2);
-        // println!("{:?}", d_ips);
     }
 }
@@ -1,34 +1,156 @@
-use crate::namenode::records::NameNodeRecords;
+use crate::namenode::acl::{AclChange, AclEntry};
+use crate::namenode::events::{self, EventsUnavailable, Subscription};
+use crate::namenode::namespace::{AlreadyExists, INodeStatus, NotFound};
+use crate::namenode::permission::{Caller, GroupMapping, PermissionDenied, DEFAULT_GROUPS_FILE};
+use crate::namenode::records::{DataNodeState, FileBlock, NameNodeRecords, NoLiveDatanodes};
 use crate::proto::{
     client_protocols_server::{ClientProtocols, ClientProtocolsServer},
     hearbeat_protocol_server::{HearbeatProtocol, HearbeatProtocolServer},
-    FileInfo, FileRequest, FileResponse, GenericReply, Heartbeat, NodeList, NodeStatus,
-    SystemInfoRequest, SystemInfoResponse,
+    set_acl_request, AddBlockRequest, BadBlockReport, BlockReport, CompleteFileRequest,
+    DeleteDirectoryRequest, FailedReplicaReport, FileInfo, FileRequest, FileResponse, FileStatus,
+    GenericReply, GetAclRequest, GetAclResponse, GetFileInfoRequest, Heartbeat, HeartbeatResponse,
+    IncrementalBlockReport, ListDirectoryRequest, ListDirectoryResponse, LocatedBlock,
+    MkdirRequest, NamespaceEvent, NodeState, NodeStatus, RenameRequest, RenewLeaseRequest,
+    ReplicationCommand, SetAclRequest, SetOwnerRequest, SetPermissionRequest, SystemInfoRequest,
+    SystemInfoResponse, WatchEventsRequest,
 };
 
+use std::error::Error;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::interval;
+use tokio_stream::wrappers::ReceiverStream;
 
 use tonic::transport::Server;
 use tonic::Response;
 
+/// Time between checks for namespace changes to fold into a new fsimage
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time between checks for clients that have stopped renewing their leases
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of events queued for a watcher before the namenode waits for it to take them
+const WATCH_BUFFER: usize = 64;
+
 pub struct NameNodeServer {
     address: SocketAddr,
     records: Arc<NameNodeRecords>,
+
+    /// Time without a heartbeat after which a datanode is considered stale
+    stale_timeout: Duration,
+
+    /// Time without a heartbeat after which a datanode is considered dead
+    dead_timeout: Duration,
+
+    /// Time without a lease renewal after which the files a client is writing are recovered
+    lease_hard_limit: Duration,
+
+    /// Groups of the users making requests
+    groups: Arc<GroupMapping>,
 }
 
 impl NameNodeServer {
-    pub fn new(port: u16, replication_count: usize, block_size: usize) -> Self {
+    /// Creates a namenode, persisting its namespace in `name_dir` if one is given and keeping it in
+    /// memory otherwise
+    /// Users' groups are read from `DEFAULT_GROUPS_FILE` until `load_groups` is given another file
+    pub fn new(
+        port: u16,
+        replication_count: usize,
+        block_size: usize,
+        stale_timeout: Duration,
+        dead_timeout: Duration,
+        lease_hard_limit: Duration,
+        name_dir: Option<PathBuf>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if block_size == 0 {
+            return Err("Block size must be positive".into());
+        }
         let address = SocketAddr::from(([127, 0, 0, 1], port));
-        Self {
+        let records = match name_dir {
+            Some(name_dir) => NameNodeRecords::open(replication_count, block_size, &name_dir)?,
+            None => NameNodeRecords::new(replication_count, block_size),
+        };
+        let groups = GroupMapping::open(Path::new(DEFAULT_GROUPS_FILE)).unwrap_or_else(|e| {
+            println!("{}", e);
+            GroupMapping::default()
+        });
+        Ok(Self {
             address,
-            records: Arc::new(NameNodeRecords::new(replication_count, block_size)),
-        }
+            records: Arc::new(records),
+            stale_timeout,
+            dead_timeout,
+            lease_hard_limit,
+            groups: Arc::new(groups),
+        })
+    }
+
+    /// Reads users' groups from a file in the format of `/etc/group` instead
+    pub fn load_groups(&mut self, groups_file: &Path) -> Result<(), Box<dyn Error>> {
+        self.groups = Arc::new(GroupMapping::open(groups_file)?);
+        Ok(())
     }
 
+    /// Runs the namenode service along with its background tasks
     pub async fn run_nameserver(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let client_protocols_service =
-            NameNodeService::new(self.address.to_string(), Arc::clone(&self.records));
+        tokio::select! {
+            result = self.run_service() => result,
+            result = self.check_heartbeats_loop() => result,
+            result = self.checkpoint_loop() => result,
+            result = self.recover_leases_loop() => result,
+        }
+    }
+
+    /// Recovers the files of clients that haven't renewed their leases within the hard limit,
+    /// checking every `LEASE_CHECK_INTERVAL`
+    async fn recover_leases_loop(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut interval = interval(LEASE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.records
+                .recover_expired_leases(self.lease_hard_limit)
+                .await;
+        }
+    }
+
+    /// Folds the edit log into a new fsimage every `CHECKPOINT_INTERVAL`, so the log doesn't grow
+    /// without bound
+    async fn checkpoint_loop(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut interval = interval(CHECKPOINT_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.records.checkpoint() {
+                println!("Failed to checkpoint namespace: {}", e);
+            }
+        }
+    }
+
+    /// Checks every second for datanodes that have stopped sending heartbeats, and schedules
+    /// fresh copies of the blocks that are left under-replicated
+    async fn check_heartbeats_loop(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut interval = interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let dead_datanodes = self
+                .records
+                .check_heartbeats(self.stale_timeout, self.dead_timeout)
+                .await;
+            for address in dead_datanodes {
+                self.records.remove_dead_datanode(&address).await;
+            }
+            self.records.schedule_replication().await;
+        }
+    }
+
+    async fn run_service(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let client_protocols_service = NameNodeService::new(
+            self.address.to_string(),
+            Arc::clone(&self.records),
+            Arc::clone(&self.groups),
+        );
         println!("Server listening on {}", self.address);
 
         Server::builder()
@@ -47,24 +169,152 @@ impl NameNodeServer {
 struct NameNodeService {
     address: String,
     records: Arc<NameNodeRecords>,
+    groups: Arc<GroupMapping>,
 }
 
 impl NameNodeService {
-    fn new(address: String, records: Arc<NameNodeRecords>) -> Self {
-        Self { address, records }
+    fn new(address: String, records: Arc<NameNodeRecords>, groups: Arc<GroupMapping>) -> Self {
+        Self {
+            address,
+            records,
+            groups,
+        }
+    }
+
+    /// Returns the user making a request, from the `user` in its metadata, in the groups the
+    /// namenode has for them
+    fn caller<T>(&self, request: &tonic::Request<T>) -> Option<Caller> {
+        let user = request
+            .metadata()
+            .get("user")
+            .and_then(|user| user.to_str().ok())
+            .filter(|user| !user.is_empty())?;
+        Some(self.groups.caller(user))
     }
 }
 
-impl From<Vec<String>> for NodeList {
-    fn from(val: Vec<String>) -> Self {
-        NodeList {
-            nodes: val.into_iter().collect(),
+impl From<FileBlock> for LocatedBlock {
+    fn from(val: FileBlock) -> Self {
+        LocatedBlock {
+            block_id: val.block_id,
+            generation_stamp: val.generation_stamp,
+            nodes: val.addrs,
+            offset: val.offset as i64,
+            length: val.length as i64,
+        }
+    }
+}
+
+impl From<LocatedBlock> for FileBlock {
+    fn from(val: LocatedBlock) -> Self {
+        FileBlock {
+            block_id: val.block_id,
+            generation_stamp: val.generation_stamp,
+            offset: val.offset as usize,
+            length: val.length as usize,
+            addrs: val.nodes,
+        }
+    }
+}
+
+impl From<INodeStatus> for FileStatus {
+    fn from(val: INodeStatus) -> Self {
+        FileStatus {
+            name: val.name,
+            is_directory: val.is_directory,
+            length: val.length as i64,
+            block_size: val.block_size as i64,
+            replication: val.replication as u32,
+            attributes: Some((&val.attributes).into()),
+        }
+    }
+}
+
+/// Status for a request that doesn't say who is making it
+fn missing_user() -> tonic::Status {
+    tonic::Status::unauthenticated("Request has no user")
+}
+
+/// Returns the name of the client making a request, from the `client` in its metadata
+fn client_name<T>(request: &tonic::Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get("client")
+        .and_then(|client| client.to_str().ok())
+        .filter(|client| !client.is_empty())
+        .map(|client| client.to_string())
+}
+
+/// Status for a write that doesn't say which client is making it, so can't be given a lease
+fn missing_client() -> tonic::Status {
+    tonic::Status::invalid_argument("Request has no client name")
+}
+
+/// Converts an error from the records into a status, keeping permission errors and missing or
+/// existing paths apart so clients can tell them from other failures
+fn error_status(context: &str, err: Box<dyn Error>) -> tonic::Status {
+    println!("{}", err);
+    if err.is::<PermissionDenied>() {
+        tonic::Status::permission_denied(err.to_string())
+    } else if err.is::<NotFound>() {
+        tonic::Status::not_found(err.to_string())
+    } else if err.is::<AlreadyExists>() {
+        tonic::Status::already_exists(err.to_string())
+    } else if err.is::<NoLiveDatanodes>() {
+        tonic::Status::unavailable(err.to_string())
+    } else if err.is::<EventsUnavailable>() {
+        tonic::Status::out_of_range(err.to_string())
+    } else {
+        tonic::Status::internal(format!("{}: {}", context, err))
+    }
+}
+
+/// Sends a watcher every event at or under `path`, first the ones it missed and then the rest as
+/// they happen, until the watcher goes away
+async fn send_events(
+    path: String,
+    subscription: Subscription,
+    sender: mpsc::Sender<Result<NamespaceEvent, tonic::Status>>,
+) {
+    let Subscription {
+        since_txid: mut last_txid,
+        history,
+        mut receiver,
+    } = subscription;
+    for event in history {
+        if sender.send(Ok(event)).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        let event = tokio::select! {
+            _ = sender.closed() => return,
+            event = receiver.recv() => event,
+        };
+        match event {
+            Ok(event) => {
+                last_txid = event.txid;
+                if events::matches(&event, &path) && sender.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                let status = tonic::Status::resource_exhausted(format!(
+                    "Watcher fell behind, resume from transaction {}",
+                    last_txid
+                ));
+                let _ = sender.send(Err(status)).await;
+                return;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
         }
     }
 }
 
 #[tonic::async_trait]
 impl ClientProtocols for NameNodeService {
+    type WatchEventsStream = ReceiverStream<Result<NamespaceEvent, tonic::Status>>;
+
     async fn get_system_status(
         &self,
         _request: tonic::Request<SystemInfoRequest>,
@@ -72,13 +322,22 @@ impl ClientProtocols for NameNodeService {
         let namenode_status = NodeStatus {
             node_address: self.address.clone(),
             is_online: true,
+            state: NodeState::Live.into(),
         };
         let nodes = self.records.get_datanode_statuses().await;
         let nodes_statuses = nodes
             .iter()
-            .map(|node| NodeStatus {
-                node_address: node.addr.clone(),
-                is_online: node.alive,
+            .map(|node| {
+                let state = match node.state {
+                    DataNodeState::Live => NodeState::Live,
+                    DataNodeState::Stale => NodeState::Stale,
+                    DataNodeState::Dead => NodeState::Dead,
+                };
+                NodeStatus {
+                    node_address: node.addr.clone(),
+                    is_online: node.state != DataNodeState::Dead,
+                    state: state.into(),
+                }
             })
             .collect();
 
@@ -96,6 +355,8 @@ impl ClientProtocols for NameNodeService {
         request: tonic::Request<FileRequest>,
     ) -> Result<tonic::Response<FileResponse>, tonic::Status> {
         println!("Received CreateFileRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let holder = client_name(&request).ok_or_else(missing_client)?;
         let create_request = request.into_inner();
         let FileInfo {
             file_path,
@@ -104,20 +365,26 @@ impl ClientProtocols for NameNodeService {
             .file_info
             .expect("File information not provided");
 
-        let addresses = match self.records.add_file(&file_path, file_size as usize).await {
-            Ok(addresses) => addresses,
-            Err(err) => {
-                println!("{}", err);
-                return Err(tonic::Status::internal(
-                    "Failed to add file (no datanodes running)",
-                ));
-            }
+        let layout = match self
+            .records
+            .add_file(&file_path, file_size as usize, &caller, &holder)
+            .await
+        {
+            Ok(layout) => layout,
+            Err(err) => return Err(error_status("Failed to add file", err)),
         };
 
-        println!("DataNode addresses: {:?}", addresses);
+        println!("Blocks: {:?}", layout.blocks);
 
         let response = FileResponse {
-            datanode_addrs: addresses.into_iter().map(|addr| addr.into()).collect(),
+            file_length: layout.length as i64,
+            block_size: layout.block_size as i64,
+            blocks: layout
+                .blocks
+                .into_iter()
+                .map(|block| block.into())
+                .collect(),
+            removed_blocks: vec![],
             response: Some(GenericReply {
                 is_success: true,
                 message: format!("Create request successfully processed for: {}", file_path),
@@ -132,6 +399,8 @@ impl ClientProtocols for NameNodeService {
         request: tonic::Request<FileRequest>,
     ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
         println!("Received UpdateFileRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let holder = client_name(&request).ok_or_else(missing_client)?;
         let update_request = request.into_inner();
 
         let FileInfo {
@@ -141,20 +410,27 @@ impl ClientProtocols for NameNodeService {
             .file_info
             .expect("File information not provided");
 
-        let addresses = match self
+        let (layout, removed_blocks) = match self
             .records
-            .update_file(&file_path, file_size as usize)
+            .update_file(&file_path, file_size as usize, &caller, &holder)
             .await
         {
-            Ok(addresses) => addresses,
-            Err(err) => {
-                println!("{}", err);
-                return Err(tonic::Status::internal("File does not exist"));
-            }
+            Ok(blocks) => blocks,
+            Err(err) => return Err(error_status("Failed to update file", err)),
         };
 
         let upd_response = FileResponse {
-            datanode_addrs: addresses.into_iter().map(|addr| addr.into()).collect(),
+            file_length: layout.length as i64,
+            block_size: layout.block_size as i64,
+            blocks: layout
+                .blocks
+                .into_iter()
+                .map(|block| block.into())
+                .collect(),
+            removed_blocks: removed_blocks
+                .into_iter()
+                .map(|block| block.into())
+                .collect(),
             response: Some(GenericReply {
                 is_success: true,
                 message: format!("Update request successfully processed for: {}", file_path),
@@ -163,11 +439,109 @@ impl ClientProtocols for NameNodeService {
         Ok(Response::new(upd_response))
     }
 
+    async fn append_file(
+        &self,
+        request: tonic::Request<FileRequest>,
+    ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
+        println!("Received AppendFileRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let holder = client_name(&request).ok_or_else(missing_client)?;
+        let FileInfo {
+            file_path,
+            file_size: _,
+        } = request
+            .into_inner()
+            .file_info
+            .expect("File information not provided");
+
+        let layout = match self.records.append_file(&file_path, &caller, &holder).await {
+            Ok(layout) => layout,
+            Err(err) => return Err(error_status("Failed to append to file", err)),
+        };
+
+        let response = FileResponse {
+            file_length: layout.length as i64,
+            block_size: layout.block_size as i64,
+            blocks: layout
+                .blocks
+                .into_iter()
+                .map(|block| block.into())
+                .collect(),
+            removed_blocks: vec![],
+            response: Some(GenericReply {
+                is_success: true,
+                message: format!("Append request successfully processed for: {}", file_path),
+            }),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn add_block(
+        &self,
+        request: tonic::Request<AddBlockRequest>,
+    ) -> std::result::Result<tonic::Response<LocatedBlock>, tonic::Status> {
+        println!("Received AddBlockRequest");
+        let holder = client_name(&request).ok_or_else(missing_client)?;
+        let AddBlockRequest { path, length } = request.into_inner();
+
+        let block = match self
+            .records
+            .allocate_block(&path, length as usize, &holder)
+            .await
+        {
+            Ok(block) => block,
+            Err(err) => return Err(error_status("Failed to add block", err)),
+        };
+        println!("Block: {:?}", block);
+
+        Ok(Response::new(block.into()))
+    }
+
+    async fn complete_file(
+        &self,
+        request: tonic::Request<CompleteFileRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        println!("Received CompleteFileRequest");
+        let holder = client_name(&request).ok_or_else(missing_client)?;
+        let CompleteFileRequest { path, blocks } = request.into_inner();
+
+        let blocks: Vec<FileBlock> = blocks.into_iter().map(|block| block.into()).collect();
+        if let Err(err) = self.records.complete_file(&path, &holder, &blocks).await {
+            println!("{}", err);
+            return Err(tonic::Status::failed_precondition(err.to_string()));
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: format!("Completed {}", path),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn renew_lease(
+        &self,
+        request: tonic::Request<RenewLeaseRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        let holder = client_name(&request).ok_or_else(missing_client)?;
+
+        let renewed = self.records.renew_lease(&holder).await;
+        let reply = GenericReply {
+            is_success: renewed,
+            message: match renewed {
+                true => format!("Renewed the lease of {}", holder),
+                false => format!("{} holds no leases", holder),
+            },
+        };
+
+        Ok(Response::new(reply))
+    }
+
     async fn delete_file(
         &self,
         request: tonic::Request<FileRequest>,
     ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
         println!("Received DeleteFileRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
         let delete_request = request.into_inner();
 
         let FileInfo {
@@ -177,18 +551,18 @@ impl ClientProtocols for NameNodeService {
             .file_info
             .expect("File information not provided");
 
-        let addresses = match self.records.remove_file(&file_path).await {
-            Ok(addresses) => addresses,
-            Err(err) => {
-                println!("{}", err);
-                return Err(tonic::Status::internal("File does not exist"));
-            }
+        let blocks = match self.records.remove_file(&file_path, &caller).await {
+            Ok(blocks) => blocks,
+            Err(err) => return Err(error_status("Failed to delete file", err)),
         };
 
-        println!("DataNode addresses: {:?}", addresses);
+        println!("Blocks: {:?}", blocks);
 
         let del_response = FileResponse {
-            datanode_addrs: addresses.into_iter().map(|addr| addr.into()).collect(),
+            file_length: 0,
+            block_size: 0,
+            blocks: vec![],
+            removed_blocks: blocks.into_iter().map(|block| block.into()).collect(),
             response: Some(GenericReply {
                 is_success: true,
                 message: format!("Delete request succesfully processed for: {}", file_path),
@@ -203,6 +577,7 @@ impl ClientProtocols for NameNodeService {
         request: tonic::Request<FileRequest>,
     ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
         println!("Received ReadFileRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
         let read_request = request.into_inner();
 
         let FileInfo {
@@ -212,12 +587,9 @@ impl ClientProtocols for NameNodeService {
             .file_info
             .expect("File information not provided");
 
-        let datanode_addr = match self.records.get_file_addresses(&file_path).await {
-            Ok(addresses) => addresses,
-            Err(err) => {
-                println!("{}", err);
-                return Err(tonic::Status::internal("File does not exist"));
-            }
+        let layout = match self.records.get_file_blocks(&file_path, &caller).await {
+            Ok(layout) => layout,
+            Err(err) => return Err(error_status("Failed to read file", err)),
         };
 
         let reply = GenericReply {
@@ -226,10 +598,255 @@ impl ClientProtocols for NameNodeService {
         };
         let read_resp = FileResponse {
             response: Some(reply), // why does this have to be an option?
-            datanode_addrs: datanode_addr.into_iter().map(|addr| addr.into()).collect(),
+            file_length: layout.length as i64,
+            block_size: layout.block_size as i64,
+            blocks: layout
+                .blocks
+                .into_iter()
+                .map(|block| block.into())
+                .collect(),
+            removed_blocks: vec![],
         };
         Ok(Response::new(read_resp))
     }
+
+    async fn report_failed_replicas(
+        &self,
+        request: tonic::Request<FailedReplicaReport>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        self.caller(&request).ok_or_else(missing_user)?;
+        let FailedReplicaReport { replicas } = request.into_inner();
+
+        println!("Client reported {} failed replicas", replicas.len());
+        for replica in replicas {
+            self.records
+                .report_failed_replica(&replica.address, replica.block_id, replica.corrupt)
+                .await;
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: "Failed replicas recorded successfully".to_string(),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn mkdir(
+        &self,
+        request: tonic::Request<MkdirRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        println!("Received MkdirRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let MkdirRequest { path, parents } = request.into_inner();
+
+        if let Err(err) = self.records.mkdir(&path, parents, &caller).await {
+            return Err(error_status("Failed to create directory", err));
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: format!("Mkdir request successfully processed for: {}", path),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn list_directory(
+        &self,
+        request: tonic::Request<ListDirectoryRequest>,
+    ) -> std::result::Result<tonic::Response<ListDirectoryResponse>, tonic::Status> {
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let ListDirectoryRequest { path } = request.into_inner();
+
+        let entries = match self.records.list_directory(&path, &caller).await {
+            Ok(entries) => entries,
+            Err(err) => return Err(error_status("Failed to list directory", err)),
+        };
+
+        Ok(Response::new(ListDirectoryResponse {
+            entries: entries.into_iter().map(|entry| entry.into()).collect(),
+        }))
+    }
+
+    async fn delete_directory(
+        &self,
+        request: tonic::Request<DeleteDirectoryRequest>,
+    ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
+        println!("Received DeleteDirectoryRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let DeleteDirectoryRequest { path, recursive } = request.into_inner();
+
+        let blocks = match self
+            .records
+            .remove_directory(&path, recursive, &caller)
+            .await
+        {
+            Ok(blocks) => blocks,
+            Err(err) => return Err(error_status("Failed to delete directory", err)),
+        };
+
+        let del_response = FileResponse {
+            file_length: 0,
+            block_size: 0,
+            blocks: vec![],
+            removed_blocks: blocks.into_iter().map(|block| block.into()).collect(),
+            response: Some(GenericReply {
+                is_success: true,
+                message: format!("Delete request succesfully processed for: {}", path),
+            }),
+        };
+        Ok(Response::new(del_response))
+    }
+
+    async fn rename(
+        &self,
+        request: tonic::Request<RenameRequest>,
+    ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
+        println!("Received RenameRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let RenameRequest {
+            source,
+            destination,
+            overwrite,
+        } = request.into_inner();
+
+        let blocks = match self
+            .records
+            .rename(&source, &destination, overwrite, &caller)
+            .await
+        {
+            Ok(blocks) => blocks,
+            Err(err) => return Err(error_status("Failed to rename", err)),
+        };
+
+        let response = FileResponse {
+            file_length: 0,
+            block_size: 0,
+            blocks: vec![],
+            removed_blocks: blocks.into_iter().map(|block| block.into()).collect(),
+            response: Some(GenericReply {
+                is_success: true,
+                message: format!("Moved {} to {}", source, destination),
+            }),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_file_info(
+        &self,
+        request: tonic::Request<GetFileInfoRequest>,
+    ) -> std::result::Result<tonic::Response<FileStatus>, tonic::Status> {
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let GetFileInfoRequest { path } = request.into_inner();
+
+        match self.records.stat(&path, &caller).await {
+            Ok(status) => Ok(Response::new(status.into())),
+            Err(err) if err.is::<PermissionDenied>() => Err(error_status("Failed to stat", err)),
+            Err(err) => {
+                println!("{}", err);
+                Err(tonic::Status::not_found(err.to_string()))
+            }
+        }
+    }
+
+    async fn set_permission(
+        &self,
+        request: tonic::Request<SetPermissionRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        println!("Received SetPermissionRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let SetPermissionRequest { path, mode } = request.into_inner();
+
+        if let Err(err) = self.records.set_permission(&path, mode, &caller).await {
+            return Err(error_status("Failed to change permissions", err));
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: format!("Changed permissions of {} to {:o}", path, mode),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_owner(
+        &self,
+        request: tonic::Request<SetOwnerRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        println!("Received SetOwnerRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let SetOwnerRequest { path, owner, group } = request.into_inner();
+
+        if let Err(err) = self.records.set_owner(&path, &owner, &group, &caller).await {
+            return Err(error_status("Failed to change owner", err));
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: format!("Changed owner of {}", path),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_acl(
+        &self,
+        request: tonic::Request<SetAclRequest>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        println!("Received SetAclRequest");
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let request = request.into_inner();
+
+        let entries = request.entries.iter().map(AclEntry::from).collect();
+        let change = match request.action() {
+            set_acl_request::Action::Modify => AclChange::Modify(entries),
+            set_acl_request::Action::Remove => AclChange::Remove(entries),
+            set_acl_request::Action::RemoveAll => AclChange::RemoveAll,
+            set_acl_request::Action::RemoveDefault => AclChange::RemoveDefault,
+            set_acl_request::Action::Replace => AclChange::Replace(entries),
+        };
+        if let Err(err) = self.records.set_acl(&request.path, &change, &caller).await {
+            return Err(error_status("Failed to change ACL", err));
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: format!("Changed ACL of {}", request.path),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_acl(
+        &self,
+        request: tonic::Request<GetAclRequest>,
+    ) -> std::result::Result<tonic::Response<GetAclResponse>, tonic::Status> {
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let GetAclRequest { path } = request.into_inner();
+
+        let (owner, group, entries) = match self.records.get_acl(&path, &caller).await {
+            Ok(acl) => acl,
+            Err(err) => return Err(error_status("Failed to get ACL", err)),
+        };
+        Ok(Response::new(GetAclResponse {
+            owner,
+            group,
+            entries: entries.iter().map(|entry| entry.into()).collect(),
+        }))
+    }
+
+    async fn watch_events(
+        &self,
+        request: tonic::Request<WatchEventsRequest>,
+    ) -> std::result::Result<tonic::Response<Self::WatchEventsStream>, tonic::Status> {
+        let caller = self.caller(&request).ok_or_else(missing_user)?;
+        let WatchEventsRequest { path, since_txid } = request.into_inner();
+
+        let (path, subscription) = match self.records.watch(&path, since_txid, &caller).await {
+            Ok(watch) => watch,
+            Err(err) => return Err(error_status("Failed to watch events", err)),
+        };
+        println!("Watching events under {} after {}", path, since_txid);
+        let (sender, stream) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(send_events(path, subscription, sender));
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
 }
 
 struct HeartbeatRecordService {
@@ -247,17 +864,100 @@ impl HearbeatProtocol for HeartbeatRecordService {
     async fn send_heartbeat(
         &self,
         request: tonic::Request<Heartbeat>,
-    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+    ) -> std::result::Result<tonic::Response<HeartbeatResponse>, tonic::Status> {
         let incoming_heartbeat = request.into_inner();
 
         let Heartbeat { address } = incoming_heartbeat;
 
-        self.records.record_heartbeat(&address).await;
+        let is_new = self.records.record_heartbeat(&address).await;
+        let replication_commands = self
+            .records
+            .take_replication_tasks(&address)
+            .await
+            .into_iter()
+            .map(|task| ReplicationCommand {
+                block_id: task.block_id,
+                targets: task.targets,
+            })
+            .collect();
+        let invalidated_blocks = self.records.take_invalidated_blocks(&address).await;
+        let suspect_blocks = self.records.take_suspect_blocks(&address).await;
         let reply = GenericReply {
             is_success: true,
             message: "Heartbeat recorded successfully".to_string(),
         };
 
+        Ok(Response::new(HeartbeatResponse {
+            reply: Some(reply),
+            replication_commands,
+            send_block_report: is_new,
+            invalidated_blocks,
+            suspect_blocks,
+        }))
+    }
+
+    async fn report_bad_blocks(
+        &self,
+        request: tonic::Request<BadBlockReport>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        let BadBlockReport { address, block_ids } = request.into_inner();
+
+        println!(
+            "Datanode {} reported {} corrupt blocks",
+            address,
+            block_ids.len()
+        );
+        self.records.report_bad_blocks(&address, &block_ids).await;
+        let reply = GenericReply {
+            is_success: true,
+            message: "Bad blocks recorded successfully".to_string(),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn block_report(
+        &self,
+        request: tonic::Request<BlockReport>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        let BlockReport { address, replicas } = request.into_inner();
+
+        let replicas: Vec<(u64, u64)> = replicas
+            .into_iter()
+            .map(|replica| (replica.block_id, replica.generation_stamp))
+            .collect();
+        self.records.process_block_report(&address, &replicas).await;
+        let reply = GenericReply {
+            is_success: true,
+            message: "Block report processed successfully".to_string(),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn incremental_block_report(
+        &self,
+        request: tonic::Request<IncrementalBlockReport>,
+    ) -> std::result::Result<tonic::Response<GenericReply>, tonic::Status> {
+        let IncrementalBlockReport { address, changes } = request.into_inner();
+
+        for change in changes {
+            let replica = change.replica.unwrap_or_default();
+            if change.deleted {
+                self.records
+                    .record_block_deleted(&address, replica.block_id)
+                    .await;
+            } else {
+                self.records
+                    .record_block_received(&address, replica.block_id, replica.generation_stamp)
+                    .await;
+            }
+        }
+        let reply = GenericReply {
+            is_success: true,
+            message: "Incremental block report processed successfully".to_string(),
+        };
+
         Ok(Response::new(reply))
     }
 }
@@ -0,0 +1,236 @@
+use crate::checksum;
+use crate::proto::{BlockInfo, BlockPacket};
+use std::error::Error;
+
+/// Most bytes of block data sent in each packet, rounded down to whole checksum chunks
+pub const PACKET_SIZE: usize = 64 * 1024;
+
+/// Returns the most bytes of block data sent in each packet, which is `PACKET_SIZE` rounded down
+/// to whole `bytes_per_checksum` sized chunks, but at least one chunk
+pub fn packet_size(bytes_per_checksum: usize) -> usize {
+    let bytes_per_checksum = bytes_per_checksum.max(1);
+    (PACKET_SIZE / bytes_per_checksum).max(1) * bytes_per_checksum
+}
+
+/// Splits a block into packets, each carrying the checksums of its own data, where `checksums`
+/// covers every `bytes_per_checksum` sized chunk of `data`
+/// An empty block is still sent as one packet, which carries the header
+pub fn split(
+    block_id: u64,
+    generation_stamp: u64,
+    data: Vec<u8>,
+    checksums: Vec<u32>,
+    bytes_per_checksum: usize,
+) -> impl Iterator<Item = BlockPacket> + Send {
+    let bytes_per_checksum = bytes_per_checksum.max(1);
+    let packet_size = packet_size(bytes_per_checksum);
+    let chunks_per_packet = packet_size / bytes_per_checksum;
+    let num_packets = data.len().div_ceil(packet_size).max(1);
+
+    (0..num_packets).map(move |i| {
+        let start = i * packet_size;
+        let end = std::cmp::min(start + packet_size, data.len());
+        let first_chunk = i * chunks_per_packet;
+        let last_chunk = std::cmp::min(first_chunk + chunks_per_packet, checksums.len());
+        BlockPacket {
+            block_id,
+            block_size: data.len() as i64,
+            checksums: checksums
+                .get(first_chunk..last_chunk)
+                .unwrap_or_default()
+                .to_vec(),
+            bytes_per_checksum: bytes_per_checksum as i64,
+            offset: start as i64,
+            data: data[start..end].to_vec(),
+            generation_stamp,
+            seqno: i as u64,
+        }
+    })
+}
+
+/// Header of a block being streamed, taken from its first packet
+#[derive(Clone, Debug)]
+pub struct BlockHeader {
+    pub block_id: u64,
+    pub generation_stamp: u64,
+    pub block_size: usize,
+    pub bytes_per_checksum: usize,
+}
+
+/// Checks the packets of a block as they arrive, without keeping their data: that they come in
+/// order, match their checksums and add up to the size the first packet advertises
+#[derive(Default)]
+pub struct PacketChecker {
+    header: Option<BlockHeader>,
+    /// Bytes of the block received so far
+    received: usize,
+    next_seqno: u64,
+}
+
+impl PacketChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the header of the block once its first packet has been checked
+    pub fn header(&self) -> Option<&BlockHeader> {
+        self.header.as_ref()
+    }
+
+    /// Checks that a packet is the next one of the block and matches its checksums
+    pub fn check(&mut self, packet: &BlockPacket) -> Result<(), Box<dyn Error>> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                if packet.bytes_per_checksum <= 0 {
+                    return Err("Bytes per checksum must be positive".into());
+                }
+                if packet.block_size < 0 {
+                    return Err("Block size must not be negative".into());
+                }
+                self.header.insert(BlockHeader {
+                    block_id: packet.block_id,
+                    generation_stamp: packet.generation_stamp,
+                    block_size: packet.block_size as usize,
+                    bytes_per_checksum: packet.bytes_per_checksum as usize,
+                })
+            }
+        };
+        let received = self.received;
+
+        if packet.seqno != self.next_seqno {
+            return Err(format!(
+                "Expected packet {} but received packet {}",
+                self.next_seqno, packet.seqno
+            )
+            .into());
+        }
+        if packet.offset as usize != received {
+            return Err(format!(
+                "Expected packet at offset {} but received offset {}",
+                received, packet.offset
+            )
+            .into());
+        }
+        // the checksums of the packets only line up if every packet but the last ends on a chunk
+        if !received.is_multiple_of(header.bytes_per_checksum) {
+            return Err(format!("Packet {} follows a partial chunk", packet.seqno).into());
+        }
+        if received + packet.data.len() > header.block_size {
+            return Err(format!("Received more than {} bytes", header.block_size).into());
+        }
+        checksum::verify(&packet.data, header.bytes_per_checksum, &packet.checksums)
+            .map_err(|e| format!("Packet {}: {}", packet.seqno, e))?;
+
+        self.received += packet.data.len();
+        self.next_seqno += 1;
+        Ok(())
+    }
+
+    /// Returns the header of the block once every byte of it has arrived
+    pub fn finish(self) -> Result<BlockHeader, Box<dyn Error>> {
+        let header = self.header.ok_or("No packets received")?;
+        if self.received != header.block_size {
+            return Err(format!(
+                "Expected {} bytes but received {}",
+                header.block_size, self.received
+            )
+            .into());
+        }
+        Ok(header)
+    }
+}
+
+/// Reassembles a block from its packets in memory, checking each packet as it arrives
+#[derive(Default)]
+pub struct Assembler {
+    checker: PacketChecker,
+    data: Vec<u8>,
+    checksums: Vec<u32>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks that a packet is the next one of the block and matches its checksums, and adds it
+    pub fn add(&mut self, packet: BlockPacket) -> Result<(), Box<dyn Error>> {
+        self.checker.check(&packet)?;
+        // the size comes from the sender, so the data only grows as it arrives
+        self.data.extend_from_slice(&packet.data);
+        self.checksums.extend_from_slice(&packet.checksums);
+        Ok(())
+    }
+
+    /// Returns the block once every byte of it has arrived
+    pub fn finish(self) -> Result<BlockInfo, Box<dyn Error>> {
+        let header = self.checker.finish()?;
+        Ok(BlockInfo {
+            block_size: header.block_size as i64,
+            block_id: header.block_id,
+            block_data: self.data,
+            checksums: self.checksums,
+            bytes_per_checksum: header.bytes_per_checksum as i64,
+            generation_stamp: header.generation_stamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_all(data: &[u8], bytes_per_checksum: usize) -> Vec<BlockPacket> {
+        let checksums = checksum::compute(data, bytes_per_checksum);
+        split(1, 2, data.to_vec(), checksums, bytes_per_checksum).collect()
+    }
+
+    fn assemble(packets: Vec<BlockPacket>) -> Result<BlockInfo, Box<dyn Error>> {
+        let mut assembler = Assembler::new();
+        for packet in packets {
+            assembler.add(packet)?;
+        }
+        assembler.finish()
+    }
+
+    #[test]
+    fn test_split_and_assemble() {
+        // packets are cut on chunk boundaries even when the chunk size doesn't divide PACKET_SIZE
+        let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let packets = split_all(&data, 500);
+        assert_eq!(packets.len(), 4);
+        assert_eq!(packets[1].offset, 65500);
+        let block = assemble(packets).unwrap();
+        assert_eq!(block.block_data, data);
+        assert_eq!(block.checksums, checksum::compute(&data, 500));
+        assert_eq!(block.generation_stamp, 2);
+
+        let empty = split_all(&[], 512);
+        assert_eq!(empty.len(), 1);
+        assert!(assemble(empty).unwrap().block_data.is_empty());
+        assert!(assemble(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_assemble_rejects_bad_packets() {
+        let data = vec![7; 3 * PACKET_SIZE];
+
+        let mut corrupt = split_all(&data, 512);
+        corrupt[1].data[0] = 0;
+        assert!(assemble(corrupt).is_err());
+
+        let mut reordered = split_all(&data, 512);
+        reordered.swap(1, 2);
+        assert!(assemble(reordered).is_err());
+
+        let mut truncated = split_all(&data, 512);
+        truncated.pop();
+        assert!(assemble(truncated).is_err());
+
+        // a huge claimed size isn't allocated up front, and fails once the data runs out
+        let mut oversized = split_all(&data[..512], 512);
+        oversized[0].block_size = i64::MAX;
+        assert!(assemble(oversized).is_err());
+    }
+}